@@ -0,0 +1,187 @@
+use crate::args_decoders::{decode_borsh_fields, ArgsDecoder, BorshField, BorshType};
+use crate::click::*;
+use std::collections::HashMap;
+use std::env;
+use std::sync::{OnceLock, RwLock};
+
+use clickhouse::Row;
+use serde::Deserialize;
+use serde_json::Value;
+
+pub const ABI_TARGET: &str = "abi";
+
+/// The parts of a NEAR ABI file (`near-abi` schema) that are needed to decode the args.
+#[derive(Deserialize)]
+struct Abi {
+    body: AbiBody,
+}
+
+#[derive(Deserialize)]
+struct AbiBody {
+    functions: Vec<AbiFunction>,
+}
+
+#[derive(Deserialize)]
+struct AbiFunction {
+    name: String,
+    #[serde(default)]
+    params: Option<AbiParams>,
+}
+
+#[derive(Deserialize)]
+struct AbiParams {
+    serialization_type: String,
+    args: Vec<AbiParameter>,
+}
+
+#[derive(Deserialize)]
+struct AbiParameter {
+    name: String,
+    type_schema: Value,
+}
+
+#[derive(Row, Deserialize)]
+struct CodeHashRow {
+    account_id: String,
+    contract_hash: String,
+}
+
+/// The NEAR ABIs by the contract code hash. The JSON args are stored as is, so only the methods
+/// with the borsh args are used, to decode them for every contract that runs the code.
+pub struct AbiRegistry {
+    /// The fields of the borsh args by the code hash and the method name.
+    methods: HashMap<String, HashMap<String, Vec<BorshField>>>,
+    /// The latest code hash of the accounts that run a code with an ABI.
+    code_hashes: RwLock<HashMap<String, String>>,
+}
+
+impl AbiRegistry {
+    pub fn get() -> Option<&'static Self> {
+        static ABI_REGISTRY: OnceLock<Option<AbiRegistry>> = OnceLock::new();
+        ABI_REGISTRY.get_or_init(Self::from_env).as_ref()
+    }
+
+    /// Loads the `<code_hash>.json` ABI files from `CONTRACT_ABI_DIR`, if set.
+    fn from_env() -> Option<Self> {
+        let dir = env::var("CONTRACT_ABI_DIR").ok()?;
+        let entries =
+            std::fs::read_dir(&dir).unwrap_or_else(|err| panic!("Failed to read {}: {}", dir, err));
+        let mut methods = HashMap::new();
+        for entry in entries {
+            let path = entry.expect("Failed to read CONTRACT_ABI_DIR").path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            let code_hash = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .expect("Invalid ABI file name")
+                .to_string();
+            let data = std::fs::read(&path)
+                .unwrap_or_else(|err| panic!("Failed to read {}: {}", path.display(), err));
+            let abi: Abi = serde_json::from_slice(&data)
+                .unwrap_or_else(|err| panic!("Invalid ABI {}: {}", path.display(), err));
+            let borsh_methods = borsh_methods(&code_hash, abi);
+            tracing::log::info!(target: ABI_TARGET, "Loaded ABI for code {} with {} borsh methods", code_hash, borsh_methods.len());
+            methods.insert(code_hash, borsh_methods);
+        }
+        Some(Self {
+            methods,
+            code_hashes: RwLock::new(HashMap::new()),
+        })
+    }
+
+    /// Loads the latest deployed code of the accounts from the `actions` table. Only the accounts
+    /// that run a code with an ABI are kept.
+    pub async fn load_code_hashes(&self, db: &ClickDB) -> clickhouse::error::Result<()> {
+        let known_code_hashes: Vec<&String> = self.methods.keys().collect();
+        let rows = db
+            .client
+            .query(&format!(
+                "SELECT
+                    account_id,
+                    assumeNotNull(argMax(contract_hash, block_height)) AS contract_hash
+                FROM actions
+                WHERE action = 'DEPLOY_CONTRACT' AND status = 'SUCCESS' AND {}
+                GROUP BY account_id
+                HAVING contract_hash IN ?",
+                chain_filter()
+            ))
+            .bind(known_code_hashes)
+            .fetch_all::<CodeHashRow>()
+            .await?;
+        tracing::log::info!(target: ABI_TARGET, "Loaded code hashes of {} accounts", rows.len());
+        let mut code_hashes = self.code_hashes.write().unwrap();
+        for row in rows {
+            code_hashes.insert(row.account_id, row.contract_hash);
+        }
+        Ok(())
+    }
+
+    /// Tracks a successful deployment, so the following calls are decoded with the new code.
+    pub fn set_code_hash(&self, account_id: &str, code_hash: String) {
+        let mut code_hashes = self.code_hashes.write().unwrap();
+        if self.methods.contains_key(&code_hash) {
+            code_hashes.insert(account_id.to_string(), code_hash);
+        } else {
+            code_hashes.remove(account_id);
+        }
+    }
+}
+
+impl ArgsDecoder for &'static AbiRegistry {
+    fn decode(&self, contract_id: &str, method_name: &str, args: &[u8]) -> Option<Value> {
+        let code_hashes = self.code_hashes.read().unwrap();
+        let fields = self
+            .methods
+            .get(code_hashes.get(contract_id)?)?
+            .get(method_name)?;
+        decode_borsh_fields(fields, args)
+    }
+}
+
+/// The methods with the borsh args. The methods with a field of an unsupported type are skipped.
+fn borsh_methods(code_hash: &str, abi: Abi) -> HashMap<String, Vec<BorshField>> {
+    let mut methods = HashMap::new();
+    for function in abi.body.functions {
+        let Some(params) = function.params else {
+            continue;
+        };
+        if params.serialization_type != "borsh" {
+            continue;
+        }
+        let fields = params
+            .args
+            .into_iter()
+            .map(|arg| {
+                Some(BorshField {
+                    kind: borsh_type(&arg.type_schema)?,
+                    name: arg.name,
+                })
+            })
+            .collect::<Option<Vec<_>>>();
+        match fields {
+            Some(fields) => {
+                methods.insert(function.name, fields);
+            }
+            None => {
+                tracing::log::warn!(target: ABI_TARGET, "Skipping method {} of code {} with unsupported borsh args", function.name, code_hash);
+            }
+        }
+    }
+    methods
+}
+
+/// Maps the declaration of the borsh schema of a parameter to a primitive type.
+fn borsh_type(type_schema: &Value) -> Option<BorshType> {
+    match type_schema.get("declaration")?.as_str()? {
+        "String" | "string" | "AccountId" => Some(BorshType::String),
+        "bool" => Some(BorshType::Bool),
+        "u8" => Some(BorshType::U8),
+        "u32" => Some(BorshType::U32),
+        "u64" => Some(BorshType::U64),
+        "u128" => Some(BorshType::U128),
+        "Vec<u8>" => Some(BorshType::Bytes),
+        _ => None,
+    }
+}
@@ -0,0 +1,187 @@
+use crate::click::positive_env_var;
+use crate::implicit::parse_account_id;
+use std::collections::HashSet;
+use std::env;
+use std::sync::OnceLock;
+
+use fastnear_primitives::near_indexer_primitives::types::AccountId;
+use serde_json::Value;
+
+const DEFAULT_ACCOUNT_ARGS: [&str; 19] = [
+    "receiver_id",
+    "account_id",
+    "sender_id",
+    "new_account_id",
+    "predecessor_account_id",
+    "contract_id",
+    "owner_id",
+    "token_owner_id",
+    "nft_contract_id",
+    "token_account_id",
+    "creator_id",
+    "referral_id",
+    "previous_owner_id",
+    "seller_id",
+    "buyer_id",
+    "user_id",
+    "beneficiary_id",
+    "staking_pool_account_id",
+    "owner_account_id",
+];
+
+const DEFAULT_EVENTS_ARGS: [&str; 10] = [
+    "account_id",
+    "owner_id",
+    "old_owner_id",
+    "new_owner_id",
+    "payer_id",
+    "farmer_id",
+    "validator_id",
+    "liquidation_account_id",
+    "contract_id",
+    "nft_contract_id",
+];
+
+const DEFAULT_SCAN_TOP_LEVEL_ACCOUNTS: [&str; 3] = ["near", "tg", "testnet"];
+
+/// The keys of the function call args and the event data that may hold account IDs of the
+/// accounts involved in a transaction.
+pub struct AccountArgs {
+    pub args_keys: Vec<String>,
+    pub events_keys: Vec<String>,
+    /// How deep to look into the nested objects. Arrays don't count as a level, so the accounts
+    /// of `{"actions": [{"receiver_id": ...}]}` are found with the depth of 1. Only the top-level
+    /// keys are checked by default.
+    pub max_depth: u64,
+    /// The top-level accounts of the named accounts that are picked from any string of the args,
+    /// the event data and the plain text logs, if the scan is enabled.
+    pub scan_top_level_accounts: Option<Vec<String>>,
+}
+
+impl AccountArgs {
+    pub fn get() -> &'static Self {
+        static ACCOUNT_ARGS: OnceLock<AccountArgs> = OnceLock::new();
+        ACCOUNT_ARGS.get_or_init(Self::from_env)
+    }
+
+    /// The comma-separated `POTENTIAL_ACCOUNT_ARGS` and `POTENTIAL_EVENTS_ARGS` replace the
+    /// default keys. `ACCOUNT_ARGS_SCAN=true` enables the scan for the top-level accounts of
+    /// `ACCOUNT_ARGS_SCAN_TOP_LEVEL_ACCOUNTS`.
+    fn from_env() -> Self {
+        let keys = |name: &str, default: &[&str]| match env::var(name) {
+            Ok(value) => value
+                .split(',')
+                .map(|key| key.trim().to_string())
+                .filter(|key| !key.is_empty())
+                .collect(),
+            Err(_) => default.iter().map(|key| key.to_string()).collect(),
+        };
+        let scan = env::var("ACCOUNT_ARGS_SCAN").map(|v| v == "true").unwrap_or(false);
+        Self {
+            args_keys: keys("POTENTIAL_ACCOUNT_ARGS", &DEFAULT_ACCOUNT_ARGS),
+            events_keys: keys("POTENTIAL_EVENTS_ARGS", &DEFAULT_EVENTS_ARGS),
+            max_depth: positive_env_var("ACCOUNT_ARGS_MAX_DEPTH").unwrap_or(0),
+            scan_top_level_accounts: scan.then(|| {
+                keys(
+                    "ACCOUNT_ARGS_SCAN_TOP_LEVEL_ACCOUNTS",
+                    &DEFAULT_SCAN_TOP_LEVEL_ACCOUNTS,
+                )
+            }),
+        }
+    }
+
+    pub fn extract_from_args(&self, accounts: &mut HashSet<AccountId>, args: &Value) {
+        self.extract(accounts, args, &self.args_keys, 0);
+        self.scan_value(accounts, args);
+    }
+
+    pub fn extract_from_event(&self, accounts: &mut HashSet<AccountId>, data: &Value) {
+        self.extract(accounts, data, &self.events_keys, 0);
+        self.scan_value(accounts, data);
+    }
+
+    /// Scans a log that is not an event.
+    pub fn extract_from_log(&self, accounts: &mut HashSet<AccountId>, log: &str) {
+        self.scan_text(accounts, log);
+    }
+
+    fn extract(
+        &self,
+        accounts: &mut HashSet<AccountId>,
+        value: &Value,
+        keys: &[String],
+        depth: u64,
+    ) {
+        match value {
+            Value::Object(object) => {
+                for key in keys {
+                    if let Some(account_id) = object.get(key).and_then(|v| v.as_str()) {
+                        if let Ok(account_id) = parse_account_id(account_id) {
+                            accounts.insert(account_id);
+                        }
+                    }
+                }
+                if depth < self.max_depth {
+                    for value in object.values() {
+                        self.extract(accounts, value, keys, depth + 1);
+                    }
+                }
+            }
+            Value::Array(values) => {
+                for value in values {
+                    self.extract(accounts, value, keys, depth);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn scan_value(&self, accounts: &mut HashSet<AccountId>, value: &Value) {
+        if self.scan_top_level_accounts.is_none() {
+            return;
+        }
+        match value {
+            Value::String(s) => self.scan_text(accounts, s),
+            Value::Object(object) => {
+                for value in object.values() {
+                    self.scan_value(accounts, value);
+                }
+            }
+            Value::Array(values) => {
+                for value in values {
+                    self.scan_value(accounts, value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Picks the account IDs between the spaces and punctuation, e.g. in memos or plain logs.
+    fn scan_text(&self, accounts: &mut HashSet<AccountId>, text: &str) {
+        let Some(top_level_accounts) = &self.scan_top_level_accounts else {
+            return;
+        };
+        let words =
+            text.split(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_')));
+        for word in words {
+            // A dot may end the sentence.
+            scan_word(accounts, top_level_accounts, word.trim_end_matches('.'));
+        }
+    }
+}
+
+/// Only the named accounts under the known top-level accounts are picked, which stands in for the
+/// existence check, since the other dotted words are mostly file names, versions or domains.
+/// Implicit accounts look the same as hex hashes and addresses of other chains, so they are
+/// skipped.
+fn scan_word(accounts: &mut HashSet<AccountId>, top_level_accounts: &[String], s: &str) {
+    let Some((_, top_level_account)) = s.rsplit_once('.') else {
+        return;
+    };
+    if !top_level_accounts.iter().any(|account| account == top_level_account) {
+        return;
+    }
+    if let Ok(account_id) = s.parse::<AccountId>() {
+        accounts.insert(account_id);
+    }
+}
@@ -1,5 +1,14 @@
+use crate::abi::AbiRegistry;
+use crate::args_decoders::ArgsDecoders;
+use crate::pool_metadata::{PoolMetadataRow, PoolMetadataSync, POOL_METADATA_TARGET};
+use crate::pool_migrations::{PoolMigrationRow, PoolMigrations};
+use crate::pool_stake_history::{PoolStakeHistory, PoolStakeRow};
+use crate::prices::{yocto_near_to_usd, NearPriceRow, PriceOracle};
+use crate::staking_pool_logs::StakingPoolLog;
+use crate::verify::Verifier;
 use crate::*;
 use base64::Engine;
+use std::collections::HashMap;
 use std::env;
 
 use base64::prelude::BASE64_STANDARD;
@@ -14,10 +23,16 @@ use fastnear_primitives::near_primitives::views::{
 };
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
+use tracing::Instrument;
 
 const MAX_TOKEN_LENGTH: usize = 64;
+/// Larger function call args are not stored in the `args_json` column.
+const MAX_ARGS_JSON_LENGTH: usize = 16 * 1024;
 const MAX_TOKEN_IDS_LENGTH: usize = 4;
 const EVENT_LOG_PREFIX: &str = "EVENT_JSON:";
+const DEFAULT_WHITELIST_CONTRACT_ID: &str = "lockup-whitelist.near";
+/// Staking pool methods with the `amount` argument in yoctoNEAR.
+pub const STAKING_POOL_AMOUNT_METHODS: [&str; 3] = ["stake", "unstake", "withdraw"];
 
 #[derive(Copy, Clone, Debug, Serialize_repr, Deserialize_repr, PartialEq)]
 #[repr(u8)]
@@ -41,7 +56,26 @@ pub enum ActionKind {
     NonrefundableStorageTransfer = 10,
 }
 
-#[derive(Row, Serialize)]
+impl From<&ActionView> for ActionKind {
+    fn from(action: &ActionView) -> Self {
+        match action {
+            ActionView::CreateAccount => ActionKind::CreateAccount,
+            ActionView::DeployContract { .. } => ActionKind::DeployContract,
+            ActionView::FunctionCall { .. } => ActionKind::FunctionCall,
+            ActionView::Transfer { .. } => ActionKind::Transfer,
+            ActionView::Stake { .. } => ActionKind::Stake,
+            ActionView::AddKey { .. } => ActionKind::AddKey,
+            ActionView::DeleteKey { .. } => ActionKind::DeleteKey,
+            ActionView::DeleteAccount { .. } => ActionKind::DeleteAccount,
+            ActionView::Delegate { .. } => ActionKind::Delegate,
+            // ActionView::NonrefundableStorageTransfer { .. } => {
+            //     ActionKind::NonrefundableStorageTransfer
+            // }
+        }
+    }
+}
+
+#[derive(Row, Serialize, Deserialize)]
 pub struct FullActionRow {
     pub block_height: u64,
     pub block_hash: String,
@@ -66,13 +100,17 @@ pub struct FullActionRow {
     pub contract_hash: Option<String>,
     pub public_key: Option<String>,
     pub access_key_contract_id: Option<String>,
+    pub access_key_allowance: Option<u128>,
+    pub access_key_method_names: Vec<String>,
     pub deposit: Option<u128>,
+    pub deposit_usd: Option<f64>,
     pub gas_price: u128,
     pub attached_gas: Option<u64>,
     pub gas_burnt: u64,
     pub tokens_burnt: u128,
     pub method_name: Option<String>,
     pub args: Option<String>,
+    pub args_json: Option<String>,
 
     pub args_account_id: Option<String>,
     pub args_new_account_id: Option<String>,
@@ -81,13 +119,14 @@ pub struct FullActionRow {
     pub args_sender_id: Option<String>,
     pub args_token_id: Option<String>,
     pub args_amount: Option<u128>,
+    pub args_amount_usd: Option<f64>,
     pub args_balance: Option<u128>,
     pub args_nft_contract_id: Option<String>,
     pub args_nft_token_id: Option<String>,
     pub return_value_int: Option<u128>,
 }
 
-#[derive(Row, Serialize)]
+#[derive(Row, Serialize, Deserialize)]
 pub struct FullEventRow {
     pub block_height: u64,
     pub block_hash: String,
@@ -119,7 +158,7 @@ pub struct FullEventRow {
     pub data_amount: Option<u128>,
 }
 
-#[derive(Row, Serialize)]
+#[derive(Row, Serialize, Deserialize)]
 pub struct FullDataRow {
     pub block_height: u64,
     pub block_hash: String,
@@ -132,15 +171,102 @@ pub struct FullDataRow {
     pub data: Option<String>,
 }
 
+/// A validator stake proposal from a `Stake` action. The proposal is only accepted or rejected at
+/// the epoch boundary.
+#[derive(Row, Serialize, Deserialize)]
+pub struct StakeProposalRow {
+    pub block_height: u64,
+    pub block_timestamp: u64,
+    pub transaction_hash: String,
+    pub receipt_id: String,
+    pub action_index: u16,
+    pub account_id: String,
+    pub public_key: String,
+    pub stake: u128,
+    pub stake_usd: Option<f64>,
+    pub status: ReceiptStatus,
+}
+
+/// An addition or a removal of a staking pool on the lockup whitelist contract, effective from
+/// the block height.
+#[derive(Row, Serialize, Deserialize)]
+pub struct WhitelistedPoolRow {
+    pub pool_id: String,
+    pub block_height: u64,
+    pub block_timestamp: u64,
+    pub transaction_hash: String,
+    pub is_whitelisted: bool,
+}
+
+/// A delegator that staked or unstaked with the staking pool within the epoch, from the staking
+/// pool logs, so only the contracts that emit them count as pools.
+#[derive(Row, Serialize, Deserialize)]
+pub struct PoolEpochDelegatorRow {
+    pub pool_id: String,
+    pub epoch_id: String,
+    pub delegator_id: String,
+    pub block_height: u64,
+    /// The staking shares of the delegator after the last log within the epoch.
+    pub staking_shares: u128,
+}
+
+/// A staking or unstaking log of a delegator of the staking pool. A new delegator stakes with no
+/// prior shares, and an exited delegator unstakes all shares. The churn of the pool within the
+/// epoch is aggregated at read time, so a reindexed range replaces the rows.
+#[derive(Row, Serialize, Deserialize)]
+pub struct PoolChurnRow {
+    pub pool_id: String,
+    pub epoch_id: String,
+    pub account_id: String,
+    pub block_height: u64,
+    pub receipt_id: String,
+    pub log_index: u16,
+    pub is_new_delegator: bool,
+    pub is_exited_delegator: bool,
+    pub staked: u128,
+    pub unstaked: u128,
+}
+
+/// Epoch rewards distributed by the staking pool. The core staking pool contract adds the rewards
+/// to the total staked balance of the pool.
+#[derive(Row, Serialize, Deserialize)]
+pub struct PoolRewardRow {
+    pub pool_id: String,
+    pub epoch_id: String,
+    pub epoch_height: u64,
+    pub block_height: u64,
+    pub block_timestamp: u64,
+    pub receipt_id: String,
+    pub rewards: u128,
+    pub total_staked_balance: u128,
+    pub total_shares: u128,
+}
+
 #[derive(Default)]
 pub struct Rows {
     pub actions: Vec<FullActionRow>,
     pub events: Vec<FullEventRow>,
     pub data: Vec<FullDataRow>,
+    pub stake_proposals: Vec<StakeProposalRow>,
+    pub near_prices: Vec<NearPriceRow>,
+    pub pool_metadata: Vec<PoolMetadataRow>,
+    pub whitelisted_pools: Vec<WhitelistedPoolRow>,
+    pub pool_stake_history: Vec<PoolStakeRow>,
+    /// The last row within the batch by the pool, the epoch and the delegator.
+    pub pool_epoch_delegators: HashMap<(String, String, String), PoolEpochDelegatorRow>,
+    pub pool_churn: Vec<PoolChurnRow>,
+    pub pool_rewards: Vec<PoolRewardRow>,
+    pub pool_migrations: Vec<PoolMigrationRow>,
 }
 
 pub struct ActionsData {
     pub commit_every_block: bool,
+    pub refresh_rollups: bool,
+    pub whitelist_contract_id: String,
+    pub price_oracle: Option<PriceOracle>,
+    pub pool_metadata: Option<PoolMetadataSync>,
+    pub pool_stake_history: Option<PoolStakeHistory>,
+    pub pool_migrations: PoolMigrations,
     pub rows: Rows,
     pub commit_handlers: Vec<tokio::task::JoinHandle<Result<(), clickhouse::error::Error>>>,
 }
@@ -150,8 +276,18 @@ impl ActionsData {
         let commit_every_block = env::var("COMMIT_EVERY_BLOCK")
             .map(|v| v == "true")
             .unwrap_or(false);
+        let refresh_rollups = env::var("REFRESH_ROLLUPS")
+            .map(|v| v == "true")
+            .unwrap_or(false);
         Self {
             commit_every_block,
+            refresh_rollups,
+            whitelist_contract_id: env::var("WHITELIST_CONTRACT_ID")
+                .unwrap_or(DEFAULT_WHITELIST_CONTRACT_ID.to_string()),
+            price_oracle: PriceOracle::from_env(),
+            pool_metadata: PoolMetadataSync::from_env(),
+            pool_stake_history: PoolStakeHistory::from_env(),
+            pool_migrations: PoolMigrations::from_env(),
             rows: Rows::default(),
             commit_handlers: vec![],
         }
@@ -161,8 +297,9 @@ impl ActionsData {
         &mut self,
         db: &ClickDB,
         block_height: BlockHeight,
+        block_timestamp: u64,
     ) -> anyhow::Result<()> {
-        let is_round_block = block_height % SAVE_STEP == 0;
+        let is_round_block = block_height % save_step() == 0;
         if is_round_block {
             tracing::log::info!(
                 target: CLICKHOUSE_TARGET,
@@ -173,7 +310,10 @@ impl ActionsData {
                 self.rows.data.len()
             );
         }
-        if self.rows.actions.len() >= db.min_batch || is_round_block || self.commit_every_block {
+        if self.rows.actions.len() >= db.effective_min_batch(block_timestamp)
+            || is_round_block
+            || self.commit_every_block
+        {
             self.commit(db).await?;
         }
 
@@ -187,7 +327,14 @@ impl ActionsData {
             self.commit_handlers.remove(0).await??;
         }
         let db = db.clone();
-        let handler = tokio::spawn(async move {
+        // The rollups are refreshed with `INSERT INTO`, and the `verify` command doesn't write.
+        let refresh_rollups = self.refresh_rollups && Verifier::get().is_none();
+        let span = tracing::info_span!(
+            "commit",
+            actions = rows.actions.len(),
+            events = rows.events.len()
+        );
+        let commit = async move {
             if !rows.actions.is_empty() {
                 insert_rows_with_retry(&db.client, &rows.actions, "actions").await?;
             }
@@ -197,20 +344,68 @@ impl ActionsData {
             if !rows.data.is_empty() {
                 insert_rows_with_retry(&db.client, &rows.data, "data").await?;
             }
+            if !rows.stake_proposals.is_empty() {
+                insert_rows_with_retry(&db.client, &rows.stake_proposals, "stake_proposals")
+                    .await?;
+            }
+            if !rows.near_prices.is_empty() {
+                insert_rows_with_retry(&db.client, &rows.near_prices, "near_prices").await?;
+            }
+            let pool_epoch_delegators: Vec<_> = rows.pool_epoch_delegators.into_values().collect();
+            if !pool_epoch_delegators.is_empty() {
+                insert_rows_with_retry(&db.client, &pool_epoch_delegators, "pool_epoch_delegators")
+                    .await?;
+            }
+            if !rows.pool_churn.is_empty() {
+                insert_rows_with_retry(&db.client, &rows.pool_churn, "pool_churn").await?;
+            }
+            if !rows.pool_rewards.is_empty() {
+                insert_rows_with_retry(&db.client, &rows.pool_rewards, "pool_rewards").await?;
+            }
+            if !rows.pool_migrations.is_empty() {
+                insert_rows_with_retry(&db.client, &rows.pool_migrations, "pool_migrations")
+                    .await?;
+            }
+            if !rows.pool_stake_history.is_empty() {
+                insert_rows_with_retry(&db.client, &rows.pool_stake_history, "pool_stake_history")
+                    .await?;
+            }
+            if !rows.whitelisted_pools.is_empty() {
+                insert_rows_with_retry(&db.client, &rows.whitelisted_pools, "whitelisted_pools")
+                    .await?;
+            }
+            if !rows.pool_metadata.is_empty() {
+                insert_rows_with_retry(&db.client, &rows.pool_metadata, "pool_metadata").await?;
+            }
             tracing::log::info!(
                 target: CLICKHOUSE_TARGET,
-                "Committed {} actions, {} events, {} data",
+                "Committed {} actions, {} events, {} data, {} stake_proposals",
                 rows.actions.len(),
                 rows.events.len(),
-                rows.data.len()
+                rows.data.len(),
+                rows.stake_proposals.len()
             );
+            status::record_commit();
+            if refresh_rollups {
+                if let (Some(first), Some(last)) = (rows.actions.first(), rows.actions.last()) {
+                    rollups::refresh(
+                        &db,
+                        &rollups::ACTIONS_ROLLUPS,
+                        first.block_height,
+                        last.block_height,
+                    )
+                    .await?;
+                }
+            }
             Ok::<(), clickhouse::error::Error>(())
-        });
+        };
+        let handler = tokio::spawn(commit.instrument(span));
         self.commit_handlers.push(handler);
 
         Ok(())
     }
 
+    #[tracing::instrument(skip_all, fields(block_height = block.block.header.height))]
     pub async fn process_block(
         &mut self,
         db: &mut ClickDB,
@@ -218,19 +413,200 @@ impl ActionsData {
         last_db_block_height: BlockHeight,
     ) -> anyhow::Result<()> {
         let block_height = block.block.header.height;
-        let rows = extract_rows(block);
+        let block_timestamp = block.block.header.timestamp_nanosec;
+        let epoch_id = block.block.header.epoch_id.to_string();
+        let mut rows = extract_rows(block);
         if block_height > last_db_block_height {
+            if let Some(price_oracle) = &mut self.price_oracle {
+                if let Some(price) = price_oracle.near_usd_price(block_timestamp).await {
+                    for action in rows.actions.iter_mut() {
+                        action.deposit_usd = action
+                            .deposit
+                            .map(|deposit| yocto_near_to_usd(deposit, price));
+                        if action.method_name.as_deref().is_some_and(|method_name| {
+                            STAKING_POOL_AMOUNT_METHODS.contains(&method_name)
+                        }) {
+                            action.args_amount_usd = action
+                                .args_amount
+                                .map(|amount| yocto_near_to_usd(amount, price));
+                        }
+                    }
+                    for stake_proposal in rows.stake_proposals.iter_mut() {
+                        stake_proposal.stake_usd =
+                            Some(yocto_near_to_usd(stake_proposal.stake, price));
+                    }
+                }
+                self.rows.near_prices.extend(price_oracle.take_new_prices());
+            }
+            self.pool_migrations.start_block(block_height);
+            for event in &rows.events {
+                if event.status != ReceiptStatus::Success {
+                    continue;
+                }
+                let Some(log) = StakingPoolLog::parse(&event.log) else {
+                    continue;
+                };
+                self.rows
+                    .pool_migrations
+                    .extend(self.pool_migrations.process_log(
+                        &event.account_id,
+                        &log,
+                        block_height,
+                        block_timestamp,
+                        &event.transaction_hash,
+                    ));
+                if let StakingPoolLog::Staking {
+                    account_id,
+                    total_shares,
+                    ..
+                }
+                | StakingPoolLog::Unstaking {
+                    account_id,
+                    total_shares,
+                    ..
+                } = &log
+                {
+                    self.rows.pool_epoch_delegators.insert(
+                        (
+                            event.account_id.clone(),
+                            epoch_id.clone(),
+                            account_id.clone(),
+                        ),
+                        PoolEpochDelegatorRow {
+                            pool_id: event.account_id.clone(),
+                            epoch_id: epoch_id.clone(),
+                            delegator_id: account_id.clone(),
+                            block_height,
+                            staking_shares: *total_shares,
+                        },
+                    );
+                }
+                let (account_id, is_new_delegator, is_exited_delegator, staked, unstaked) = match log {
+                    StakingPoolLog::Staking {
+                        account_id,
+                        amount,
+                        shares,
+                        total_shares,
+                        ..
+                    } => (account_id, shares == total_shares, false, amount, 0),
+                    StakingPoolLog::Unstaking {
+                        account_id,
+                        amount,
+                        total_shares,
+                        ..
+                    } => (account_id, false, total_shares == 0, 0, amount),
+                    StakingPoolLog::EpochRewards {
+                        epoch_height,
+                        rewards,
+                        total_staked_balance,
+                        total_shares,
+                    } => {
+                        self.rows.pool_rewards.push(PoolRewardRow {
+                            pool_id: event.account_id.clone(),
+                            epoch_id: epoch_id.clone(),
+                            epoch_height,
+                            block_height,
+                            block_timestamp,
+                            receipt_id: event.receipt_id.clone(),
+                            rewards,
+                            total_staked_balance,
+                            total_shares,
+                        });
+                        continue;
+                    }
+                    _ => continue,
+                };
+                self.rows.pool_churn.push(PoolChurnRow {
+                    pool_id: event.account_id.clone(),
+                    epoch_id: epoch_id.clone(),
+                    account_id,
+                    block_height,
+                    receipt_id: event.receipt_id.clone(),
+                    log_index: event.log_index,
+                    is_new_delegator,
+                    is_exited_delegator,
+                    staked,
+                    unstaked,
+                });
+            }
+            for action in &rows.actions {
+                if action.account_id != self.whitelist_contract_id
+                    || action.status != ReceiptStatus::Success
+                {
+                    continue;
+                }
+                let is_whitelisted = match action.method_name.as_deref() {
+                    Some("add_staking_pool") => true,
+                    Some("remove_staking_pool") => false,
+                    _ => continue,
+                };
+                let Some(pool_id) = action
+                    .args
+                    .as_ref()
+                    .and_then(|args| serde_json::from_str::<serde_json::Value>(args).ok())
+                    .and_then(|args| {
+                        Some(args.get("staking_pool_account_id")?.as_str()?.to_string())
+                    })
+                else {
+                    continue;
+                };
+                self.rows.whitelisted_pools.push(WhitelistedPoolRow {
+                    pool_id,
+                    block_height,
+                    block_timestamp,
+                    transaction_hash: action.transaction_hash.clone(),
+                    is_whitelisted,
+                });
+            }
+            if let Some(pool_stake_history) = &mut self.pool_stake_history {
+                let pool_stake_rows = pool_stake_history
+                    .process_block(&epoch_id, block_height, block_timestamp)
+                    .await;
+                self.rows.pool_stake_history.extend(pool_stake_rows);
+            }
+            if let Some(pool_metadata) = &mut self.pool_metadata {
+                if pool_metadata.is_sync_needed(block_height) {
+                    match pool_metadata.sync_all(block_height).await {
+                        Ok(pool_metadata_rows) => {
+                            self.rows.pool_metadata.extend(pool_metadata_rows)
+                        }
+                        Err(err) => {
+                            tracing::log::error!(target: POOL_METADATA_TARGET, "Failed to sync pool metadata: {}", err);
+                        }
+                    }
+                }
+                for action in &rows.actions {
+                    if !pool_metadata.is_update_call(action) {
+                        continue;
+                    }
+                    let Some(pool_id) = action
+                        .args
+                        .as_ref()
+                        .and_then(|args| serde_json::from_str::<serde_json::Value>(args).ok())
+                        .and_then(|args| Some(args.get("pool_id")?.as_str()?.to_string()))
+                    else {
+                        continue;
+                    };
+                    match pool_metadata.sync_pool(&pool_id, block_height).await {
+                        Ok(pool_metadata_row) => self.rows.pool_metadata.extend(pool_metadata_row),
+                        Err(err) => {
+                            tracing::log::error!(target: POOL_METADATA_TARGET, "Failed to sync metadata of {}: {}", pool_id, err);
+                        }
+                    }
+                }
+            }
             self.rows.actions.extend(rows.actions);
             self.rows.events.extend(rows.events);
             self.rows.data.extend(rows.data);
+            self.rows.stake_proposals.extend(rows.stake_proposals);
         }
 
-        let is_round_block = block_height % SAVE_STEP == 0;
+        let is_round_block = block_height % save_step() == 0;
         if is_round_block {
             tracing::log::info!(target: CLICKHOUSE_TARGET, "#{}: Having {} actions, {} events, {} data", block_height, self.rows.actions.len(), self.rows.events.len(), self.rows.data.len());
         }
 
-        self.maybe_commit(db, block_height).await?;
+        self.maybe_commit(db, block_height, block_timestamp).await?;
         Ok(())
     }
 
@@ -260,10 +636,20 @@ pub struct ArgsData {
     pub balance: Option<String>,
 }
 
-pub fn extract_args_data(action: &ActionView) -> Option<ArgsData> {
+pub fn extract_args_data(account_id: &str, action: &ActionView) -> Option<ArgsData> {
     match action {
-        ActionView::FunctionCall { args, .. } => {
-            let mut args_data: ArgsData = serde_json::from_slice(&args).ok()?;
+        ActionView::FunctionCall {
+            method_name, args, ..
+        } => {
+            let mut args_data: ArgsData = match serde_json::from_slice(args) {
+                Ok(args_data) => args_data,
+                Err(_) => serde_json::from_value(ArgsDecoders::get().decode(
+                    account_id,
+                    method_name,
+                    args,
+                )?)
+                .ok()?,
+            };
             // If token length is larger than 64 bytes, we remove it.
             limit_length(&mut args_data.token_id);
             limit_length(&mut args_data.nft_token_id);
@@ -273,6 +659,20 @@ pub fn extract_args_data(action: &ActionView) -> Option<ArgsData> {
     }
 }
 
+/// Returns the function call args if they are valid JSON (or decoded by the `ArgsDecoders`) within
+/// `MAX_ARGS_JSON_LENGTH`, so they can be queried with the JSON functions without decoding.
+pub fn extract_args_json(account_id: &str, method_name: &str, args: &[u8]) -> Option<String> {
+    if args.len() > MAX_ARGS_JSON_LENGTH {
+        return None;
+    }
+    if serde_json::from_slice::<serde::de::IgnoredAny>(args).is_ok() {
+        return String::from_utf8(args.to_vec()).ok();
+    }
+    ArgsDecoders::get()
+        .decode(account_id, method_name, args)
+        .map(|args| args.to_string())
+}
+
 fn limit_length(s: &mut Option<String>) {
     if s.as_ref().map(|s| s.len()).unwrap_or(0) > MAX_TOKEN_LENGTH {
         *s = None;
@@ -286,7 +686,7 @@ fn string_from_vec_u8(value: &Vec<u8>) -> String {
 
 fn extract_return_value_int(execution_status: &ExecutionStatusView) -> Option<u128> {
     if let ExecutionStatusView::SuccessValue(value) = execution_status {
-        let str_value = serde_json::from_slice::<String>(&value).ok()?;
+        let str_value = serde_json::from_slice::<String>(value).ok()?;
         str_value.parse::<u128>().ok()
     } else {
         None
@@ -316,7 +716,7 @@ pub struct Event {
 }
 
 pub fn parse_event(event: &str) -> Option<Event> {
-    let mut event: Event = serde_json::from_str(&event).ok()?;
+    let mut event: Event = serde_json::from_str(event).ok()?;
     limit_length(&mut event.version);
     limit_length(&mut event.standard);
     limit_length(&mut event.event);
@@ -461,7 +861,31 @@ pub fn extract_rows(msg: BlockWithTxHashes) -> Rows {
                     for (action_index, action) in actions.into_iter().enumerate() {
                         let action_index =
                             u16::try_from(action_index).expect("Action index overflow");
-                        let args_data = extract_args_data(&action);
+                        if let (ActionView::DeployContract { code }, Some(abi_registry)) =
+                            (&action, AbiRegistry::get())
+                        {
+                            if status == ReceiptStatus::Success {
+                                abi_registry.set_code_hash(
+                                    &account_id,
+                                    CryptoHash::hash_bytes(code).to_string(),
+                                );
+                            }
+                        }
+                        let args_data = extract_args_data(&account_id, &action);
+                        if let ActionView::Stake { stake, public_key } = &action {
+                            rows.stake_proposals.push(StakeProposalRow {
+                                block_height,
+                                block_timestamp,
+                                transaction_hash: tx_hash.clone(),
+                                receipt_id: receipt_id.clone(),
+                                action_index,
+                                account_id: account_id.clone(),
+                                public_key: public_key.to_string(),
+                                stake: *stake,
+                                stake_usd: None,
+                                status,
+                            });
+                        }
                         rows.actions.push(FullActionRow {
                             block_height,
                             block_hash: block_hash.clone(),
@@ -475,20 +899,7 @@ pub fn extract_rows(msg: BlockWithTxHashes) -> Rows {
                             predecessor_id: predecessor_id.clone(),
                             account_id: account_id.clone(),
                             status,
-                            action: match action {
-                                ActionView::CreateAccount => ActionKind::CreateAccount,
-                                ActionView::DeployContract { .. } => ActionKind::DeployContract,
-                                ActionView::FunctionCall { .. } => ActionKind::FunctionCall,
-                                ActionView::Transfer { .. } => ActionKind::Transfer,
-                                ActionView::Stake { .. } => ActionKind::Stake,
-                                ActionView::AddKey { .. } => ActionKind::AddKey,
-                                ActionView::DeleteKey { .. } => ActionKind::DeleteKey,
-                                ActionView::DeleteAccount { .. } => ActionKind::DeleteAccount,
-                                ActionView::Delegate { .. } => ActionKind::Delegate,
-                                // ActionView::NonrefundableStorageTransfer { .. } => {
-                                //     ActionKind::NonrefundableStorageTransfer
-                                // }
-                            },
+                            action: ActionKind::from(&action),
                             action_json: serde_json::to_string(&action).unwrap(),
                             input_data_ids: input_data_ids
                                 .iter()
@@ -499,7 +910,7 @@ pub fn extract_rows(msg: BlockWithTxHashes) -> Rows {
                             status_failure: status_failure.clone(),
                             contract_hash: match &action {
                                 ActionView::DeployContract { code } => {
-                                    Some(CryptoHash::hash_bytes(&code).to_string())
+                                    Some(CryptoHash::hash_bytes(code).to_string())
                                 }
                                 _ => None,
                             },
@@ -524,6 +935,30 @@ pub fn extract_rows(msg: BlockWithTxHashes) -> Rows {
                                 }
                                 _ => None,
                             },
+                            access_key_allowance: match &action {
+                                ActionView::AddKey { access_key, .. } => {
+                                    match &access_key.permission {
+                                        AccessKeyPermissionView::FunctionCall {
+                                            allowance,
+                                            ..
+                                        } => *allowance,
+                                        _ => None,
+                                    }
+                                }
+                                _ => None,
+                            },
+                            access_key_method_names: match &action {
+                                ActionView::AddKey { access_key, .. } => {
+                                    match &access_key.permission {
+                                        AccessKeyPermissionView::FunctionCall {
+                                            method_names,
+                                            ..
+                                        } => method_names.clone(),
+                                        _ => vec![],
+                                    }
+                                }
+                                _ => vec![],
+                            },
                             deposit: match &action {
                                 ActionView::Transfer { deposit, .. } => Some(*deposit),
                                 ActionView::Stake { stake, .. } => Some(*stake),
@@ -533,6 +968,7 @@ pub fn extract_rows(msg: BlockWithTxHashes) -> Rows {
                                 // }
                                 _ => None,
                             },
+                            deposit_usd: None,
                             gas_price,
                             attached_gas: match &action {
                                 ActionView::FunctionCall { gas, .. } => Some(*gas),
@@ -552,6 +988,12 @@ pub fn extract_rows(msg: BlockWithTxHashes) -> Rows {
                                 }
                                 _ => None,
                             },
+                            args_json: match &action {
+                                ActionView::FunctionCall {
+                                    method_name, args, ..
+                                } => extract_args_json(&account_id, method_name, args),
+                                _ => None,
+                            },
                             args_account_id: args_data.as_ref().and_then(|args| {
                                 args.account_id
                                     .as_ref()
@@ -583,6 +1025,7 @@ pub fn extract_rows(msg: BlockWithTxHashes) -> Rows {
                             args_amount: args_data.as_ref().and_then(|args| {
                                 args.amount.as_ref().and_then(|amount| amount.parse().ok())
                             }),
+                            args_amount_usd: None,
                             args_balance: args_data.as_ref().and_then(|args| {
                                 args.balance
                                     .as_ref()
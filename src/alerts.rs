@@ -0,0 +1,806 @@
+use crate::actions::ReceiptStatus;
+use crate::click::*;
+use crate::labels::{load_labels, AccountLabelRow};
+use crate::metrics::Metrics;
+use crate::watch_list::{matches_account_pattern, WatchList};
+use crate::webhook_outbox::WebhookOutbox;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use clickhouse::Row;
+use fastnear_primitives::near_primitives::types::AccountId;
+use handlebars::{handlebars_helper, Handlebars};
+use hmac::{Hmac, Mac};
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::{Deserialize, Serialize};
+use serde_repr::{Deserialize_repr, Serialize_repr};
+use sha2::Sha256;
+use tokio::sync::{mpsc, oneshot, Semaphore};
+
+pub const ALERTS_TARGET: &str = "alerts";
+const YOCTO_PER_NEAR: u128 = 10u128.pow(24);
+const DEFAULT_EMAIL_DIGEST_MINUTES: u64 = 10;
+const DEFAULT_WHALE_MIN_AMOUNT: u128 = 100_000 * YOCTO_PER_NEAR;
+/// Staking pool methods with an amount that are checked by the whale rules, in addition to
+/// transfers.
+const WHALE_METHODS: [&str; 3] = ["deposit_and_stake", "stake", "unstake"];
+const DEFAULT_TEMPLATE_NAME: &str = "default";
+const SIGNATURE_HEADER: &str = "X-Signature";
+const SIGNATURE_TIMESTAMP_HEADER: &str = "X-Signature-Timestamp";
+const DEFAULT_TEXT_TEMPLATE: &str =
+    "Alert {{rule_id}}: {{signer_id}} called {{join methods}} with {{near deposit}} ({{status}}) in {{transaction_hash}}";
+const DEFAULT_DIGEST_TEMPLATE_NAME: &str = "default_digest";
+const DEFAULT_DIGEST_TEXT_TEMPLATE: &str =
+    "Alert {{rule_id}}: {{num_transactions}} transactions with {{near deposit}} for {{join account_ids}}, the last one {{transaction_hash}}";
+/// How often the alert digests are checked for the end of their windows.
+const ALERT_DIGEST_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+const DEFAULT_MAX_ALERT_DELIVERIES: usize = 64;
+/// How long the shutdown waits for the alerts that are being delivered.
+const ALERT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Copy, Clone, Debug, Serialize_repr, Deserialize_repr, PartialEq)]
+#[repr(u8)]
+pub enum AlertChannel {
+    /// `POST` the alert as JSON to the `target` URL.
+    Webhook = 1,
+    /// Send the alert text to the Telegram chat ID from the `target` with the
+    /// `TELEGRAM_BOT_TOKEN` bot.
+    Telegram = 2,
+    /// `POST` the alert as an embed to the Discord webhook URL from the `target`.
+    Discord = 3,
+    /// Send the alert text to the email address from the `target`. Emails are batched into a
+    /// digest every `EMAIL_DIGEST_MINUTES`.
+    Email = 4,
+}
+
+#[derive(Copy, Clone, Debug, Serialize_repr, Deserialize_repr, PartialEq)]
+#[repr(u8)]
+pub enum AlertKind {
+    /// Matches transactions by the rule conditions.
+    Transaction = 1,
+    /// Matches any single stake, unstake or transfer of at least `min_amount_yocto` (defaults to
+    /// 100,000 NEAR) that touches a pool of the `account_pattern`. The watch list is ignored.
+    Whale = 2,
+}
+
+/// A rule from the `alert_rules` table. Empty conditions match everything.
+#[derive(Row, Deserialize, Clone, Debug)]
+pub struct AlertRuleRow {
+    pub rule_id: String,
+    pub kind: AlertKind,
+    /// Either an exact account ID, `*` for any account, `*.suffix` for the account and its
+    /// sub-accounts, or a public key (e.g. `ed25519:...`) that matches the signer of the
+    /// transactions signed with the key.
+    pub account_pattern: String,
+    pub method_name: Option<String>,
+    /// The minimum amount in yoctoNEAR. Matches if either the total attached deposit of the
+    /// transaction, or an attached deposit or the staking pool `amount` argument of an action (of
+    /// the `method_name`, if set) reaches it.
+    pub min_amount_yocto: Option<u128>,
+    /// The minimum amount in the smallest units of the token. Matches if either the `amount`
+    /// argument of a non-staking function call (of the `method_name`, if set) or the `amount` of
+    /// an event reaches it.
+    pub min_token_amount_units: Option<u128>,
+    pub status: Option<ReceiptStatus>,
+    pub channel: AlertChannel,
+    pub target: String,
+    /// The handlebars template of the message with the alert fields. For the webhook channel,
+    /// the rendered template is added to the JSON body as `text`.
+    pub template: Option<String>,
+    /// The shared secret of the webhook channel to sign the requests with.
+    pub secret: Option<String>,
+    /// The window to coalesce the matches of the same accounts into a single digest alert.
+    pub digest_seconds: Option<u32>,
+}
+
+/// The matched transaction that is sent to the notification channel.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Alert {
+    pub rule_id: String,
+    pub transaction_hash: String,
+    pub signer_id: String,
+    pub account_ids: Vec<String>,
+    pub methods: Vec<String>,
+    pub deposit: String,
+    pub status: String,
+    pub tx_block_height: u64,
+    pub tx_block_timestamp: u64,
+    pub events: Vec<AlertEvent>,
+    /// Labels of the signer and the matched accounts from the `account_labels` table.
+    pub labels: HashMap<String, String>,
+    /// The number of the transactions of a digest alert, otherwise 1. The other fields are of the
+    /// last transaction, except for the total `deposit`.
+    pub num_transactions: u64,
+    /// The transaction hashes of a digest alert.
+    pub transaction_hashes: Vec<String>,
+}
+
+/// The JSON body of the webhook channel, with the rendered template of the rule, if set.
+#[derive(Serialize)]
+struct WebhookPayload<'a> {
+    #[serde(flatten)]
+    alert: &'a Alert,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<&'a str>,
+}
+
+/// An `EVENT_JSON` event of the matched transaction.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AlertEvent {
+    pub contract_id: String,
+    pub standard: String,
+    pub version: String,
+    pub event: String,
+    pub data: serde_json::Value,
+}
+
+/// An attached deposit or an `amount` argument of an action.
+pub struct ActionAmount {
+    pub predecessor_id: AccountId,
+    pub receiver_id: AccountId,
+    /// The method name for function calls.
+    pub method_name: Option<String>,
+    /// In yoctoNEAR, or in the token units if `is_token` is set.
+    pub amount: u128,
+    /// Whether the amount is the `amount` argument of a non-staking function call.
+    pub is_token: bool,
+}
+
+/// The transaction fields that are checked against the alert rules.
+pub struct AlertCandidate<'a> {
+    pub transaction_hash: &'a str,
+    pub signer_id: &'a str,
+    pub signer_public_key: &'a str,
+    pub accounts: &'a HashSet<AccountId>,
+    pub methods: &'a [String],
+    pub deposit: u128,
+    pub action_amounts: &'a [ActionAmount],
+    pub status: ReceiptStatus,
+    pub tx_block_height: u64,
+    pub tx_block_timestamp: u64,
+    pub events: &'a [AlertEvent],
+}
+
+impl AlertRuleRow {
+    fn matches_account(&self, account_id: &AccountId) -> bool {
+        matches_account_pattern(&self.account_pattern, account_id)
+    }
+
+    fn matches_action_amount(
+        &self,
+        candidate: &AlertCandidate,
+        is_token: bool,
+        min_amount: u128,
+    ) -> bool {
+        candidate.action_amounts.iter().any(|action_amount| {
+            action_amount.is_token == is_token
+                && action_amount.amount >= min_amount
+                && (self.method_name.is_none() || action_amount.method_name == self.method_name)
+        })
+    }
+
+    fn matches_amount_yocto(&self, candidate: &AlertCandidate, min_amount: u128) -> bool {
+        candidate.deposit >= min_amount || self.matches_action_amount(candidate, false, min_amount)
+    }
+
+    fn matches_token_amount_units(&self, candidate: &AlertCandidate, min_amount: u128) -> bool {
+        let event_amount_matches = candidate
+            .events
+            .iter()
+            .filter_map(|event| event.data.as_array())
+            .flatten()
+            .filter_map(|data| data.get("amount")?.as_str()?.parse::<u128>().ok())
+            .any(|amount| amount >= min_amount);
+        event_amount_matches || self.matches_action_amount(candidate, true, min_amount)
+    }
+
+    /// Returns the pools touched by a large enough stake, unstake or transfer.
+    fn matches_whale(&self, candidate: &AlertCandidate) -> Vec<String> {
+        let min_amount = self.min_amount_yocto.unwrap_or(DEFAULT_WHALE_MIN_AMOUNT);
+        let mut account_ids = vec![];
+        for action_amount in candidate.action_amounts {
+            if action_amount.is_token {
+                continue;
+            }
+            let is_whale_action = match &action_amount.method_name {
+                Some(method_name) => WHALE_METHODS.contains(&method_name.as_str()),
+                None => true,
+            };
+            if !is_whale_action || action_amount.amount < min_amount {
+                continue;
+            }
+            for account_id in [&action_amount.receiver_id, &action_amount.predecessor_id] {
+                if self.matches_account(account_id)
+                    && !account_ids.contains(&account_id.to_string())
+                {
+                    account_ids.push(account_id.to_string());
+                }
+            }
+        }
+        account_ids
+    }
+
+    /// Returns the matched accounts, or `None` if the rule doesn't match the transaction.
+    fn matches(
+        &self,
+        candidate: &AlertCandidate,
+        is_active: impl Fn(&AccountId) -> bool,
+    ) -> Option<Vec<String>> {
+        if let Some(method_name) = &self.method_name {
+            if !candidate.methods.contains(method_name) {
+                return None;
+            }
+        }
+        if let Some(status) = self.status {
+            if candidate.status != status {
+                return None;
+            }
+        }
+        if self.kind == AlertKind::Whale {
+            let account_ids = self.matches_whale(candidate);
+            return (!account_ids.is_empty()).then_some(account_ids);
+        }
+        if let Some(min_amount) = self.min_amount_yocto {
+            if !self.matches_amount_yocto(candidate, min_amount) {
+                return None;
+            }
+        }
+        if let Some(min_amount) = self.min_token_amount_units {
+            if !self.matches_token_amount_units(candidate, min_amount) {
+                return None;
+            }
+        }
+        if self.account_pattern == candidate.signer_public_key {
+            return Some(vec![candidate.signer_id.to_string()]);
+        }
+        let account_ids: Vec<String> = candidate
+            .accounts
+            .iter()
+            .filter(|account_id| self.matches_account(account_id) && is_active(account_id))
+            .map(|account_id| account_id.to_string())
+            .collect();
+        (!account_ids.is_empty()).then_some(account_ids)
+    }
+}
+
+/// Delivers alerts to the notification channels.
+#[derive(Clone)]
+pub struct Notifier {
+    pub client: reqwest::Client,
+    /// Templates of the rules by the rule ID, and the default text template.
+    pub templates: Arc<Handlebars<'static>>,
+    pub telegram_bot_token: Option<String>,
+    /// Queue of the email digest, with the email address and the alert text.
+    pub email_digest: Option<mpsc::UnboundedSender<(String, String)>>,
+    /// Alerts that failed to be delivered are retried from the outbox, with `WEBHOOK_OUTBOX=true`.
+    pub outbox: Option<WebhookOutbox>,
+    /// Limits the alerts that are delivered at once to `MAX_ALERT_DELIVERIES`, so a burst of
+    /// matches against a slow channel doesn't pile up tasks.
+    pub deliveries: Arc<Semaphore>,
+    pub max_deliveries: usize,
+}
+
+/// Alert rules loaded from the `alert_rules` table on start.
+pub struct Alerts {
+    pub notifier: Notifier,
+    pub rules: Vec<AlertRuleRow>,
+    /// Accounts muted in the watch list are excluded from the matched accounts.
+    pub watch_list: WatchList,
+    /// Account labels, loaded with `ACCOUNT_LABELS=true`.
+    pub labels: HashMap<String, AccountLabelRow>,
+    /// Queue of the alerts of the rules with `digest_seconds`.
+    pub alert_digest: Option<mpsc::UnboundedSender<(AlertRuleRow, Alert)>>,
+    pub email_digest_task: Option<EmailDigestTask>,
+}
+
+impl Alerts {
+    pub async fn load(db: &ClickDB) -> anyhow::Result<Self> {
+        let rules = db
+            .client
+            .query(&format!(
+                "SELECT ?fields FROM alert_rules FINAL WHERE {}",
+                chain_filter()
+            ))
+            .fetch_all::<AlertRuleRow>()
+            .await?;
+        tracing::log::info!(target: ALERTS_TARGET, "Loaded {} alert rules", rules.len());
+        let (email_digest, email_digest_task) = match EmailDigest::from_env()? {
+            Some(email_digest) => {
+                let (sender, task) = email_digest.start();
+                (Some(sender), Some(task))
+            }
+            None => (None, None),
+        };
+        let max_deliveries = env::var("MAX_ALERT_DELIVERIES")
+            .map(|v| v.parse().expect("Invalid MAX_ALERT_DELIVERIES"))
+            .unwrap_or(DEFAULT_MAX_ALERT_DELIVERIES);
+        let notifier = Notifier {
+            client: reqwest::Client::new(),
+            templates: Arc::new(build_templates(&rules)?),
+            telegram_bot_token: env::var("TELEGRAM_BOT_TOKEN").ok(),
+            email_digest,
+            outbox: WebhookOutbox::from_env(db),
+            deliveries: Arc::new(Semaphore::new(max_deliveries)),
+            max_deliveries,
+        };
+        if let Some(outbox) = &notifier.outbox {
+            outbox.clone().start(notifier.clone(), rules.clone());
+        }
+        let alert_digest = rules
+            .iter()
+            .any(|rule| rule.digest_seconds.is_some())
+            .then(|| start_alert_digest(notifier.clone()));
+        Ok(Self {
+            notifier,
+            rules,
+            watch_list: WatchList::load(db).await?,
+            labels: if env::var("ACCOUNT_LABELS") == Ok("true".to_string()) {
+                load_labels(db).await?
+            } else {
+                HashMap::new()
+            },
+            alert_digest,
+            email_digest_task,
+        })
+    }
+
+    /// Fires all alerts for the rules that match the transaction. The delivery happens in the
+    /// background, so the indexing is not blocked by the notification channels. When
+    /// `MAX_ALERT_DELIVERIES` alerts are already being delivered, the alert is dropped.
+    pub fn process(&self, candidate: &AlertCandidate) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64;
+        for rule in &self.rules {
+            let Some(account_ids) = rule.matches(candidate, |account_id| {
+                !self.watch_list.is_muted(account_id, now)
+            }) else {
+                continue;
+            };
+            let labels = std::iter::once(candidate.signer_id)
+                .chain(account_ids.iter().map(String::as_str))
+                .filter_map(|account_id| {
+                    let label = self.labels.get(account_id)?;
+                    Some((account_id.to_string(), label.label.clone()))
+                })
+                .collect();
+            let alert = Alert {
+                rule_id: rule.rule_id.clone(),
+                transaction_hash: candidate.transaction_hash.to_string(),
+                signer_id: candidate.signer_id.to_string(),
+                account_ids,
+                methods: candidate.methods.to_vec(),
+                deposit: candidate.deposit.to_string(),
+                status: match candidate.status {
+                    ReceiptStatus::Success => "SUCCESS",
+                    ReceiptStatus::Failure => "FAILURE",
+                }
+                .to_string(),
+                tx_block_height: candidate.tx_block_height,
+                tx_block_timestamp: candidate.tx_block_timestamp,
+                events: candidate.events.to_vec(),
+                labels,
+                num_transactions: 1,
+                transaction_hashes: vec![candidate.transaction_hash.to_string()],
+            };
+            if let (Some(_), Some(alert_digest)) = (rule.digest_seconds, &self.alert_digest) {
+                let _ = alert_digest.send((rule.clone(), alert));
+                continue;
+            }
+            let Ok(permit) = self.notifier.deliveries.clone().try_acquire_owned() else {
+                tracing::log::error!(target: ALERTS_TARGET, "Dropped alert {} for {}: too many alerts are being delivered", rule.rule_id, alert.transaction_hash);
+                Metrics::get().dropped_alerts.inc();
+                continue;
+            };
+            let notifier = self.notifier.clone();
+            let rule = rule.clone();
+            tokio::spawn(async move {
+                notifier.deliver(&rule, &alert).await;
+                drop(permit);
+            });
+        }
+    }
+
+    /// Waits for the alerts that are being delivered and sends the pending email digests.
+    pub async fn shutdown(&mut self) {
+        let max_deliveries = self.notifier.max_deliveries as u32;
+        if tokio::time::timeout(
+            ALERT_SHUTDOWN_TIMEOUT,
+            self.notifier.deliveries.acquire_many(max_deliveries),
+        )
+        .await
+        .is_err()
+        {
+            tracing::log::warn!(target: ALERTS_TARGET, "Timed out waiting for the alerts to be delivered");
+        }
+        if let Some(task) = self.email_digest_task.take() {
+            let _ = task.shutdown.send(());
+            let _ = task.handle.await;
+        }
+    }
+}
+
+/// An alert digest that is collecting the matches until the end of its window.
+struct PendingAlertDigest {
+    rule: AlertRuleRow,
+    alert: Alert,
+    deposit: u128,
+    deadline: Instant,
+}
+
+/// Starts the loop that coalesces the alerts of the same rule and accounts within the
+/// `digest_seconds` window of the rule, and returns the queue for the alerts. The window starts
+/// with the first match. The pending digests are lost on restart.
+fn start_alert_digest(notifier: Notifier) -> mpsc::UnboundedSender<(AlertRuleRow, Alert)> {
+    let (sender, mut receiver) = mpsc::unbounded_channel::<(AlertRuleRow, Alert)>();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(ALERT_DIGEST_FLUSH_INTERVAL);
+        let mut pending: HashMap<(String, Vec<String>), PendingAlertDigest> = HashMap::new();
+        loop {
+            tokio::select! {
+                item = receiver.recv() => match item {
+                    Some((rule, alert)) => {
+                        let mut account_ids = alert.account_ids.clone();
+                        account_ids.sort();
+                        let deposit: u128 = alert.deposit.parse().unwrap_or_default();
+                        match pending.entry((rule.rule_id.clone(), account_ids)) {
+                            Entry::Occupied(mut entry) => {
+                                let digest = entry.get_mut();
+                                let mut transaction_hashes =
+                                    std::mem::take(&mut digest.alert.transaction_hashes);
+                                transaction_hashes.push(alert.transaction_hash.clone());
+                                digest.alert = Alert {
+                                    num_transactions: digest.alert.num_transactions + 1,
+                                    transaction_hashes,
+                                    ..alert
+                                };
+                                digest.deposit += deposit;
+                            }
+                            Entry::Vacant(entry) => {
+                                let window = Duration::from_secs(
+                                    rule.digest_seconds.unwrap_or_default().into(),
+                                );
+                                entry.insert(PendingAlertDigest {
+                                    rule,
+                                    alert,
+                                    deposit,
+                                    deadline: Instant::now() + window,
+                                });
+                            }
+                        }
+                    }
+                    None => break,
+                },
+                _ = interval.tick() => {
+                    let now = Instant::now();
+                    let due: Vec<_> = pending
+                        .iter()
+                        .filter(|(_, digest)| digest.deadline <= now)
+                        .map(|(key, _)| key.clone())
+                        .collect();
+                    for key in due {
+                        let digest = pending.remove(&key).unwrap();
+                        spawn_alert_digest(&notifier, digest);
+                    }
+                }
+            }
+        }
+        for (_, digest) in pending {
+            spawn_alert_digest(&notifier, digest);
+        }
+    });
+    sender
+}
+
+/// Delivers the digest in the background, so a slow channel doesn't hold up the digest loop. The
+/// digests wait for a delivery permit instead of being dropped over `MAX_ALERT_DELIVERIES`.
+fn spawn_alert_digest(notifier: &Notifier, digest: PendingAlertDigest) {
+    let notifier = notifier.clone();
+    tokio::spawn(async move {
+        let Ok(_permit) = notifier.deliveries.clone().acquire_owned().await else {
+            return;
+        };
+        let alert = Alert {
+            deposit: digest.deposit.to_string(),
+            ..digest.alert
+        };
+        notifier.deliver(&digest.rule, &alert).await;
+    });
+}
+
+impl Notifier {
+    /// Sends the alert with retries, and queues it in the outbox if all of them failed.
+    async fn deliver(&self, rule: &AlertRuleRow, alert: &Alert) {
+        let Err(err) = self.send_with_retry(rule, alert).await else {
+            return;
+        };
+        tracing::log::error!(target: ALERTS_TARGET, "Failed to send alert {} for {}: {}", rule.rule_id, alert.transaction_hash, err);
+        // The email digest only fails without `SMTP_URL`, so a retry wouldn't help.
+        if rule.channel == AlertChannel::Email {
+            return;
+        }
+        if let Some(outbox) = &self.outbox {
+            if let Err(err) = outbox.enqueue(alert).await {
+                tracing::log::error!(target: ALERTS_TARGET, "Failed to queue alert {} for {}: {}", rule.rule_id, alert.transaction_hash, err);
+            }
+        }
+    }
+
+    pub async fn send(&self, rule: &AlertRuleRow, alert: &Alert) -> anyhow::Result<()> {
+        let template_name = match &rule.template {
+            Some(_) => rule.rule_id.as_str(),
+            None if alert.num_transactions > 1 => DEFAULT_DIGEST_TEMPLATE_NAME,
+            None => DEFAULT_TEMPLATE_NAME,
+        };
+        let text = self.templates.render(template_name, alert)?;
+        match rule.channel {
+            AlertChannel::Webhook => {
+                let body = serde_json::to_vec(&WebhookPayload {
+                    alert,
+                    text: rule.template.as_ref().map(|_| text.as_str()),
+                })?;
+                let mut request = self
+                    .client
+                    .post(&rule.target)
+                    .header(reqwest::header::CONTENT_TYPE, "application/json");
+                if let Some(secret) = &rule.secret {
+                    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+                    request = request
+                        .header(SIGNATURE_TIMESTAMP_HEADER, timestamp.to_string())
+                        .header(
+                            SIGNATURE_HEADER,
+                            format!("sha256={}", webhook_signature(secret, timestamp, &body)),
+                        );
+                }
+                request.body(body).send().await?.error_for_status()?;
+            }
+            AlertChannel::Telegram => {
+                let token = self
+                    .telegram_bot_token
+                    .as_ref()
+                    .ok_or_else(|| anyhow::anyhow!("TELEGRAM_BOT_TOKEN is not set"))?;
+                self.client
+                    .post(format!("https://api.telegram.org/bot{}/sendMessage", token))
+                    .json(&serde_json::json!({
+                        "chat_id": rule.target,
+                        "text": text,
+                        "disable_web_page_preview": true,
+                    }))
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+            AlertChannel::Discord => {
+                self.client
+                    .post(&rule.target)
+                    .json(&discord_payload(rule, alert, &text))
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+            AlertChannel::Email => {
+                self.email_digest
+                    .as_ref()
+                    .ok_or_else(|| anyhow::anyhow!("SMTP_URL is not set"))?
+                    .send((rule.target.clone(), text))?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn send_with_retry(&self, rule: &AlertRuleRow, alert: &Alert) -> anyhow::Result<()> {
+        let mut delay = Duration::from_millis(100);
+        let max_retries = 5;
+        let mut i = 0;
+        loop {
+            match self.send(rule, alert).await {
+                Ok(_) => break Ok(()),
+                Err(err) => {
+                    tracing::log::warn!(target: ALERTS_TARGET, "Attempt #{}: Error sending alert {}: {}", i, rule.rule_id, err);
+                    if i == max_retries - 1 {
+                        break Err(err);
+                    }
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+            }
+            i += 1;
+        }
+    }
+}
+
+handlebars_helper!(join: |values: array| values
+    .iter()
+    .map(|value| value.as_str().map(str::to_string).unwrap_or(value.to_string()))
+    .collect::<Vec<_>>()
+    .join(", "));
+handlebars_helper!(near: |amount: str| format_near(amount.parse().unwrap_or_default()));
+handlebars_helper!(json: |value: Json| value.to_string());
+
+/// Compiles the rule templates, so an invalid template fails on start. The templates are
+/// registered by the rule ID, so the rule IDs have to be unique and can't be the names of the
+/// default templates.
+fn build_templates(rules: &[AlertRuleRow]) -> anyhow::Result<Handlebars<'static>> {
+    let mut templates = Handlebars::new();
+    // The messages are either plain text or JSON, so the HTML escaping is not needed.
+    templates.register_escape_fn(handlebars::no_escape);
+    templates.register_helper("join", Box::new(join));
+    templates.register_helper("near", Box::new(near));
+    templates.register_helper("json", Box::new(json));
+    templates.register_template_string(DEFAULT_TEMPLATE_NAME, DEFAULT_TEXT_TEMPLATE)?;
+    templates.register_template_string(DEFAULT_DIGEST_TEMPLATE_NAME, DEFAULT_DIGEST_TEXT_TEMPLATE)?;
+    let mut rule_ids = HashSet::new();
+    for rule in rules {
+        if rule.rule_id.is_empty()
+            || rule.rule_id == DEFAULT_TEMPLATE_NAME
+            || rule.rule_id == DEFAULT_DIGEST_TEMPLATE_NAME
+        {
+            anyhow::bail!("Invalid rule ID {:?}", rule.rule_id);
+        }
+        if !rule_ids.insert(rule.rule_id.as_str()) {
+            anyhow::bail!("Duplicate rule ID {}", rule.rule_id);
+        }
+        if let Some(template) = &rule.template {
+            templates
+                .register_template_string(&rule.rule_id, template)
+                .map_err(|err| {
+                    anyhow::anyhow!("Invalid template of rule {}: {}", rule.rule_id, err)
+                })?;
+        }
+    }
+    Ok(templates)
+}
+
+/// Signs `{timestamp}.{body}` with HMAC-SHA256, so the receivers can verify the sender and reject
+/// replayed requests by the timestamp.
+fn webhook_signature(secret: &str, timestamp: u64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn discord_payload(rule: &AlertRuleRow, alert: &Alert, text: &str) -> serde_json::Value {
+    let field = |name: &str, value: String| {
+        serde_json::json!({
+            "name": name,
+            "value": if value.is_empty() { "-".to_string() } else { value },
+            "inline": true,
+        })
+    };
+    serde_json::json!({
+        "embeds": [{
+            "title": if alert.num_transactions > 1 {
+                format!("Alert {} ({} transactions)", alert.rule_id, alert.num_transactions)
+            } else {
+                format!("Alert {}", alert.rule_id)
+            },
+            // Only the custom template is used as the description, since the fields already
+            // have the default text.
+            "description": rule.template.as_ref().map(|_| text),
+            "fields": [
+                field("Transaction", format!("`{}`", alert.transaction_hash)),
+                field("Signer", match alert.labels.get(&alert.signer_id) {
+                    Some(label) => format!("{} ({})", label, alert.signer_id),
+                    None => alert.signer_id.clone(),
+                }),
+                field("Accounts", alert.account_ids.join(", ")),
+                field("Methods", alert.methods.join(", ")),
+                field("Amount", format_near(alert.deposit.parse().unwrap_or_default())),
+                field("Status", alert.status.clone()),
+                field("Block", alert.tx_block_height.to_string()),
+            ],
+        }],
+    })
+}
+
+/// Formats the yoctoNEAR amount as NEAR without losing precision.
+fn format_near(amount: u128) -> String {
+    let fraction = amount % YOCTO_PER_NEAR;
+    if fraction == 0 {
+        format!("{} NEAR", amount / YOCTO_PER_NEAR)
+    } else {
+        let fraction = format!("{:024}", fraction);
+        format!(
+            "{}.{} NEAR",
+            amount / YOCTO_PER_NEAR,
+            fraction.trim_end_matches('0')
+        )
+    }
+}
+
+/// The running email digest loop. The pending digests are sent on shutdown.
+pub struct EmailDigestTask {
+    pub shutdown: oneshot::Sender<()>,
+    pub handle: tokio::task::JoinHandle<()>,
+}
+
+/// Batches email alerts per recipient and sends them as a single digest email every interval,
+/// to avoid mail floods during busy epochs.
+pub struct EmailDigest {
+    pub mailer: AsyncSmtpTransport<Tokio1Executor>,
+    pub from: String,
+    pub interval: Duration,
+}
+
+impl EmailDigest {
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(url) = env::var("SMTP_URL") else {
+            return Ok(None);
+        };
+        let digest_minutes = env::var("EMAIL_DIGEST_MINUTES")
+            .map(|v| v.parse().expect("Invalid EMAIL_DIGEST_MINUTES"))
+            .unwrap_or(DEFAULT_EMAIL_DIGEST_MINUTES);
+        Ok(Some(Self {
+            mailer: AsyncSmtpTransport::<Tokio1Executor>::from_url(&url)?.build(),
+            from: env::var("SMTP_FROM").expect("Missing SMTP_FROM env var"),
+            interval: Duration::from_secs(digest_minutes * 60),
+        }))
+    }
+
+    /// Starts the digest loop in the background and returns the queue for the alerts.
+    pub fn start(self) -> (mpsc::UnboundedSender<(String, String)>, EmailDigestTask) {
+        let (sender, mut receiver) = mpsc::unbounded_channel::<(String, String)>();
+        let (shutdown, mut shutdown_receiver) = oneshot::channel();
+        let handle = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.interval);
+            // The first tick completes immediately.
+            interval.tick().await;
+            let mut pending: HashMap<String, Vec<String>> = HashMap::new();
+            loop {
+                tokio::select! {
+                    item = receiver.recv() => match item {
+                        Some((to, text)) => pending.entry(to).or_default().push(text),
+                        None => break,
+                    },
+                    _ = interval.tick() => {
+                        for (to, texts) in std::mem::take(&mut pending) {
+                            self.send_digest(&to, &texts).await;
+                        }
+                    }
+                    _ = &mut shutdown_receiver => {
+                        while let Ok((to, text)) = receiver.try_recv() {
+                            pending.entry(to).or_default().push(text);
+                        }
+                        break;
+                    }
+                }
+            }
+            for (to, texts) in pending {
+                self.send_digest(&to, &texts).await;
+            }
+        });
+        (sender, EmailDigestTask { shutdown, handle })
+    }
+
+    async fn send_digest(&self, to: &str, texts: &[String]) {
+        let res = async {
+            let email = Message::builder()
+                .from(self.from.parse()?)
+                .to(to.parse()?)
+                .subject(format!("{} staking alerts", texts.len()))
+                .body(texts.join("\n\n"))?;
+            self.mailer.send(email).await?;
+            anyhow::Ok(())
+        }
+        .await;
+        match res {
+            Ok(_) => {
+                tracing::log::info!(target: ALERTS_TARGET, "Sent a digest of {} alerts to {}", texts.len(), to);
+            }
+            Err(err) => {
+                tracing::log::error!(target: ALERTS_TARGET, "Failed to send a digest of {} alerts to {}: {}", texts.len(), to, err);
+            }
+        }
+    }
+}
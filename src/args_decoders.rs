@@ -0,0 +1,117 @@
+use crate::abi::AbiRegistry;
+use std::env;
+use std::sync::OnceLock;
+
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use fastnear_primitives::near_primitives::borsh::BorshDeserialize;
+use serde::Deserialize;
+use serde_json::Value;
+
+/// Decodes the function call args that are not JSON into a JSON object, so the `args_*` columns
+/// and `args_json` can be filled for them too.
+pub trait ArgsDecoder: Send + Sync {
+    /// Returns `None` if the decoder doesn't know the method of the contract, or the args don't
+    /// match it.
+    fn decode(&self, contract_id: &str, method_name: &str, args: &[u8]) -> Option<Value>;
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum BorshType {
+    /// A string or an account ID.
+    String,
+    Bool,
+    U8,
+    U32,
+    U64,
+    /// Decoded as a string, like the JSON amounts.
+    U128,
+    /// A `Vec<u8>`, decoded as a base64 string.
+    Bytes,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct BorshField {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: BorshType,
+}
+
+/// The borsh-serialized struct of the args of a contract method.
+#[derive(Deserialize, Debug)]
+pub struct BorshArgsSchema {
+    pub contract_id: String,
+    pub method_name: String,
+    pub fields: Vec<BorshField>,
+}
+
+impl ArgsDecoder for BorshArgsSchema {
+    fn decode(&self, contract_id: &str, method_name: &str, args: &[u8]) -> Option<Value> {
+        if contract_id != self.contract_id || method_name != self.method_name {
+            return None;
+        }
+        decode_borsh_fields(&self.fields, args)
+    }
+}
+
+/// Decodes the args as a borsh struct with the fields.
+pub fn decode_borsh_fields(fields: &[BorshField], args: &[u8]) -> Option<Value> {
+    let mut buf = args;
+    let mut object = serde_json::Map::new();
+    for field in fields {
+        let value = match field.kind {
+            BorshType::String => Value::from(read::<String>(&mut buf)?),
+            BorshType::Bool => Value::from(read::<bool>(&mut buf)?),
+            BorshType::U8 => Value::from(read::<u8>(&mut buf)?),
+            BorshType::U32 => Value::from(read::<u32>(&mut buf)?),
+            BorshType::U64 => Value::from(read::<u64>(&mut buf)?),
+            BorshType::U128 => Value::from(read::<u128>(&mut buf)?.to_string()),
+            BorshType::Bytes => Value::from(BASE64_STANDARD.encode(read::<Vec<u8>>(&mut buf)?)),
+        };
+        object.insert(field.name.clone(), value);
+    }
+    // Trailing bytes mean that the args are of a different shape.
+    buf.is_empty().then_some(Value::Object(object))
+}
+
+fn read<T: BorshDeserialize>(buf: &mut &[u8]) -> Option<T> {
+    T::deserialize(buf).ok()
+}
+
+/// The registry of the args decoders. New decoders are added to the list in `from_env`.
+pub struct ArgsDecoders {
+    pub decoders: Vec<Box<dyn ArgsDecoder>>,
+}
+
+impl ArgsDecoders {
+    pub fn get() -> &'static Self {
+        static ARGS_DECODERS: OnceLock<ArgsDecoders> = OnceLock::new();
+        ARGS_DECODERS.get_or_init(Self::from_env)
+    }
+
+    /// Loads the borsh schemas from the JSON file at `BORSH_ARGS_SCHEMAS_PATH`, if set, and the
+    /// contract ABIs from `CONTRACT_ABI_DIR`.
+    fn from_env() -> Self {
+        let mut decoders: Vec<Box<dyn ArgsDecoder>> = vec![];
+        if let Some(abi_registry) = AbiRegistry::get() {
+            decoders.push(Box::new(abi_registry));
+        }
+        if let Ok(path) = env::var("BORSH_ARGS_SCHEMAS_PATH") {
+            let data = std::fs::read(&path)
+                .unwrap_or_else(|err| panic!("Failed to read {}: {}", path, err));
+            let schemas: Vec<BorshArgsSchema> = serde_json::from_slice(&data)
+                .unwrap_or_else(|err| panic!("Invalid BORSH_ARGS_SCHEMAS_PATH {}: {}", path, err));
+            for schema in schemas {
+                decoders.push(Box::new(schema));
+            }
+        }
+        Self { decoders }
+    }
+
+    pub fn decode(&self, contract_id: &str, method_name: &str, args: &[u8]) -> Option<Value> {
+        self.decoders
+            .iter()
+            .find_map(|decoder| decoder.decode(contract_id, method_name, args))
+    }
+}
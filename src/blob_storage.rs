@@ -0,0 +1,374 @@
+use std::env;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+use tokio::task::JoinSet;
+
+pub const BLOB_STORAGE_TARGET: &str = "blob_storage";
+const MAX_CONCURRENT_UPLOADS: usize = 32;
+
+const GCS_METADATA_TOKEN_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+/// The GCS access token is refreshed this long before it expires.
+const GCS_TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(300);
+
+const DEFAULT_S3_REGION: &str = "us-east-1";
+
+/// How the requests are authorized, configured with `BLOB_STORAGE_AUTH` env var.
+#[derive(Clone)]
+enum BlobStorageAuth {
+    /// No authorization, e.g. a public bucket or a local gateway.
+    None,
+    /// A static `BLOB_STORAGE_TOKEN` sent as a bearer token.
+    Bearer(String),
+    /// GCS OAuth access tokens from the metadata server, refreshed before they expire.
+    Gcs(Arc<Mutex<Option<(String, Instant)>>>),
+    /// AWS Signature Version 4 with the `AWS_*` credentials.
+    S3(S3Credentials),
+}
+
+#[derive(Clone)]
+struct S3Credentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+    region: String,
+}
+
+#[derive(Deserialize)]
+struct GcsToken {
+    access_token: String,
+    expires_in: u64,
+}
+
+/// Object storage for large blobs (e.g. full transaction views), accessed with plain HTTP
+/// `PUT`/`GET` requests against `{BLOB_STORAGE_URL}/{key}`.
+/// Works with the GCS XML API, S3 and S3-compatible gateways. The rows only store the keys, so the
+/// bucket or the endpoint can be moved by changing `BLOB_STORAGE_URL`.
+#[derive(Clone)]
+pub struct BlobStorage {
+    pub client: reqwest::Client,
+    pub url: String,
+    auth: BlobStorageAuth,
+}
+
+impl BlobStorage {
+    pub fn from_env() -> Option<Self> {
+        let url = env::var("BLOB_STORAGE_URL").ok()?;
+        let token = env::var("BLOB_STORAGE_TOKEN").ok();
+        let auth = match env::var("BLOB_STORAGE_AUTH").ok().as_deref() {
+            Some("none") => BlobStorageAuth::None,
+            Some("bearer") => BlobStorageAuth::Bearer(
+                token.expect("BLOB_STORAGE_AUTH=bearer requires BLOB_STORAGE_TOKEN"),
+            ),
+            Some("gcs") => BlobStorageAuth::Gcs(Arc::new(Mutex::new(None))),
+            Some("s3") => BlobStorageAuth::S3(S3Credentials {
+                access_key_id: env::var("AWS_ACCESS_KEY_ID")
+                    .expect("BLOB_STORAGE_AUTH=s3 requires AWS_ACCESS_KEY_ID"),
+                secret_access_key: env::var("AWS_SECRET_ACCESS_KEY")
+                    .expect("BLOB_STORAGE_AUTH=s3 requires AWS_SECRET_ACCESS_KEY"),
+                session_token: env::var("AWS_SESSION_TOKEN").ok(),
+                region: env::var("AWS_REGION").unwrap_or(DEFAULT_S3_REGION.to_string()),
+            }),
+            Some(auth) => panic!("Unknown BLOB_STORAGE_AUTH: {}", auth),
+            None => match token {
+                Some(token) => BlobStorageAuth::Bearer(token),
+                None => BlobStorageAuth::None,
+            },
+        };
+        Some(Self {
+            client: reqwest::Client::new(),
+            url: url.trim_end_matches('/').to_string(),
+            auth,
+        })
+    }
+
+    fn blob_url(&self, key: &str) -> String {
+        format!("{}/{}", self.url, key)
+    }
+
+    async fn request(
+        &self,
+        method: reqwest::Method,
+        key: &str,
+        body: Option<Vec<u8>>,
+    ) -> anyhow::Result<reqwest::RequestBuilder> {
+        let url = self.blob_url(key);
+        let mut request = match &self.auth {
+            BlobStorageAuth::None => self.client.request(method, &url),
+            BlobStorageAuth::Bearer(token) => self.client.request(method, &url).bearer_auth(token),
+            BlobStorageAuth::Gcs(token) => {
+                let token = self.gcs_token(token).await?;
+                self.client.request(method, &url).bearer_auth(token)
+            }
+            BlobStorageAuth::S3(credentials) => {
+                let payload = body.as_deref().unwrap_or_default();
+                let url = reqwest::Url::parse(&url)?;
+                let headers = sign_v4(credentials, &method, &url, payload, SystemTime::now())?;
+                let mut request = self.client.request(method, url);
+                for (name, value) in headers {
+                    request = request.header(name, value);
+                }
+                request
+            }
+        };
+        if let Some(body) = body {
+            request = request.body(body);
+        }
+        Ok(request)
+    }
+
+    /// Returns the cached GCS access token, or fetches a new one from the metadata server if it's
+    /// about to expire.
+    async fn gcs_token(&self, token: &Mutex<Option<(String, Instant)>>) -> anyhow::Result<String> {
+        let mut token = token.lock().await;
+        if let Some((access_token, expires_at)) = token.as_ref() {
+            if Instant::now() + GCS_TOKEN_REFRESH_MARGIN < *expires_at {
+                return Ok(access_token.clone());
+            }
+        }
+        let gcs_token: GcsToken = self
+            .client
+            .get(GCS_METADATA_TOKEN_URL)
+            .header("Metadata-Flavor", "Google")
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        tracing::log::debug!(target: BLOB_STORAGE_TARGET, "Refreshed the GCS access token, expires in {}s", gcs_token.expires_in);
+        *token = Some((
+            gcs_token.access_token.clone(),
+            Instant::now() + Duration::from_secs(gcs_token.expires_in),
+        ));
+        Ok(gcs_token.access_token)
+    }
+
+    pub async fn get(&self, key: &str) -> anyhow::Result<Vec<u8>> {
+        let response = self
+            .request(reqwest::Method::GET, key, None)
+            .await?
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    pub async fn put_with_retry(&self, key: &str, data: Vec<u8>) -> anyhow::Result<()> {
+        let mut delay = Duration::from_millis(100);
+        let max_retries = 10;
+        let mut i = 0;
+        loop {
+            let res = match self
+                .request(reqwest::Method::PUT, key, Some(data.clone()))
+                .await
+            {
+                Ok(request) => request
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .map_err(anyhow::Error::from),
+                Err(err) => Err(err),
+            };
+            match res {
+                Ok(_) => break Ok(()),
+                Err(err) => {
+                    tracing::log::error!(target: BLOB_STORAGE_TARGET, "Attempt #{}: Error uploading blob \"{}\": {}", i, key, err);
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                    if i == max_retries - 1 {
+                        break Err(err);
+                    }
+                }
+            }
+            i += 1;
+        }
+    }
+
+    /// Uploads all blobs by their keys concurrently. Returns the first error, if any.
+    pub async fn put_all(&self, blobs: Vec<(String, Vec<u8>)>) -> anyhow::Result<()> {
+        let mut uploads = JoinSet::new();
+        for (key, data) in blobs {
+            if uploads.len() >= MAX_CONCURRENT_UPLOADS {
+                uploads.join_next().await.unwrap()??;
+            }
+            let this = self.clone();
+            uploads.spawn(async move { this.put_with_retry(&key, data).await });
+        }
+        while let Some(res) = uploads.join_next().await {
+            res??;
+        }
+        Ok(())
+    }
+}
+
+/// Signs the request with AWS Signature Version 4 and returns the headers to add, including the
+/// `Authorization` header. The payload is always hashed, since the blobs are in memory anyway.
+fn sign_v4(
+    credentials: &S3Credentials,
+    method: &reqwest::Method,
+    url: &reqwest::Url,
+    payload: &[u8],
+    now: SystemTime,
+) -> anyhow::Result<Vec<(&'static str, String)>> {
+    let host = url
+        .host_str()
+        .ok_or_else(|| anyhow::anyhow!("BLOB_STORAGE_URL has no host"))?;
+    let host = match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    };
+    let amz_date = amz_date(now.duration_since(UNIX_EPOCH)?.as_secs());
+    let date = &amz_date[..8];
+    let payload_hash = hex(&Sha256::digest(payload));
+
+    let mut headers = vec![
+        ("host", host),
+        ("x-amz-content-sha256", payload_hash.clone()),
+        ("x-amz-date", amz_date.clone()),
+    ];
+    if let Some(session_token) = &credentials.session_token {
+        headers.push(("x-amz-security-token", session_token.clone()));
+    }
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+    let mut query: Vec<_> = url
+        .query_pairs()
+        .map(|(name, value)| (uri_encode(&name, true), uri_encode(&value, true)))
+        .collect();
+    query.sort();
+    let canonical_query = query
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join("&");
+    // The path of the URL is already percent-encoded, so it's only decoded to be encoded again
+    // the way S3 expects.
+    let path = percent_decode(url.path());
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        method.as_str(),
+        uri_encode(&path, false),
+        canonical_query,
+        canonical_headers,
+        signed_headers,
+        payload_hash
+    );
+
+    let scope = format!("{}/{}/s3/aws4_request", date, credentials.region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex(&Sha256::digest(canonical_request.as_bytes()))
+    );
+    let signing_key = [
+        credentials.region.as_bytes(),
+        b"s3".as_slice(),
+        b"aws4_request".as_slice(),
+    ]
+    .into_iter()
+    .fold(
+        hmac_sha256(
+            format!("AWS4{}", credentials.secret_access_key).as_bytes(),
+            date.as_bytes(),
+        ),
+        |key, data| hmac_sha256(&key, data),
+    );
+    let signature = hex(&hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+
+    // reqwest sets the host header from the URL.
+    headers.remove(0);
+    headers.push((
+        "authorization",
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            credentials.access_key_id, scope, signed_headers, signature
+        ),
+    ));
+    Ok(headers)
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Percent-encodes everything but the unreserved characters (and `/`, unless `encode_slash`).
+fn uri_encode(value: &str, encode_slash: bool) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            b'/' if !encode_slash => "/".to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex_byte = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex_byte) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Formats the unix timestamp as `YYYYMMDDTHHMMSSZ` in UTC.
+fn amz_date(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let seconds = timestamp % 86400;
+    // Converts the days since the epoch into a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
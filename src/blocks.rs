@@ -0,0 +1,432 @@
+use crate::watch_list::WatchList;
+use crate::*;
+use std::collections::HashMap;
+use std::env;
+
+use clickhouse::Row;
+use fastnear_primitives::near_primitives::types::{AccountId, BlockHeight};
+use fastnear_primitives::near_primitives::views::{BlockView, StateChangeValueView};
+use serde::{Deserialize, Serialize};
+use tracing::Instrument;
+
+/// Simplified block view in case there a block with no associated transactions.
+/// Also includes some extra metadata.
+#[derive(Row, Serialize, Deserialize, Clone, Debug)]
+pub struct BlockRow {
+    pub block_height: u64,
+    pub block_hash: String,
+    pub block_timestamp: u64,
+    pub prev_block_height: Option<u64>,
+    pub epoch_id: String,
+    pub chunks_included: u64,
+    pub prev_block_hash: String,
+    pub author_id: String,
+    pub signature: String,
+    pub protocol_version: u32,
+    pub gas_price: u128,
+    pub total_supply: u128,
+    /// The block height of the oldest transaction still pending after the block, written by the
+    /// `transactions` command to replay the cache from.
+    pub oldest_pending_tx_block_height: Option<u64>,
+}
+
+impl BlockRow {
+    pub fn from_block_view(block: &BlockView) -> Self {
+        Self {
+            block_height: block.header.height,
+            block_hash: block.header.hash.to_string(),
+            block_timestamp: block.header.timestamp,
+            prev_block_height: block.header.prev_height,
+            epoch_id: block.header.epoch_id.to_string(),
+            chunks_included: block.header.chunks_included,
+            prev_block_hash: block.header.prev_hash.to_string(),
+            author_id: block.author.to_string(),
+            signature: block.header.signature.to_string(),
+            protocol_version: block.header.latest_protocol_version,
+            gas_price: block.header.gas_price,
+            total_supply: block.header.total_supply,
+            oldest_pending_tx_block_height: None,
+        }
+    }
+}
+
+/// Gas price at the block where it changed from the previous block.
+#[derive(Row, Serialize, Deserialize)]
+pub struct GasPriceRow {
+    pub block_height: u64,
+    pub block_timestamp: u64,
+    pub gas_price: u128,
+}
+
+/// The first block of a new protocol version. Rows of later blocks with the same version are
+/// replaced by the earliest one.
+#[derive(Row, Serialize, Deserialize)]
+pub struct ProtocolUpgradeRow {
+    pub protocol_version: u32,
+    pub block_height: u64,
+    pub block_timestamp: u64,
+    pub epoch_id: String,
+}
+
+/// Total supply at the first block of every epoch.
+#[derive(Row, Serialize, Deserialize)]
+pub struct TotalSupplyRow {
+    pub block_height: u64,
+    pub block_timestamp: u64,
+    pub epoch_id: String,
+    pub total_supply: u128,
+}
+
+/// Epoch boundaries seen within a commit batch. Partial rows are combined with `min`/`max`/`sum`
+/// by the `AggregatingMergeTree` engine across batches.
+#[derive(Row, Serialize, Deserialize)]
+pub struct EpochRow {
+    pub epoch_id: String,
+    pub start_block_height: u64,
+    pub end_block_height: u64,
+    pub start_block_timestamp: u64,
+    pub end_block_timestamp: u64,
+    pub blocks: u64,
+}
+
+/// A chunk included in a block with the validator that produced it.
+#[derive(Row, Serialize, Deserialize)]
+pub struct ChunkRow {
+    pub block_height: u64,
+    pub block_timestamp: u64,
+    pub shard_id: u64,
+    pub chunk_hash: String,
+    pub author_id: String,
+    pub height_created: u64,
+    pub gas_used: u64,
+    pub gas_limit: u64,
+    pub balance_burnt: u128,
+}
+
+/// Storage usage of a watched account at the block where it changed.
+#[derive(Row, Serialize, Deserialize)]
+pub struct StorageUsageRow {
+    pub account_id: String,
+    pub block_height: u64,
+    pub block_timestamp: u64,
+    pub storage_usage: u64,
+}
+
+/// A height skipped by the chain, derived from the `prev_height` of the next produced block.
+#[derive(Row, Serialize, Deserialize)]
+pub struct SkippedBlockRow {
+    pub block_height: u64,
+    pub next_block_height: u64,
+    pub next_block_timestamp: u64,
+}
+
+#[derive(Default)]
+pub struct BlockRows {
+    pub blocks: Vec<BlockRow>,
+    pub gas_prices: Vec<GasPriceRow>,
+    pub storage_usage: Vec<StorageUsageRow>,
+    pub protocol_upgrades: Vec<ProtocolUpgradeRow>,
+    pub epochs: HashMap<String, EpochRow>,
+    pub chunks: Vec<ChunkRow>,
+    pub total_supply: Vec<TotalSupplyRow>,
+    pub skipped_blocks: Vec<SkippedBlockRow>,
+}
+
+/// `STORE_BLOCK_HEADERS=false` leaves the `blocks` table to the `transactions` command, which writes
+/// the same rows along with the state of its cache.
+pub fn store_block_headers() -> bool {
+    env::var("STORE_BLOCK_HEADERS")
+        .map(|v| v == "true")
+        .unwrap_or(true)
+}
+
+pub struct BlocksData {
+    pub commit_every_block: bool,
+    pub store_block_headers: bool,
+    pub store_summing_tables: bool,
+    pub watch_list: WatchList,
+    pub last_gas_price: Option<u128>,
+    pub last_protocol_version: Option<u32>,
+    pub last_epoch_id: Option<String>,
+    pub last_storage_usage: HashMap<AccountId, u64>,
+    pub rows: BlockRows,
+    pub commit_handlers: Vec<tokio::task::JoinHandle<Result<(), clickhouse::error::Error>>>,
+}
+
+impl BlocksData {
+    pub fn new(watch_list: WatchList) -> Self {
+        let commit_every_block = env::var("COMMIT_EVERY_BLOCK")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+        Self {
+            commit_every_block,
+            store_block_headers: store_block_headers(),
+            store_summing_tables: true,
+            watch_list,
+            last_gas_price: None,
+            last_protocol_version: None,
+            last_epoch_id: None,
+            last_storage_usage: HashMap::new(),
+            rows: BlockRows::default(),
+            commit_handlers: vec![],
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(block_height = block.block.header.height))]
+    pub async fn process_block(
+        &mut self,
+        db: &ClickDB,
+        block: BlockWithTxHashes,
+        last_db_block_height: BlockHeight,
+    ) -> anyhow::Result<()> {
+        let block_height = block.block.header.height;
+        let header = &block.block.header;
+        if block_height > last_db_block_height {
+            if self.store_block_headers {
+                self.rows
+                    .blocks
+                    .push(BlockRow::from_block_view(&block.block));
+            }
+            // The first processed block always records the gas price, since the previous one is
+            // not known after a restart.
+            if self.last_gas_price != Some(header.gas_price) {
+                self.rows.gas_prices.push(GasPriceRow {
+                    block_height,
+                    block_timestamp: header.timestamp,
+                    gas_price: header.gas_price,
+                });
+            }
+        }
+        self.last_gas_price = Some(header.gas_price);
+
+        if block_height > last_db_block_height {
+            let epoch = self
+                .rows
+                .epochs
+                .entry(header.epoch_id.to_string())
+                .or_insert_with(|| EpochRow {
+                    epoch_id: header.epoch_id.to_string(),
+                    start_block_height: block_height,
+                    end_block_height: block_height,
+                    start_block_timestamp: header.timestamp,
+                    end_block_timestamp: header.timestamp,
+                    blocks: 0,
+                });
+            epoch.start_block_height = epoch.start_block_height.min(block_height);
+            epoch.end_block_height = epoch.end_block_height.max(block_height);
+            epoch.start_block_timestamp = epoch.start_block_timestamp.min(header.timestamp);
+            epoch.end_block_timestamp = epoch.end_block_timestamp.max(header.timestamp);
+            epoch.blocks += 1;
+        }
+
+        if block_height > last_db_block_height {
+            if let Some(prev_height) = header.prev_height {
+                for skipped_block_height in prev_height + 1..block_height {
+                    self.rows.skipped_blocks.push(SkippedBlockRow {
+                        block_height: skipped_block_height,
+                        next_block_height: block_height,
+                        next_block_timestamp: header.timestamp,
+                    });
+                }
+            }
+            for shard in &block.shards {
+                if let Some(chunk) = &shard.chunk {
+                    self.rows.chunks.push(ChunkRow {
+                        block_height,
+                        block_timestamp: header.timestamp,
+                        shard_id: shard.shard_id,
+                        chunk_hash: chunk.header.chunk_hash.to_string(),
+                        author_id: chunk.author.to_string(),
+                        height_created: chunk.header.height_created,
+                        gas_used: chunk.header.gas_used,
+                        gas_limit: chunk.header.gas_limit,
+                        balance_burnt: chunk.header.balance_burnt,
+                    });
+                }
+            }
+        }
+
+        let epoch_id = header.epoch_id.to_string();
+        let is_epoch_start = self.last_epoch_id.as_ref() != Some(&epoch_id);
+        if is_epoch_start {
+            // Without a known previous epoch, the block is not necessarily the first one.
+            if self.last_epoch_id.is_some() && block_height > last_db_block_height {
+                self.rows.total_supply.push(TotalSupplyRow {
+                    block_height,
+                    block_timestamp: header.timestamp,
+                    epoch_id: epoch_id.clone(),
+                    total_supply: header.total_supply,
+                });
+            }
+            self.last_epoch_id = Some(epoch_id.clone());
+        }
+
+        // The protocol version only changes at the epoch boundaries. The first processed block
+        // after a restart is recorded as well, but the table keeps the earliest block of the version.
+        if (is_epoch_start || self.last_protocol_version.is_none())
+            && self.last_protocol_version != Some(header.latest_protocol_version)
+        {
+            if block_height > last_db_block_height {
+                self.rows.protocol_upgrades.push(ProtocolUpgradeRow {
+                    protocol_version: header.latest_protocol_version,
+                    block_height,
+                    block_timestamp: header.timestamp,
+                    epoch_id,
+                });
+            }
+            self.last_protocol_version = Some(header.latest_protocol_version);
+        }
+
+        if !self.watch_list.accounts.is_empty() {
+            self.process_storage_usage(&block, block_height > last_db_block_height);
+        }
+
+        self.maybe_commit(db, block_height, header.timestamp).await
+    }
+
+    #[tracing::instrument(name = "watch_list", skip_all)]
+    fn process_storage_usage(&mut self, block: &BlockWithTxHashes, store_rows: bool) {
+        for shard in &block.shards {
+            for state_change in &shard.state_changes {
+                if let StateChangeValueView::AccountUpdate {
+                    account_id,
+                    account,
+                } = &state_change.value
+                {
+                    if !self.watch_list.contains(account_id) {
+                        continue;
+                    }
+                    let prev_storage_usage = self
+                        .last_storage_usage
+                        .insert(account_id.clone(), account.storage_usage);
+                    if store_rows && prev_storage_usage != Some(account.storage_usage) {
+                        self.rows.storage_usage.push(StorageUsageRow {
+                            account_id: account_id.to_string(),
+                            block_height: block.block.header.height,
+                            block_timestamp: block.block.header.timestamp,
+                            storage_usage: account.storage_usage,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    pub async fn maybe_commit(
+        &mut self,
+        db: &ClickDB,
+        block_height: BlockHeight,
+        block_timestamp: u64,
+    ) -> anyhow::Result<()> {
+        let is_round_block = block_height % save_step() == 0;
+        if is_round_block {
+            tracing::log::info!(
+                target: CLICKHOUSE_TARGET,
+                "#{}: Having {} blocks, {} gas_prices, {} storage_usage, {} protocol_upgrades, {} epochs, {} chunks, {} total_supply, {} skipped_blocks",
+                block_height,
+                self.rows.blocks.len(),
+                self.rows.gas_prices.len(),
+                self.rows.storage_usage.len(),
+                self.rows.protocol_upgrades.len(),
+                self.rows.epochs.len(),
+                self.rows.chunks.len(),
+                self.rows.total_supply.len(),
+                self.rows.skipped_blocks.len(),
+            );
+        }
+        if self.rows.blocks.len() >= db.effective_min_batch(block_timestamp)
+            || is_round_block
+            || self.commit_every_block
+        {
+            self.commit(db).await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn commit(&mut self, db: &ClickDB) -> anyhow::Result<()> {
+        let mut rows = BlockRows::default();
+        std::mem::swap(&mut rows, &mut self.rows);
+        while self.commit_handlers.len() >= MAX_COMMIT_HANDLERS {
+            self.commit_handlers.remove(0).await??;
+        }
+        let db = db.clone();
+        let store_summing_tables = self.store_summing_tables;
+        let span = tracing::info_span!("commit", blocks = rows.blocks.len());
+        let commit = async move {
+            if !rows.blocks.is_empty() {
+                insert_rows_with_retry(&db.client, &rows.blocks, "blocks").await?;
+            }
+            if !rows.gas_prices.is_empty() {
+                insert_rows_with_retry(&db.client, &rows.gas_prices, "gas_prices").await?;
+            }
+            if !rows.storage_usage.is_empty() {
+                insert_rows_with_retry(&db.client, &rows.storage_usage, "storage_usage").await?;
+            }
+            if !rows.protocol_upgrades.is_empty() {
+                insert_rows_with_retry(&db.client, &rows.protocol_upgrades, "protocol_upgrades")
+                    .await?;
+            }
+            let epochs: Vec<_> = rows.epochs.into_values().collect();
+            if store_summing_tables && !epochs.is_empty() {
+                insert_rows_with_retry(&db.client, &epochs, "epochs").await?;
+            }
+            if !rows.chunks.is_empty() {
+                insert_rows_with_retry(&db.client, &rows.chunks, "chunks").await?;
+            }
+            if !rows.total_supply.is_empty() {
+                insert_rows_with_retry(&db.client, &rows.total_supply, "total_supply").await?;
+            }
+            if !rows.skipped_blocks.is_empty() {
+                insert_rows_with_retry(&db.client, &rows.skipped_blocks, "skipped_blocks").await?;
+            }
+            tracing::log::info!(
+                target: CLICKHOUSE_TARGET,
+                "Committed {} blocks, {} gas_prices, {} storage_usage, {} protocol_upgrades, {} epochs, {} chunks, {} total_supply, {} skipped_blocks",
+                rows.blocks.len(),
+                rows.gas_prices.len(),
+                rows.storage_usage.len(),
+                rows.protocol_upgrades.len(),
+                epochs.len(),
+                rows.chunks.len(),
+                rows.total_supply.len(),
+                rows.skipped_blocks.len(),
+            );
+            status::record_commit();
+            Ok::<(), clickhouse::error::Error>(())
+        };
+        let handler = tokio::spawn(commit.instrument(span));
+        self.commit_handlers.push(handler);
+
+        Ok(())
+    }
+
+    /// The `epochs` table is written for every block and only by this command, so it's the
+    /// checkpoint. The `blocks` table is also written by the `transactions` command.
+    pub async fn last_block_height(&self, db: &ClickDB) -> BlockHeight {
+        db.max("end_block_height", "epochs").await.unwrap_or(0)
+    }
+
+    /// Restores the epoch of the last processed block, so the restart doesn't record a new epoch
+    /// start.
+    pub async fn restore_last_epoch(&mut self, db: &ClickDB, last_block_height: BlockHeight) {
+        self.last_epoch_id = db
+            .client
+            .query(&format!(
+                "SELECT epoch_id FROM epochs WHERE start_block_height <= ? AND {} ORDER BY start_block_height DESC LIMIT 1",
+                chain_filter()
+            ))
+            .bind(last_block_height)
+            .fetch_optional::<String>()
+            .await
+            .ok()
+            .flatten();
+    }
+
+    pub async fn flush(&mut self) -> anyhow::Result<()> {
+        while let Some(handler) = self.commit_handlers.pop() {
+            handler.await??;
+        }
+        Ok(())
+    }
+}
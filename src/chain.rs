@@ -0,0 +1,69 @@
+use crate::*;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clickhouse::Row;
+use fastnear_primitives::near_primitives::hash::CryptoHash;
+use fastnear_primitives::near_primitives::types::BlockHeight;
+use serde::{Deserialize, Serialize};
+
+pub const CHAIN_TARGET: &str = "chain";
+
+/// A block that doesn't follow the previously processed block.
+#[derive(Row, Serialize, Deserialize)]
+pub struct ChainAnomalyRow {
+    pub command: String,
+    pub block_height: u64,
+    pub block_hash: String,
+    pub block_timestamp: u64,
+    pub prev_hash: String,
+    pub last_block_height: u64,
+    pub last_block_hash: String,
+    pub detected_timestamp: u64,
+}
+
+/// Tracks the processed blocks of a command to verify the hash chain.
+pub struct ChainContinuity {
+    pub command: String,
+    last_block: Option<(BlockHeight, CryptoHash)>,
+}
+
+impl ChainContinuity {
+    pub fn new(command: &str) -> Self {
+        Self {
+            command: command.to_string(),
+            last_block: None,
+        }
+    }
+
+    /// Checks that the `prev_hash` of the block is the hash of the last processed block. On a
+    /// mismatch the anomaly is written to the `chain_anomalies` table and an error is returned,
+    /// since the state carried between the blocks can't be trusted anymore. The resequencer only
+    /// orders the blocks by height, so a block that doesn't follow is passed on to this check.
+    pub async fn check(&mut self, db: &ClickDB, block: &BlockWithTxHashes) -> anyhow::Result<()> {
+        let header = &block.block.header;
+        if let Some((last_block_height, last_block_hash)) = self.last_block {
+            if header.prev_hash != last_block_hash {
+                let detected_timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+                let row = ChainAnomalyRow {
+                    command: self.command.clone(),
+                    block_height: header.height,
+                    block_hash: header.hash.to_string(),
+                    block_timestamp: header.timestamp,
+                    prev_hash: header.prev_hash.to_string(),
+                    last_block_height,
+                    last_block_hash: last_block_hash.to_string(),
+                    detected_timestamp: detected_timestamp as u64,
+                };
+                tracing::log::error!(target: CHAIN_TARGET, "Block #{} with prev_hash {} doesn't follow the last block #{} {}", header.height, header.prev_hash, last_block_height, last_block_hash);
+                insert_rows_with_retry(&db.client, &vec![row], "chain_anomalies").await?;
+                anyhow::bail!(
+                    "Chain discontinuity at block #{} after block #{}",
+                    header.height,
+                    last_block_height
+                );
+            }
+        }
+        self.last_block = Some((header.height, header.hash));
+        Ok(())
+    }
+}
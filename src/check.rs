@@ -0,0 +1,201 @@
+use crate::blob_storage::BlobStorage;
+use crate::click::*;
+use crate::transactions::TransactionEncoding;
+use crate::trim::TrimPolicy;
+use std::collections::HashMap;
+
+use clickhouse::Row;
+use fastnear_primitives::near_primitives::types::BlockHeight;
+use serde::{Deserialize, Serialize};
+
+/// The number of the offending keys listed in the report per invariant.
+const MAX_SAMPLES: usize = 100;
+
+#[derive(Row, Deserialize)]
+struct ViolationsRow {
+    count: u64,
+    samples: Vec<String>,
+}
+
+#[derive(Row, Deserialize)]
+struct CheckTransactionRow {
+    transaction_hash: String,
+    #[serde(with = "serde_bytes")]
+    transaction: Vec<u8>,
+    transaction_encoding: TransactionEncoding,
+    transaction_blob_key: Option<String>,
+}
+
+/// Violations of a single invariant.
+#[derive(Serialize)]
+pub struct Violations {
+    pub invariant: &'static str,
+    pub count: u64,
+    /// Up to `MAX_SAMPLES` offending transaction hashes or block heights.
+    pub samples: Vec<String>,
+}
+
+/// The result of the consistency check of the explorer tables over a block range.
+#[derive(Serialize)]
+pub struct CheckReport {
+    pub from_block_height: BlockHeight,
+    pub to_block_height: BlockHeight,
+    pub violations: Vec<Violations>,
+    pub transactions_checked: u64,
+    /// Offloaded transactions that were not checked, since `BLOB_STORAGE_URL` is not set.
+    pub transactions_skipped: u64,
+}
+
+impl CheckReport {
+    pub fn is_ok(&self) -> bool {
+        self.violations.iter().all(|violations| violations.count == 0)
+    }
+}
+
+/// Checks the invariants between the tables of the `transactions` command for the transactions
+/// included within the block range:
+/// - every `account_txs` transaction is in `transactions` (or `failed_transactions`);
+/// - every `block_txs` block is in `blocks` with the same timestamp and not before the
+///   transaction block;
+/// - the number of `receipt_txs` rows of a transaction matches the receipts of its view, or
+///   every action receipt of the view has a row with `TRIM_DATA_RECEIPTS=true`.
+pub async fn check(
+    db: &ClickDB,
+    from_block_height: BlockHeight,
+    to_block_height: BlockHeight,
+) -> anyhow::Result<CheckReport> {
+    let has_failed_transactions = db
+        .tables()
+        .await?
+        .iter()
+        .any(|table| table == "failed_transactions");
+    let transactions = if has_failed_transactions {
+        format!(
+            "SELECT transaction_hash FROM transactions WHERE tx_block_height BETWEEN ? AND ? AND {0}
+            UNION ALL SELECT transaction_hash FROM failed_transactions WHERE tx_block_height BETWEEN ? AND ? AND {0}",
+            chain_filter()
+        )
+    } else {
+        format!(
+            "SELECT transaction_hash FROM transactions WHERE tx_block_height BETWEEN ? AND ? AND {}",
+            chain_filter()
+        )
+    };
+    let mut query = db.client.query(&format!(
+        "SELECT uniqExact(transaction_hash) AS count, groupUniqArray({})(transaction_hash) AS samples
+        FROM account_txs
+        WHERE tx_block_height BETWEEN ? AND ? AND {} AND transaction_hash NOT IN ({})",
+        MAX_SAMPLES,
+        chain_filter(),
+        transactions
+    ));
+    let num_ranges = if has_failed_transactions { 3 } else { 2 };
+    for _ in 0..num_ranges {
+        query = query.bind(from_block_height).bind(to_block_height);
+    }
+    let missing_transactions = query.fetch_one::<ViolationsRow>().await?;
+
+    let invalid_blocks = db
+        .client
+        .query(&format!(
+            "SELECT uniqExact(b.block_height) AS count, groupUniqArray({})(toString(b.block_height)) AS samples
+            FROM block_txs AS b
+            LEFT JOIN (
+                SELECT block_height, block_timestamp FROM blocks WHERE block_height >= ? AND {1}
+            ) AS h ON b.block_height = h.block_height
+            WHERE b.tx_block_height BETWEEN ? AND ? AND {1}
+                AND (b.block_timestamp != h.block_timestamp OR b.block_height < b.tx_block_height)",
+            MAX_SAMPLES,
+            chain_filter()
+        ))
+        // Receipts of the transactions can be executed after the end of the range.
+        .bind(from_block_height)
+        .bind(from_block_height)
+        .bind(to_block_height)
+        .fetch_one::<ViolationsRow>()
+        .await?;
+
+    let receipt_ids: HashMap<String, Vec<String>> = db
+        .client
+        .query(&format!(
+            "SELECT transaction_hash, groupUniqArray(receipt_id) FROM receipt_txs
+            WHERE tx_block_height BETWEEN ? AND ? AND {} GROUP BY transaction_hash",
+            chain_filter()
+        ))
+        .bind(from_block_height)
+        .bind(to_block_height)
+        .fetch_all::<(String, Vec<String>)>()
+        .await?
+        .into_iter()
+        .collect();
+    // The data receipts are dropped from the stored views with `TRIM_DATA_RECEIPTS=true`, but
+    // still have rows, so only the action receipts of the views are matched.
+    let drop_data_receipts =
+        TrimPolicy::from_env().is_some_and(|trim_policy| trim_policy.drop_data_receipts);
+    let blob_storage = BlobStorage::from_env();
+    let mut receipt_mismatches = Violations {
+        invariant: "receipt_txs rows match the receipts of the transaction",
+        count: 0,
+        samples: vec![],
+    };
+    let mut transactions_checked = 0;
+    let mut transactions_skipped = 0;
+    let mut cursor = db
+        .client
+        .query(&format!(
+            "SELECT ?fields FROM transactions FINAL WHERE tx_block_height BETWEEN ? AND ? AND {}",
+            chain_filter()
+        ))
+        .bind(from_block_height)
+        .bind(to_block_height)
+        .fetch::<CheckTransactionRow>()?;
+    while let Some(row) = cursor.next().await? {
+        let data = match (&row.transaction_blob_key, &blob_storage) {
+            (Some(key), Some(blob_storage)) => blob_storage.get(key).await?,
+            (Some(_), None) => {
+                transactions_skipped += 1;
+                continue;
+            }
+            (None, _) => row.transaction,
+        };
+        let transaction = row.transaction_encoding.decode(&data)?;
+        let receipt_ids = receipt_ids
+            .get(&row.transaction_hash)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let is_match = if drop_data_receipts {
+            transaction.receipts.iter().all(|receipt| {
+                receipt_ids.contains(&receipt.receipt.receipt_id.to_string())
+            })
+        } else {
+            receipt_ids.len() == transaction.receipts.len() + transaction.data_receipts.len()
+        };
+        if !is_match {
+            receipt_mismatches.count += 1;
+            if receipt_mismatches.samples.len() < MAX_SAMPLES {
+                receipt_mismatches.samples.push(row.transaction_hash);
+            }
+        }
+        transactions_checked += 1;
+    }
+
+    Ok(CheckReport {
+        from_block_height,
+        to_block_height,
+        violations: vec![
+            Violations {
+                invariant: "account_txs transactions exist in transactions",
+                count: missing_transactions.count,
+                samples: missing_transactions.samples,
+            },
+            Violations {
+                invariant: "block_txs blocks exist in blocks with the same timestamp",
+                count: invalid_blocks.count,
+                samples: invalid_blocks.samples,
+            },
+            receipt_mismatches,
+        ],
+        transactions_checked,
+        transactions_skipped,
+    })
+}
@@ -1,14 +1,38 @@
+use crate::metrics::Metrics;
+use crate::shadow::ShadowDB;
+use crate::spill::SpillQueue;
+use crate::verify::Verifier;
 use clickhouse::{Client, Row};
+use std::collections::hash_map::RandomState;
 use std::env;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
 
+use serde::de::DeserializeOwned;
 use serde::Serialize;
 
 use fastnear_primitives::near_primitives::types::BlockHeight;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 pub const CLICKHOUSE_TARGET: &str = "clickhouse";
-pub const SAVE_STEP: u64 = 1000;
+const DEFAULT_SAVE_STEP: u64 = 1000;
 pub const MAX_COMMIT_HANDLERS: usize = 3;
+const MAX_INSERT_RETRIES: usize = 10;
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(100);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+/// ClickHouse error codes that won't go away on a retry: parsing and type errors, unknown
+/// tables, columns and databases, and access errors.
+const PERMANENT_ERROR_CODES: [u32; 14] = [6, 8, 16, 27, 33, 36, 47, 53, 60, 62, 81, 117, 497, 516];
+/// Blocks within the lag are at the head and committed in the smallest batches.
+const HEAD_BLOCK_LAG: Duration = Duration::from_secs(60);
+/// Blocks behind the lag are catching up and committed in the largest batches.
+const CATCH_UP_BLOCK_LAG: Duration = Duration::from_secs(3600);
+const DEFAULT_BATCH_TARGET_LATENCY_MS: u64 = 2000;
+const MAX_LATENCY_FACTOR: usize = 8;
+/// The custom setting with the chain ID. `SQL_` is the default allowed prefix of the custom
+/// settings.
+const CHAIN_ID_SETTING: &str = "SQL_chain_id";
 
 #[derive(Clone)]
 pub struct ClickDB {
@@ -24,39 +48,218 @@ impl ClickDB {
         }
     }
 
+    /// The number of rows to commit at the block, tuned by the `AdaptiveBatch` if enabled.
+    pub fn effective_min_batch(&self, block_timestamp: u64) -> usize {
+        let min_batch = match AdaptiveBatch::get() {
+            Some(adaptive_batch) => adaptive_batch.min_batch(block_timestamp),
+            None => self.min_batch,
+        };
+        Metrics::get().min_batch.set(min_batch as i64);
+        min_batch
+    }
+
     pub async fn max(&self, column: &str, table: &str) -> clickhouse::error::Result<BlockHeight> {
         let block_height = self
             .client
-            .query(&format!("SELECT max({}) FROM {}", column, table))
+            .query(&format!(
+                "SELECT max({}) FROM {} WHERE {}",
+                column,
+                table,
+                chain_filter()
+            ))
             .fetch_one::<u64>()
             .await?;
         Ok(block_height)
     }
 
+    /// The names of the tables in the current database.
+    pub async fn tables(&self) -> clickhouse::error::Result<Vec<String>> {
+        self.client
+            .query("SELECT name FROM system.tables WHERE database = currentDatabase()")
+            .fetch_all::<String>()
+            .await
+    }
+
+    /// The `MergeTree` tables without `chain_id` first in the sort key, whose rows of the chains
+    /// with the same key would replace each other with `MULTI_CHAIN=true`.
+    pub async fn tables_without_chain_sort_key(&self) -> clickhouse::error::Result<Vec<String>> {
+        self.client
+            .query(
+                "SELECT name FROM system.tables
+                WHERE database = currentDatabase() AND engine LIKE '%MergeTree'
+                    AND NOT startsWith(sorting_key || ',', 'chain_id,')
+                ORDER BY name",
+            )
+            .fetch_all::<String>()
+            .await
+    }
+
     pub async fn verify_connection(&self) -> clickhouse::error::Result<()> {
         self.client.query("SELECT 1").execute().await?;
         Ok(())
     }
 }
 
+/// Tunes the batch size between `MIN_BATCH_LOWER` and `MIN_BATCH_UPPER`. Blocks at the head are
+/// committed in small batches for freshness, and the batch grows with the block lag up to the
+/// upper bound during a catch-up. Inserts slower than `BATCH_TARGET_LATENCY_MS` double the batch
+/// size to reduce the number of inserts, and it shrinks back once they are twice as fast.
+pub struct AdaptiveBatch {
+    pub lower: usize,
+    pub upper: usize,
+    pub target_latency: Duration,
+    latency_factor: AtomicUsize,
+}
+
+impl AdaptiveBatch {
+    /// Returns the adaptive batch if both `MIN_BATCH_LOWER` and `MIN_BATCH_UPPER` are set.
+    pub fn get() -> Option<&'static Self> {
+        static ADAPTIVE_BATCH: OnceLock<Option<AdaptiveBatch>> = OnceLock::new();
+        ADAPTIVE_BATCH.get_or_init(Self::from_env).as_ref()
+    }
+
+    fn from_env() -> Option<Self> {
+        let lower: usize = env::var("MIN_BATCH_LOWER")
+            .ok()?
+            .parse()
+            .expect("Invalid MIN_BATCH_LOWER");
+        let upper: usize = env::var("MIN_BATCH_UPPER")
+            .ok()?
+            .parse()
+            .expect("Invalid MIN_BATCH_UPPER");
+        assert!(lower <= upper, "MIN_BATCH_LOWER is over MIN_BATCH_UPPER");
+        Some(Self {
+            lower,
+            upper,
+            target_latency: Duration::from_millis(
+                env::var("BATCH_TARGET_LATENCY_MS")
+                    .map(|v| v.parse().expect("Invalid BATCH_TARGET_LATENCY_MS"))
+                    .unwrap_or(DEFAULT_BATCH_TARGET_LATENCY_MS),
+            ),
+            latency_factor: AtomicUsize::new(1),
+        })
+    }
+
+    pub fn min_batch(&self, block_timestamp: u64) -> usize {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64;
+        let lag = Duration::from_nanos(now.saturating_sub(block_timestamp));
+        let backlog = (lag.saturating_sub(HEAD_BLOCK_LAG).as_secs_f64()
+            / (CATCH_UP_BLOCK_LAG - HEAD_BLOCK_LAG).as_secs_f64())
+        .min(1.0);
+        let base = self.lower + ((self.upper - self.lower) as f64 * backlog) as usize;
+        (base * self.latency_factor.load(Ordering::Relaxed)).min(self.upper)
+    }
+
+    pub fn observe_insert_latency(&self, latency: Duration) {
+        let _ = self
+            .latency_factor
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |factor| {
+                if latency > self.target_latency {
+                    Some((factor * 2).min(MAX_LATENCY_FACTOR))
+                } else if latency < self.target_latency / 2 {
+                    Some((factor / 2).max(1))
+                } else {
+                    None
+                }
+            });
+    }
+}
+
+/// Blocks between the forced commits, configured with `SAVE_STEP` env var.
+pub fn save_step() -> u64 {
+    static SAVE_STEP: OnceLock<u64> = OnceLock::new();
+    *SAVE_STEP.get_or_init(|| positive_env_var("SAVE_STEP").unwrap_or(DEFAULT_SAVE_STEP))
+}
+
+/// Parses the env var as a positive number, if set.
+pub fn positive_env_var(name: &str) -> Option<u64> {
+    let value: u64 = env::var(name)
+        .ok()?
+        .parse()
+        .unwrap_or_else(|_| panic!("Invalid {}", name));
+    assert!(value > 0, "{} must be positive", name);
+    Some(value)
+}
+
+/// Whether the database is shared by several chains, with `MULTI_CHAIN=true`. The `chain_id`
+/// columns are filled by their `DEFAULT getSetting('SQL_chain_id')` from the connection setting,
+/// so the rows don't carry the chain ID.
+pub fn is_multi_chain() -> bool {
+    static MULTI_CHAIN: OnceLock<bool> = OnceLock::new();
+    *MULTI_CHAIN.get_or_init(|| env::var("MULTI_CHAIN").map(|v| v == "true").unwrap_or(false))
+}
+
+/// The condition that scopes the queries of the data tables to the chain of the connection.
+pub fn chain_filter() -> &'static str {
+    if is_multi_chain() {
+        "chain_id = getSetting('SQL_chain_id')"
+    } else {
+        "1"
+    }
+}
+
+/// Sets the chain ID of the connection, with `MULTI_CHAIN=true`.
+pub fn with_chain_id(client: Client) -> Client {
+    if !is_multi_chain() {
+        return client;
+    }
+    client.with_option(
+        CHAIN_ID_SETTING,
+        env::var("CHAIN_ID").expect("CHAIN_ID is not set"),
+    )
+}
+
 fn establish_connection() -> Client {
-    Client::default()
-        .with_url(env::var("DATABASE_URL").unwrap())
-        .with_user(env::var("DATABASE_USER").unwrap())
-        .with_password(env::var("DATABASE_PASSWORD").unwrap())
-        .with_database(env::var("DATABASE_DATABASE").unwrap())
+    with_chain_id(
+        Client::default()
+            .with_url(env::var("DATABASE_URL").unwrap())
+            .with_user(env::var("DATABASE_USER").unwrap())
+            .with_password(env::var("DATABASE_PASSWORD").unwrap())
+            .with_database(env::var("DATABASE_DATABASE").unwrap()),
+    )
 }
 
+/// Inserts the rows, spilling them to disk on outages if `SPILL_DIR` is set. The committed rows
+/// are also written to the shadow database, if configured. The `verify` command compares the rows
+/// with the database instead.
 pub async fn insert_rows_with_retry<T>(
     client: &Client,
     rows: &Vec<T>,
     table: &str,
 ) -> clickhouse::error::Result<()>
+where
+    T: Row + Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    if let Some(verifier) = Verifier::get() {
+        return verifier.compare(client, rows, table).await;
+    }
+    let start = Instant::now();
+    match SpillQueue::get() {
+        Some(spill_queue) => spill_queue.insert(client, rows, table).await?,
+        None => insert_rows(client, rows, table).await?,
+    }
+    Metrics::get().observe_insert(table, rows.len(), start.elapsed());
+    if let Some(adaptive_batch) = AdaptiveBatch::get() {
+        adaptive_batch.observe_insert_latency(start.elapsed());
+    }
+    if let Some(shadow_db) = ShadowDB::get() {
+        shadow_db.insert(rows, table);
+    }
+    Ok(())
+}
+
+/// Inserts the rows, retrying on transient errors.
+pub async fn insert_rows<T>(
+    client: &Client,
+    rows: &Vec<T>,
+    table: &str,
+) -> clickhouse::error::Result<()>
 where
     T: Row + Serialize,
 {
-    let mut delay = Duration::from_millis(100);
-    let max_retries = 10;
     let mut i = 0;
     loop {
         let res = || async {
@@ -73,13 +276,47 @@ where
             Ok(v) => break Ok(v),
             Err(err) => {
                 tracing::log::error!(target: CLICKHOUSE_TARGET, "Attempt #{}: Error inserting rows into \"{}\": {}", i, table, err);
-                tokio::time::sleep(delay).await;
-                delay *= 2;
-                if i == max_retries - 1 {
+                if !is_transient_error(&err) || i == MAX_INSERT_RETRIES - 1 {
                     break Err(err);
                 }
+                tokio::time::sleep(retry_delay(i)).await;
             }
         };
         i += 1;
     }
 }
+
+/// Whether the insert may succeed on a retry. Network errors, timeouts and server errors (e.g.
+/// too many parts or memory limits) are transient, while row serialization and schema errors fail
+/// right away. Duplicate rows are not errors, since the tables are deduplicated by their engines.
+pub fn is_transient_error(err: &clickhouse::error::Error) -> bool {
+    use clickhouse::error::Error;
+    match err {
+        Error::Network(_) | Error::TimedOut | Error::Compression(_) | Error::Decompression(_) => {
+            true
+        }
+        Error::BadResponse(response) => {
+            error_code(response).map_or(true, |code| !PERMANENT_ERROR_CODES.contains(&code))
+        }
+        _ => false,
+    }
+}
+
+/// Parses the code from the `Code: 60. DB::Exception: ...` server response.
+fn error_code(response: &str) -> Option<u32> {
+    let (_, code) = response.split_once("Code: ")?;
+    code.split(|c: char| !c.is_ascii_digit())
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// The exponential backoff with a random half, so the parallel commit handlers don't retry in
+/// lockstep.
+fn retry_delay(attempt: usize) -> Duration {
+    let backoff = INITIAL_RETRY_DELAY
+        .saturating_mul(1 << attempt.min(16))
+        .min(MAX_RETRY_DELAY);
+    let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+    backoff / 2 + (backoff / 2).mul_f64(random)
+}
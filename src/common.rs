@@ -1,6 +1,34 @@
-use tracing_subscriber::EnvFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+#[cfg(feature = "otel")]
+use opentelemetry_sdk::trace::Tracer;
+#[cfg(feature = "otel")]
+use tracing_opentelemetry::OpenTelemetryLayer;
 
 pub fn setup_tracing(default: &str) {
+    let fmt_layer = tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_filter(env_filter(default));
+    let registry = tracing_subscriber::registry().with(fmt_layer);
+    // The console layer has its own filter for the tokio runtime spans.
+    #[cfg(feature = "tokio-console")]
+    let registry = registry.with(console_subscriber::spawn());
+    #[cfg(feature = "otel")]
+    let registry = registry.with(otel_layer().map(|layer| layer.with_filter(env_filter(default))));
+    registry.init();
+}
+
+/// Resident set size of the process in bytes. Only available on Linux.
+pub fn process_rss() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let rss_kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(rss_kb * 1024)
+}
+
+fn env_filter(default: &str) -> EnvFilter {
     let mut env_filter = EnvFilter::new(default);
 
     if let Ok(rust_log) = std::env::var("RUST_LOG") {
@@ -17,8 +45,51 @@ pub fn setup_tracing(default: &str) {
         }
     }
 
-    tracing_subscriber::fmt::Subscriber::builder()
-        .with_env_filter(env_filter)
-        .with_writer(std::io::stderr)
-        .init();
+    env_filter
+}
+
+/// The transport of the OTLP exporters from `OTEL_EXPORTER_OTLP_PROTOCOL`: `grpc`,
+/// `http/protobuf` (default) or `http/json`. Returns `None` for gRPC.
+#[cfg(feature = "otel")]
+pub fn otlp_http_protocol() -> Option<opentelemetry_otlp::Protocol> {
+    match std::env::var("OTEL_EXPORTER_OTLP_PROTOCOL").as_deref() {
+        Ok("grpc") => None,
+        Ok("http/json") => Some(opentelemetry_otlp::Protocol::HttpJson),
+        Ok("http/protobuf") | Err(_) => Some(opentelemetry_otlp::Protocol::HttpBinary),
+        Ok(protocol) => panic!("Unsupported OTEL_EXPORTER_OTLP_PROTOCOL {}", protocol),
+    }
+}
+
+/// Exports the spans with OTLP if `OTEL_EXPORTER_OTLP_ENDPOINT` is set. The exporter, the service
+/// name, the resource attributes and the sampler are configured with the standard `OTEL_*` env
+/// vars.
+#[cfg(feature = "otel")]
+fn otel_layer<S>() -> Option<OpenTelemetryLayer<S, Tracer>>
+where
+    S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+{
+    use opentelemetry::trace::TracerProvider;
+    use opentelemetry_otlp::WithExportConfig;
+
+    std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok()?;
+    let builder = opentelemetry_otlp::SpanExporter::builder();
+    let exporter = match otlp_http_protocol() {
+        Some(protocol) => builder.with_http().with_protocol(protocol).build(),
+        None => builder.with_tonic().build(),
+    }
+    .expect("Failed to build the OTLP exporter");
+    let provider = opentelemetry_sdk::trace::TracerProvider::builder()
+        .with_batch_exporter(exporter, opentelemetry_sdk::runtime::Tokio)
+        .build();
+    let tracer = provider.tracer(env!("CARGO_PKG_NAME"));
+    opentelemetry::global::set_tracer_provider(provider);
+    Some(tracing_opentelemetry::layer().with_tracer(tracer))
+}
+
+/// Exports the remaining spans and metrics before the exit.
+pub fn shutdown_tracing() {
+    #[cfg(feature = "otel")]
+    opentelemetry::global::shutdown_tracer_provider();
+    #[cfg(feature = "otel")]
+    crate::metrics::shutdown_otlp_export();
 }
@@ -0,0 +1,151 @@
+use crate::click::*;
+use crate::staking_pool_logs::StakingPoolLog;
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::str::FromStr;
+use std::vec::IntoIter;
+
+use bigdecimal::BigDecimal;
+use clickhouse::Row;
+use serde::Deserialize;
+
+#[derive(Row, Deserialize)]
+struct PoolLogRow {
+    account_id: String,
+    block_height: u64,
+    log: String,
+}
+
+#[derive(Row, Deserialize)]
+struct PoolRewardsRow {
+    pool_id: String,
+    epoch_height: u64,
+    block_height: u64,
+    block_timestamp: u64,
+    month: String,
+    total_staked_balance: u128,
+    total_shares: u128,
+}
+
+/// The delegator shares in the staking pool at the block of the epoch rewards log.
+pub struct DelegatorEpoch {
+    pub pool_id: String,
+    pub epoch_height: u64,
+    pub block_height: u64,
+    pub block_timestamp: u64,
+    /// The month of the block timestamp as `YYYY-MM`.
+    pub month: String,
+    pub shares: u128,
+    pub total_staked_balance: u128,
+    pub total_shares: u128,
+}
+
+impl DelegatorEpoch {
+    /// The staked balance of the delegator at the pool share price.
+    pub fn balance(&self) -> u128 {
+        self.balance_of(self.shares)
+    }
+
+    /// Converts the shares to the staked balance rounded down, like the staking pool contract.
+    /// The product doesn't fit into `u128`.
+    pub fn balance_of(&self, shares: u128) -> u128 {
+        if self.total_shares == 0 {
+            return 0;
+        }
+        let balance =
+            decimal(shares) * decimal(self.total_staked_balance) / decimal(self.total_shares);
+        balance.with_scale(0).to_string().parse().unwrap()
+    }
+
+    /// The delegator shares valued at the share price growth since the previous epoch rewards
+    /// log, rounded like the `account_monthly_rewards` rollup, so both agree for the delegator.
+    pub fn rewards_since(&self, previous: &DelegatorEpoch) -> u128 {
+        let previous_share_price = previous.share_price();
+        let share_price = self.share_price();
+        if previous_share_price == BigDecimal::from(0) || share_price <= previous_share_price {
+            return 0;
+        }
+        let rewards = decimal(self.shares) * (share_price - previous_share_price);
+        rewards.with_scale(0).to_string().parse().unwrap()
+    }
+
+    /// The share price with the 24 decimals of the `Decimal256(24)` of the rollup.
+    fn share_price(&self) -> BigDecimal {
+        if self.total_shares == 0 {
+            return BigDecimal::from(0);
+        }
+        (decimal(self.total_staked_balance) / decimal(self.total_shares)).with_scale(24)
+    }
+}
+
+fn decimal(value: u128) -> BigDecimal {
+    BigDecimal::from_str(&value.to_string()).unwrap()
+}
+
+/// Returns the delegator shares at every epoch rewards log up to the block height, ordered by the
+/// pool and the block height. The shares are the total shares from the last staking or unstaking
+/// log in the `events` table, like the `account_monthly_rewards` rollup, so the shares of a
+/// delegator that staked before the indexing started are known from its first indexed log.
+pub async fn delegator_epochs(
+    db: &ClickDB,
+    delegator_id: &str,
+    pool_id: Option<&str>,
+    to_block_height: u64,
+) -> clickhouse::error::Result<Vec<DelegatorEpoch>> {
+    let logs = db
+        .client
+        .query(&format!("SELECT ?fields FROM events WHERE status = 'SUCCESS' AND block_height <= ? AND (startsWith(log, ?) OR startsWith(log, ?)) AND {} ORDER BY block_height, receipt_index, log_index", chain_filter()))
+        .bind(to_block_height)
+        .bind(format!("@{} staking ", delegator_id))
+        .bind(format!("@{} unstaking ", delegator_id))
+        .fetch_all::<PoolLogRow>()
+        .await?;
+    let mut pool_logs: HashMap<String, Vec<PoolLogRow>> = HashMap::new();
+    for log in logs {
+        if pool_id.map_or(true, |pool_id| pool_id == log.account_id) {
+            pool_logs
+                .entry(log.account_id.clone())
+                .or_default()
+                .push(log);
+        }
+    }
+    let pool_ids: Vec<&String> = pool_logs.keys().collect();
+    let rewards = db
+        .client
+        .query(&format!("SELECT pool_id, epoch_height, block_height, block_timestamp, formatDateTime(block_timestamp, '%Y-%m') AS month, total_staked_balance, total_shares FROM pool_rewards FINAL WHERE has(?, pool_id) AND block_height <= ? AND {} ORDER BY pool_id, block_height", chain_filter()))
+        .bind(pool_ids)
+        .bind(to_block_height)
+        .fetch_all::<PoolRewardsRow>()
+        .await?;
+
+    let mut pool_shares: HashMap<String, (u128, Peekable<IntoIter<PoolLogRow>>)> = pool_logs
+        .into_iter()
+        .map(|(pool_id, logs)| (pool_id, (0, logs.into_iter().peekable())))
+        .collect();
+    let mut epochs = vec![];
+    for reward in rewards {
+        let Some((shares, logs)) = pool_shares.get_mut(&reward.pool_id) else {
+            continue;
+        };
+        while let Some(log) = logs.next_if(|log| log.block_height <= reward.block_height) {
+            if let Some(
+                StakingPoolLog::Staking { total_shares, .. }
+                | StakingPoolLog::Unstaking { total_shares, .. },
+            ) = StakingPoolLog::parse(&log.log)
+            {
+                *shares = total_shares;
+            }
+        }
+        epochs.push(DelegatorEpoch {
+            pool_id: reward.pool_id,
+            epoch_height: reward.epoch_height,
+            block_height: reward.block_height,
+            block_timestamp: reward.block_timestamp,
+            month: reward.month,
+            shares: *shares,
+            total_staked_balance: reward.total_staked_balance,
+            total_shares: reward.total_shares,
+        });
+    }
+    Ok(epochs)
+}
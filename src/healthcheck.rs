@@ -0,0 +1,113 @@
+use crate::click::*;
+use crate::transactions::is_memory_cache;
+use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+/// The checkpoint of a running pipeline is at most this old, since the blocks are produced every
+/// second.
+const DEFAULT_HEALTHCHECK_MAX_LAG_SECONDS: u64 = 600;
+
+#[derive(Serialize)]
+pub struct HealthCheck {
+    pub name: &'static str,
+    pub ok: bool,
+    pub message: String,
+}
+
+#[derive(Serialize)]
+pub struct HealthReport {
+    pub checks: Vec<HealthCheck>,
+}
+
+impl HealthReport {
+    pub fn is_ok(&self) -> bool {
+        self.checks.iter().all(|check| check.ok)
+    }
+}
+
+impl HealthCheck {
+    fn new(name: &'static str, result: anyhow::Result<String>) -> Self {
+        match result {
+            Ok(message) => Self {
+                name,
+                ok: true,
+                message,
+            },
+            Err(err) => Self {
+                name,
+                ok: false,
+                message: err.to_string(),
+            },
+        }
+    }
+}
+
+/// Checks the database connection, the transaction cache of the `transactions` pipeline and that
+/// the checkpoint of the pipeline is within `HEALTHCHECK_MAX_LAG_SECONDS`.
+pub async fn healthcheck(db: &ClickDB, pipeline: &str) -> HealthReport {
+    let mut checks = vec![HealthCheck::new(
+        "clickhouse",
+        db.verify_connection()
+            .await
+            .map(|_| "Connected".to_string())
+            .map_err(Into::into),
+    )];
+    if pipeline == "transactions" && !is_memory_cache() {
+        checks.push(HealthCheck::new("sled", check_sled()));
+    }
+    checks.push(HealthCheck::new("checkpoint", check_checkpoint(db, pipeline).await));
+    HealthReport { checks }
+}
+
+/// Only stats the cache directory, since opening the cache would race with the pipeline for the
+/// lock of sled, and would create an empty cache at a wrong path.
+fn check_sled() -> anyhow::Result<String> {
+    let sled_db_path = env::var("SLED_DB_PATH")?;
+    let metadata = std::fs::metadata(&sled_db_path)
+        .map_err(|err| anyhow::anyhow!("Failed to stat {}: {}", sled_db_path, err))?;
+    if !metadata.is_dir() {
+        anyhow::bail!("{} is not a directory", sled_db_path);
+    }
+    let mut last_modified = None;
+    for entry in std::fs::read_dir(&sled_db_path)? {
+        let modified = entry?.metadata()?.modified()?;
+        last_modified = last_modified.max(Some(modified));
+    }
+    let Some(last_modified) = last_modified else {
+        anyhow::bail!("{} is empty", sled_db_path);
+    };
+    let age = SystemTime::now()
+        .duration_since(last_modified)
+        .unwrap_or_default()
+        .as_secs();
+    Ok(format!("{} was written {} seconds ago", sled_db_path, age))
+}
+
+async fn check_checkpoint(db: &ClickDB, pipeline: &str) -> anyhow::Result<String> {
+    let (table, column) = match pipeline {
+        "actions" => ("actions", "block_timestamp"),
+        "transactions" => ("blocks", "block_timestamp"),
+        "blocks" => ("epochs", "end_block_timestamp"),
+        _ => anyhow::bail!("Unknown pipeline: {}", pipeline),
+    };
+    let max_lag = positive_env_var("HEALTHCHECK_MAX_LAG_SECONDS")
+        .unwrap_or(DEFAULT_HEALTHCHECK_MAX_LAG_SECONDS);
+    let block_timestamp = db
+        .client
+        .query(&format!(
+            "SELECT toUnixTimestamp(max({})) FROM {} WHERE {}",
+            column,
+            table,
+            chain_filter()
+        ))
+        .fetch_one::<u32>()
+        .await? as u64;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let lag = now.saturating_sub(block_timestamp);
+    if lag > max_lag {
+        anyhow::bail!("The last block of {} is {} seconds old", table, lag);
+    }
+    Ok(format!("The last block of {} is {} seconds old", table, lag))
+}
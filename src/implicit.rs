@@ -0,0 +1,69 @@
+use std::str::FromStr;
+
+use fastnear_primitives::near_primitives::types::AccountId;
+use serde_repr::{Deserialize_repr, Serialize_repr};
+
+const ED25519_PREFIX: &str = "ed25519:";
+const ETH_ADDRESS_PREFIX: &str = "0x";
+const ETH_ADDRESS_LENGTH: usize = 40;
+const NEAR_IMPLICIT_LENGTH: usize = 64;
+
+#[derive(Copy, Clone, Debug, Serialize_repr, Deserialize_repr, PartialEq)]
+#[repr(u8)]
+pub enum AccountKind {
+    Named = 1,
+    /// 64 lowercase hex characters of an ed25519 public key.
+    NearImplicit = 2,
+    /// `0x` with 40 lowercase hex characters of an Ethereum address.
+    EthImplicit = 3,
+}
+
+impl From<&AccountId> for AccountKind {
+    fn from(account_id: &AccountId) -> Self {
+        let account_id = account_id.as_str();
+        if account_id.len() == NEAR_IMPLICIT_LENGTH && is_lowercase_hex(account_id) {
+            AccountKind::NearImplicit
+        } else if account_id.len() == ETH_ADDRESS_PREFIX.len() + ETH_ADDRESS_LENGTH
+            && account_id
+                .strip_prefix(ETH_ADDRESS_PREFIX)
+                .is_some_and(is_lowercase_hex)
+        {
+            AccountKind::EthImplicit
+        } else {
+            AccountKind::Named
+        }
+    }
+}
+
+fn is_lowercase_hex(s: &str) -> bool {
+    s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// Parses an account ID, an `ed25519:` public key (as its NEAR implicit account) or an Ethereum
+/// address in any case (as its ETH implicit account).
+pub fn parse_account_or_key(s: &str) -> Result<AccountId, String> {
+    if let Some(key) = s.strip_prefix(ED25519_PREFIX) {
+        let key = bs58::decode(key)
+            .into_vec()
+            .map_err(|err| format!("Invalid public key {}: {}", s, err))?;
+        if key.len() != 32 {
+            return Err(format!("Invalid public key length {}", s));
+        }
+        let hex: String = key.iter().map(|b| format!("{:02x}", b)).collect();
+        return AccountId::from_str(&hex).map_err(|err| err.to_string());
+    }
+    parse_account_id(s)
+}
+
+/// Parses an account ID, accepting checksummed (mixed case) Ethereum addresses.
+pub fn parse_account_id(s: &str) -> Result<AccountId, String> {
+    let lowercase = s.to_ascii_lowercase();
+    if lowercase.len() == ETH_ADDRESS_PREFIX.len() + ETH_ADDRESS_LENGTH
+        && lowercase
+            .strip_prefix(ETH_ADDRESS_PREFIX)
+            .is_some_and(is_lowercase_hex)
+    {
+        return AccountId::from_str(&lowercase).map_err(|err| err.to_string());
+    }
+    AccountId::from_str(s).map_err(|err| err.to_string())
+}
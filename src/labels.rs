@@ -0,0 +1,56 @@
+use crate::click::*;
+use std::collections::HashMap;
+
+use clickhouse::Row;
+use serde::{Deserialize, Serialize};
+
+/// A human-readable label of an account, e.g. `Binance deposit` with the `exchange` category.
+#[derive(Row, Serialize, Deserialize, Clone, Debug)]
+pub struct AccountLabelRow {
+    pub account_id: String,
+    pub label: String,
+    pub category: String,
+}
+
+/// Reads a CSV seed file with `account_id,category,label` lines. The label is the last column, so
+/// it can contain commas. Empty lines and lines starting with `#` are skipped.
+pub fn read_seed_file(path: &str) -> anyhow::Result<Vec<AccountLabelRow>> {
+    let content = std::fs::read_to_string(path)?;
+    let mut rows = vec![];
+    for (line_index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut columns = line.splitn(3, ',').map(str::trim);
+        match (columns.next(), columns.next(), columns.next()) {
+            (Some(account_id), Some(category), Some(label)) if !account_id.is_empty() => {
+                rows.push(AccountLabelRow {
+                    account_id: account_id.to_string(),
+                    label: label.to_string(),
+                    category: category.to_string(),
+                });
+            }
+            _ => anyhow::bail!("Invalid line {}: {}", line_index + 1, line),
+        }
+    }
+    Ok(rows)
+}
+
+/// Loads all labels from the `account_labels` table by the account ID.
+pub async fn load_labels(
+    db: &ClickDB,
+) -> clickhouse::error::Result<HashMap<String, AccountLabelRow>> {
+    let rows = db
+        .client
+        .query(&format!(
+            "SELECT ?fields FROM account_labels FINAL WHERE {}",
+            chain_filter()
+        ))
+        .fetch_all::<AccountLabelRow>()
+        .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.account_id.clone(), row))
+        .collect())
+}
@@ -0,0 +1,141 @@
+use crate::click::*;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const LEADERBOARDS_TARGET: &str = "leaderboards";
+
+const DEFAULT_LEADERBOARD_SIZE: u64 = 100;
+
+/// The rolling windows of the leaderboards in days, ending today.
+const WINDOWS_DAYS: [u16; 3] = [1, 7, 30];
+
+/// A ranking of the top entries by a metric, computed from a summary table. The query is executed
+/// for every window and is bound with the window in days, the metric, the refresh timestamp, the
+/// window in days again and the leaderboard size. `{chain_filter}` is replaced by the condition of
+/// the configured chain.
+pub struct Leaderboard {
+    pub table: &'static str,
+    pub metric: &'static str,
+    pub query: &'static str,
+}
+
+/// Leaderboards over the `account_daily_stats` table of the `transactions` mode.
+pub const ACCOUNT_LEADERBOARDS: [Leaderboard; 2] = [
+    Leaderboard {
+        table: "account_leaderboards",
+        metric: "txs",
+        query: "INSERT INTO account_leaderboards
+            SELECT
+                ? AS window_days,
+                ? AS metric,
+                toUInt32(row_number() OVER (ORDER BY value DESC, account_id)) AS rank,
+                account_id,
+                value,
+                fromUnixTimestamp64Nano(toInt64(?)) AS refreshed_at
+            FROM (
+                SELECT account_id, toUInt128(sum(txs_signed + txs_received)) AS value
+                FROM account_daily_stats
+                WHERE date > today() - ? AND {chain_filter}
+                GROUP BY account_id
+                ORDER BY value DESC
+                LIMIT ?
+            )",
+    },
+    Leaderboard {
+        table: "account_leaderboards",
+        metric: "deposit",
+        query: "INSERT INTO account_leaderboards
+            SELECT
+                ? AS window_days,
+                ? AS metric,
+                toUInt32(row_number() OVER (ORDER BY value DESC, account_id)) AS rank,
+                account_id,
+                value,
+                fromUnixTimestamp64Nano(toInt64(?)) AS refreshed_at
+            FROM (
+                SELECT account_id, toUInt128(sum(deposit)) AS value
+                FROM account_daily_stats
+                WHERE date > today() - ? AND {chain_filter}
+                GROUP BY account_id
+                ORDER BY value DESC
+                LIMIT ?
+            )",
+    },
+];
+
+/// Leaderboards over the `pool_daily_stake_delta` rollup of the `actions` mode.
+pub const POOL_LEADERBOARDS: [Leaderboard; 1] = [Leaderboard {
+    table: "pool_leaderboards",
+    metric: "stake_inflow",
+    query: "INSERT INTO pool_leaderboards
+        SELECT
+            ? AS window_days,
+            ? AS metric,
+            toUInt32(row_number() OVER (ORDER BY value DESC, pool_id)) AS rank,
+            pool_id,
+            value,
+            fromUnixTimestamp64Nano(toInt64(?)) AS refreshed_at
+        FROM (
+            SELECT pool_id, toInt128(sum(staked)) - toInt128(sum(unstaked)) AS value
+            FROM pool_daily_stake_delta FINAL
+            WHERE date > today() - ? AND {chain_filter}
+            GROUP BY pool_id
+            ORDER BY value DESC
+            LIMIT ?
+        )",
+}];
+
+/// Periodically recomputes the leaderboards, so the dashboards don't scan the summary tables.
+pub struct Leaderboards {
+    pub interval: Duration,
+    pub size: u64,
+}
+
+impl Leaderboards {
+    /// Requires `LEADERBOARD_REFRESH_MINUTES`.
+    pub fn from_env() -> Option<Self> {
+        let refresh_minutes = positive_env_var("LEADERBOARD_REFRESH_MINUTES")?;
+        Some(Self {
+            interval: Duration::from_secs(refresh_minutes * 60),
+            size: positive_env_var("LEADERBOARD_SIZE").unwrap_or(DEFAULT_LEADERBOARD_SIZE),
+        })
+    }
+
+    /// Starts the refresh loop in the background. A failed refresh is retried on the next tick.
+    pub fn start(self, db: ClickDB, leaderboards: &'static [Leaderboard]) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.interval);
+            loop {
+                interval.tick().await;
+                if let Err(err) = self.refresh(&db, leaderboards).await {
+                    tracing::log::error!(target: LEADERBOARDS_TARGET, "Failed to refresh the leaderboards: {}", err);
+                }
+            }
+        });
+    }
+
+    pub async fn refresh(
+        &self,
+        db: &ClickDB,
+        leaderboards: &[Leaderboard],
+    ) -> clickhouse::error::Result<()> {
+        let refreshed_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64;
+        for leaderboard in leaderboards {
+            for window_days in WINDOWS_DAYS {
+                db.client
+                    .query(&leaderboard.query.replace("{chain_filter}", chain_filter()))
+                    .bind(window_days)
+                    .bind(leaderboard.metric)
+                    .bind(refreshed_at)
+                    .bind(window_days)
+                    .bind(self.size)
+                    .execute()
+                    .await?;
+            }
+            tracing::log::info!(target: LEADERBOARDS_TARGET, "Refreshed leaderboard {} by {}", leaderboard.table, leaderboard.metric);
+        }
+        Ok(())
+    }
+}
@@ -1,14 +1,63 @@
+mod abi;
+mod account_args;
 mod actions;
+mod alerts;
+mod args_decoders;
+mod blob_storage;
+mod blocks;
+mod chain;
+mod check;
 mod click;
 mod common;
+mod delegator_history;
+mod healthcheck;
+mod implicit;
+mod labels;
+mod leaderboards;
+mod metrics;
+mod pool_metadata;
+mod pool_migrations;
+mod pool_stake_history;
+mod prices;
+mod reconcile;
+mod reindex;
+mod repair;
+mod report;
+mod resequencer;
+mod rollups;
+mod rpc;
+mod shadow;
+mod spill;
+mod staking_pool_logs;
+mod staking_pools;
+mod stats;
+mod status;
+mod systemd;
 
 mod transactions;
+mod trim;
+mod tx_cache_snapshots;
+mod tx_wal;
 mod types;
+mod verify;
+mod watch_list;
+mod webhook_outbox;
 
+use crate::abi::AbiRegistry;
 use crate::actions::ActionsData;
+use crate::alerts::Alerts;
+use crate::blocks::BlocksData;
+use crate::chain::ChainContinuity;
 use crate::click::*;
-use crate::transactions::TransactionsData;
-use std::sync::Arc;
+use crate::leaderboards::{Leaderboards, ACCOUNT_LEADERBOARDS, POOL_LEADERBOARDS};
+use crate::resequencer::Resequencer;
+use crate::staking_pools::StakingPools;
+use crate::status::StatusFile;
+use crate::systemd::Systemd;
+use crate::transactions::{is_memory_cache, TransactionsData, TxCache};
+use crate::tx_cache_snapshots::TxCacheSnapshots;
+use crate::watch_list::WatchList;
+use std::sync::{Arc, OnceLock};
 
 use dotenv::dotenv;
 use fastnear_neardata_fetcher::fetcher;
@@ -19,7 +68,33 @@ use tokio::sync::mpsc;
 
 const PROJECT_ID: &str = "provider";
 
-const SAFE_CATCH_UP_OFFSET: u64 = 1000;
+const DEFAULT_SAFE_CATCH_UP_OFFSET: u64 = 1000;
+
+/// Blocks replayed to warm up the pipeline state, configured with `SAFE_CATCH_UP_OFFSET` env var.
+fn safe_catch_up_offset() -> u64 {
+    static SAFE_CATCH_UP_OFFSET: OnceLock<u64> = OnceLock::new();
+    *SAFE_CATCH_UP_OFFSET.get_or_init(|| {
+        positive_env_var("SAFE_CATCH_UP_OFFSET").unwrap_or(DEFAULT_SAFE_CATCH_UP_OFFSET)
+    })
+}
+
+/// Loads the deployed code hashes for the ABI registry, if `CONTRACT_ABI_DIR` is set, for the
+/// pipelines that decode the args. Without the `actions` table, only the deployments seen by the
+/// pipeline are tracked.
+async fn load_abi_code_hashes(db: &ClickDB) {
+    let Some(abi_registry) = AbiRegistry::get() else {
+        return;
+    };
+    let tables = db.tables().await.expect("Failed to list the tables");
+    if !tables.iter().any(|table| table == "actions") {
+        tracing::log::warn!(target: PROJECT_ID, "No actions table to load the contract code hashes from");
+        return;
+    }
+    abi_registry
+        .load_code_hashes(db)
+        .await
+        .expect("Failed to load the contract code hashes");
+}
 
 #[tokio::main]
 async fn main() {
@@ -31,22 +106,177 @@ async fn main() {
 
     ctrlc::set_handler(move || {
         ctrl_c_running.store(false, Ordering::SeqCst);
-        println!("Received Ctrl+C, starting shutdown...");
+        println!("Received Ctrl+C or SIGTERM, starting shutdown...");
+        if let Some(systemd) = Systemd::get() {
+            systemd.notify_stopping();
+        }
     })
     .expect("Error setting Ctrl+C handler");
 
-    common::setup_tracing("clickhouse=info,provider=info,neardata-fetcher=info");
+    common::setup_tracing(
+        "clickhouse=info,provider=info,neardata-fetcher=info,blob_storage=info,prices=info,alerts=info,pool_metadata=info,pool_stake_history=info,tx_wal=info,metrics=info,resequencer=info,chain=info,shadow=info,tx_cache_snapshots=info,leaderboards=info,abi=info,verify=info,status=info,systemd=info",
+    );
 
-    tracing::log::info!(target: PROJECT_ID, "Starting Clickhouse Provider");
+    let args: Vec<String> = std::env::args().collect();
+    let command = args
+        .get(1)
+        .map(|arg| arg.as_str())
+        .expect("You need to provide a command");
 
     let db = ClickDB::new(10000);
+
+    // The probes run next to the pipeline, so they don't start the metrics server or the OTLP
+    // export. Checked before connecting, so an unreachable database is reported instead of
+    // panicking.
+    if command == "healthcheck" {
+        let pipeline = args.get(2).expect("You need to provide a pipeline");
+        let report = healthcheck::healthcheck(&db, pipeline).await;
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+        if !report.is_ok() {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if command == "stats" {
+        let stats = stats::stats(&db).await.expect("Failed to collect stats");
+        println!("{}", serde_json::to_string_pretty(&stats).unwrap());
+        return;
+    }
+
+    if command == "status" {
+        let path = std::env::var("STATUS_FILE").expect("Missing STATUS_FILE env var");
+        let chain_id_name = std::env::var("CHAIN_ID").expect("CHAIN_ID is not set");
+        let report = status::status(std::path::Path::new(&path), &chain_id_name)
+            .await
+            .expect("Failed to read the status");
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+        return;
+    }
+
+    metrics::start_server();
+    #[cfg(feature = "otel")]
+    metrics::start_otlp_export();
+
+    tracing::log::info!(target: PROJECT_ID, "Starting Clickhouse Provider");
+
     db.verify_connection()
         .await
         .expect("Failed to connect to Clickhouse");
 
+    if is_multi_chain() {
+        let tables = db
+            .tables_without_chain_sort_key()
+            .await
+            .expect("Failed to check the sort keys");
+        assert!(
+            tables.is_empty(),
+            "MULTI_CHAIN=true requires chain_id first in the sort key of {}",
+            tables.join(", ")
+        );
+    }
+
+    if command == "labels" {
+        let path = args.get(2).expect("You need to provide a seed file path");
+        let rows = labels::read_seed_file(path).expect("Failed to read seed file");
+        insert_rows_with_retry(&db.client, &rows, "account_labels")
+            .await
+            .expect("Failed to insert labels");
+        tracing::log::info!(target: PROJECT_ID, "Inserted {} account labels", rows.len());
+        return;
+    }
+
+    if command == "reconcile" {
+        let pool_id = args.get(2).expect("You need to provide a pool ID");
+        let delegator_id = args.get(3).expect("You need to provide a delegator ID");
+        let block_height = |index: usize| -> u64 {
+            args.get(index)
+                .expect("You need to provide a block range")
+                .parse()
+                .expect("Failed to parse block height")
+        };
+        let rpc = rpc::RpcClient::from_env().expect("Missing RPC_URL env var");
+        let rows = reconcile::reconcile(
+            &db,
+            &rpc,
+            pool_id,
+            delegator_id,
+            block_height(4),
+            block_height(5),
+        )
+        .await
+        .expect("Failed to reconcile");
+        println!("{}", serde_json::to_string_pretty(&rows).unwrap());
+        return;
+    }
+
+    if command == "report" {
+        let delegator_id = args.get(2).expect("You need to provide a delegator ID");
+        let block_height = |index: usize| -> u64 {
+            args.get(index)
+                .expect("You need to provide a block range")
+                .parse()
+                .expect("Failed to parse block height")
+        };
+        let report = report::build_report(&db, delegator_id, block_height(3), block_height(4))
+            .await
+            .expect("Failed to build report");
+        match args.get(5).map(|arg| arg.as_str()) {
+            Some("csv") => print!("{}", report::to_csv(&report.epochs)),
+            Some("csv-monthly") => print!("{}", report::to_csv(&report.months)),
+            Some("json") | None => println!("{}", serde_json::to_string_pretty(&report).unwrap()),
+            Some(format) => panic!("Unknown report format: {}", format),
+        }
+        return;
+    }
+
+    if command == "check" {
+        let block_height = |index: usize| -> u64 {
+            args.get(index)
+                .expect("You need to provide a block range")
+                .parse()
+                .expect("Failed to parse block height")
+        };
+        let report = check::check(&db, block_height(2), block_height(3))
+            .await
+            .expect("Failed to check");
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+        if !report.is_ok() {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if command == "transaction" {
+        let tx_hash = args.get(2).expect("You need to provide a transaction hash");
+        let transaction = transactions::fetch_transaction(&db, tx_hash)
+            .await
+            .expect("Failed to fetch transaction");
+        println!("{}", serde_json::to_string_pretty(&transaction).unwrap());
+        return;
+    }
+
+    if command == "cache" {
+        let path = std::path::Path::new(args.get(3).expect("You need to provide an archive path"));
+        let sled_db_path = std::env::var("SLED_DB_PATH").expect("Missing SLED_DB_PATH env var");
+        std::fs::create_dir_all(&sled_db_path).expect("Failed to create SLED_DB_PATH");
+        let sled_db = sled::open(&sled_db_path).expect("Failed to open sled_db_path");
+        let tx_cache = match args.get(2).map(|arg| arg.as_str()) {
+            Some("export") => {
+                let tx_cache = TxCache::new(sled_db);
+                tx_cache.export(path).expect("Failed to export the cache");
+                tx_cache
+            }
+            Some("import") => TxCache::import(sled_db, path).expect("Failed to import the cache"),
+            _ => panic!("You need to provide a cache command: export or import"),
+        };
+        tracing::log::info!(target: PROJECT_ID, "Cache at block #{}: {}", tx_cache.last_block_height, tx_cache.stats());
+        return;
+    }
+
     let client = reqwest::Client::new();
-    let chain_id = ChainId::try_from(std::env::var("CHAIN_ID").expect("CHAIN_ID is not set"))
-        .expect("Invalid chain id");
+    let chain_id_name = std::env::var("CHAIN_ID").expect("CHAIN_ID is not set");
+    let chain_id = ChainId::try_from(chain_id_name.clone()).expect("Invalid chain id");
     let num_threads = std::env::var("NUM_FETCHING_THREADS")
         .expect("NUM_FETCHING_THREADS is not set")
         .parse::<u64>()
@@ -61,18 +291,68 @@ async fn main() {
 
     tracing::log::info!(target: PROJECT_ID, "First block: {}", first_block_height);
 
-    let args: Vec<String> = std::env::args().collect();
-    let command = args
-        .get(1)
-        .map(|arg| arg.as_str())
-        .expect("You need to provide a command");
+    if command == "reindex" || command == "repair" || command == "verify" {
+        let pipeline = args.get(2).expect("You need to provide a pipeline");
+        let block_height = |index: usize| -> u64 {
+            args.get(index)
+                .expect("You need to provide a block range")
+                .parse()
+                .expect("Failed to parse block height")
+        };
+        let config = reindex::ReindexConfig {
+            chain_id,
+            chain_id_name,
+            num_threads,
+            from_block_height: first_block_height.max(block_height(3)),
+            to_block_height: block_height(4),
+        };
+        if pipeline == "actions" || pipeline == "transactions" {
+            load_abi_code_hashes(&db).await;
+        }
+        if command == "repair" {
+            repair::delete_range(&db, pipeline, config.from_block_height, config.to_block_height)
+                .await
+                .expect("Failed to delete the block range");
+        }
+        let verifier = if command == "verify" {
+            let verifier = verify::Verifier::start(
+                &db,
+                pipeline,
+                config.from_block_height,
+                config.to_block_height,
+            )
+            .await
+            .expect("Failed to start the verifier");
+            Some(verifier)
+        } else {
+            None
+        };
+        reindex::reindex(db.clone(), pipeline, config, is_running).await;
+        if let Some(verifier) = verifier {
+            let report = verifier.report(&db).await.expect("Failed to verify");
+            println!("{}", serde_json::to_string_pretty(&report).unwrap());
+            common::shutdown_tracing();
+            if !report.is_ok() {
+                std::process::exit(1);
+            }
+            return;
+        }
+        tracing::log::info!(target: PROJECT_ID, "Reindexed {} blocks", pipeline);
+        common::shutdown_tracing();
+        return;
+    }
+
     let backfill_block_height = args
         .get(2)
         .map(|v| v.parse().expect("Failed to parse backfill block height"));
 
     match command {
         "actions" => {
+            load_abi_code_hashes(&db).await;
             let mut actions_data = ActionsData::new();
+            if let Some(leaderboards) = Leaderboards::from_env() {
+                leaderboards.start(db.clone(), &POOL_LEADERBOARDS);
+            }
             let db_last_block_height = actions_data.last_block_height(&db).await;
             let last_block_height = backfill_block_height.unwrap_or(db_last_block_height);
             let start_block_height = first_block_height.max(last_block_height + 1);
@@ -88,10 +368,45 @@ async fn main() {
                 sender,
                 is_running,
             ));
-            listen_blocks_for_actions(receiver, db, actions_data, last_block_height).await;
+            let receiver = Resequencer::new(&chain_id_name, start_block_height).spawn(receiver);
+            let status_file = StatusFile::from_env("actions");
+            listen_blocks_for_actions(receiver, db, actions_data, last_block_height, status_file)
+                .await;
         }
         "transactions" => {
+            let tx_cache_snapshots = TxCacheSnapshots::from_env().filter(|_| !is_memory_cache());
+            let restored_block_height = match tx_cache_snapshots {
+                Some(tx_cache_snapshots) => {
+                    let sled_db_path =
+                        std::env::var("SLED_DB_PATH").expect("Missing SLED_DB_PATH env var");
+                    tx_cache_snapshots
+                        .restore_if_empty(&sled_db_path)
+                        .await
+                        .expect("Failed to restore the cache snapshot")
+                }
+                None => None,
+            };
             let mut transactions_data = TransactionsData::new();
+            if transactions_data.needs_watch_list() {
+                transactions_data.watch_list = WatchList::load(&db)
+                    .await
+                    .expect("Failed to load watch list");
+            }
+            if transactions_data.store_account_daily_stats {
+                transactions_data.staking_pools = StakingPools::load(&db)
+                    .await
+                    .expect("Failed to load staking pools");
+            }
+            if transactions_data.store_tx_actions {
+                load_abi_code_hashes(&db).await;
+            }
+            if std::env::var("ENABLE_ALERTS") == Ok("true".to_string()) {
+                transactions_data.alerts =
+                    Some(Alerts::load(&db).await.expect("Failed to load alert rules"));
+            }
+            if let Some(leaderboards) = Leaderboards::from_env() {
+                leaderboards.start(db.clone(), &ACCOUNT_LEADERBOARDS);
+            }
             let db_last_block_height = transactions_data.last_block_height(&db).await;
             let last_block_height = backfill_block_height.unwrap_or(db_last_block_height);
             let is_cache_ready = transactions_data.is_cache_ready(last_block_height);
@@ -99,8 +414,13 @@ async fn main() {
 
             let start_block_height = if is_cache_ready {
                 last_block_height + 1
+            } else if let Some(restored_block_height) = restored_block_height {
+                // All rows up to the snapshot are committed, so the blocks after it are replayed.
+                restored_block_height + 1
             } else {
-                last_block_height.saturating_sub(SAFE_CATCH_UP_OFFSET)
+                transactions_data
+                    .catch_up_start_block_height(&db, last_block_height)
+                    .await
             };
 
             let start_block_height = first_block_height.max(start_block_height);
@@ -116,7 +436,41 @@ async fn main() {
                 sender,
                 is_running,
             ));
-            listen_blocks_for_transactions(receiver, db, transactions_data, last_block_height)
+            let receiver = Resequencer::new(&chain_id_name, start_block_height).spawn(receiver);
+            let status_file = StatusFile::from_env("transactions");
+            listen_blocks_for_transactions(
+                receiver,
+                db,
+                transactions_data,
+                last_block_height,
+                status_file,
+            )
+            .await;
+        }
+        "blocks" => {
+            let watch_list = WatchList::load(&db)
+                .await
+                .expect("Failed to load watch list");
+            let mut blocks_data = BlocksData::new(watch_list);
+            let db_last_block_height = blocks_data.last_block_height(&db).await;
+            let last_block_height = backfill_block_height.unwrap_or(db_last_block_height);
+            blocks_data.restore_last_epoch(&db, last_block_height).await;
+            let start_block_height = first_block_height.max(last_block_height + 1);
+            let (sender, receiver) = mpsc::channel(100);
+            let config = fetcher::FetcherConfig {
+                num_threads,
+                start_block_height,
+                chain_id,
+            };
+            tokio::spawn(fetcher::start_fetcher(
+                Some(client),
+                config,
+                sender,
+                is_running,
+            ));
+            let receiver = Resequencer::new(&chain_id_name, start_block_height).spawn(receiver);
+            let status_file = StatusFile::from_env("blocks");
+            listen_blocks_for_blocks(receiver, db, blocks_data, last_block_height, status_file)
                 .await;
         }
         _ => {
@@ -125,6 +479,7 @@ async fn main() {
     };
 
     tracing::log::info!(target: PROJECT_ID, "Gracefully shut down");
+    common::shutdown_tracing();
 }
 
 async fn listen_blocks_for_actions(
@@ -132,36 +487,113 @@ async fn listen_blocks_for_actions(
     mut db: ClickDB,
     mut actions_data: ActionsData,
     last_block_height: u64,
+    mut status_file: Option<StatusFile>,
 ) {
+    let mut chain_continuity = ChainContinuity::new("actions");
+    if let Some(systemd) = Systemd::get() {
+        systemd.notify_ready();
+    }
     while let Some(block) = stream.recv().await {
         let block_height = block.block.header.height;
+        let block_timestamp = block.block.header.timestamp;
         tracing::log::info!(target: PROJECT_ID, "Processing block: {}", block_height);
+        chain_continuity.check(&db, &block).await.unwrap();
         actions_data
             .process_block(&mut db, block, last_block_height)
             .await
             .unwrap();
+        if let Some(systemd) = Systemd::get() {
+            systemd.notify_block(block_height);
+        }
+        if let Some(status_file) = &mut status_file {
+            status_file.update(
+                block_height,
+                block_timestamp,
+                actions_data.rows.actions.len(),
+                stream.len(),
+                stream.max_capacity(),
+            );
+        }
     }
     tracing::log::info!(target: PROJECT_ID, "Committing the last batch");
-    actions_data.commit(&mut db).await.unwrap();
+    actions_data.commit(&db).await.unwrap();
     actions_data.flush().await.unwrap();
 }
 
+async fn listen_blocks_for_blocks(
+    mut stream: mpsc::Receiver<BlockWithTxHashes>,
+    db: ClickDB,
+    mut blocks_data: BlocksData,
+    last_block_height: u64,
+    mut status_file: Option<StatusFile>,
+) {
+    let mut chain_continuity = ChainContinuity::new("blocks");
+    if let Some(systemd) = Systemd::get() {
+        systemd.notify_ready();
+    }
+    while let Some(block) = stream.recv().await {
+        let block_height = block.block.header.height;
+        let block_timestamp = block.block.header.timestamp;
+        tracing::log::info!(target: PROJECT_ID, "Processing block: {}", block_height);
+        chain_continuity.check(&db, &block).await.unwrap();
+        blocks_data
+            .process_block(&db, block, last_block_height)
+            .await
+            .unwrap();
+        if let Some(systemd) = Systemd::get() {
+            systemd.notify_block(block_height);
+        }
+        if let Some(status_file) = &mut status_file {
+            status_file.update(
+                block_height,
+                block_timestamp,
+                blocks_data.rows.blocks.len(),
+                stream.len(),
+                stream.max_capacity(),
+            );
+        }
+    }
+    tracing::log::info!(target: PROJECT_ID, "Committing the last batch");
+    blocks_data.commit(&db).await.unwrap();
+    blocks_data.flush().await.unwrap();
+}
+
 async fn listen_blocks_for_transactions(
     mut stream: mpsc::Receiver<BlockWithTxHashes>,
     db: ClickDB,
     mut transactions_data: TransactionsData,
     last_block_height: u64,
+    mut status_file: Option<StatusFile>,
 ) {
+    let mut chain_continuity = ChainContinuity::new("transactions");
+    if let Some(systemd) = Systemd::get() {
+        systemd.notify_ready();
+    }
     let mut prev_block_hash = None;
     while let Some(block) = stream.recv().await {
         let block_height = block.block.header.height;
+        let block_timestamp = block.block.header.timestamp;
         tracing::log::info!(target: PROJECT_ID, "Processing block: {}", block_height);
+        chain_continuity.check(&db, &block).await.unwrap();
         prev_block_hash = Some(
             transactions_data
                 .process_block(&db, block, last_block_height, prev_block_hash)
                 .await
                 .unwrap(),
         );
+        if let Some(systemd) = Systemd::get() {
+            systemd.notify_block(block_height);
+        }
+        if let Some(status_file) = &mut status_file {
+            status_file.update(
+                block_height,
+                block_timestamp,
+                transactions_data.rows.transactions.len()
+                    + transactions_data.rows.failed_transactions.len(),
+                stream.len(),
+                stream.max_capacity(),
+            );
+        }
     }
     tracing::log::info!(target: PROJECT_ID, "Committing the last batch");
     transactions_data.commit(&db).await.unwrap();
@@ -0,0 +1,313 @@
+use std::env;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use prometheus::{
+    exponential_buckets, register_histogram, register_histogram_vec, register_int_counter,
+    register_int_counter_vec, register_int_gauge, register_int_gauge_vec, Encoder, Histogram,
+    HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+pub const METRICS_TARGET: &str = "metrics";
+
+pub struct Metrics {
+    /// Number of entries in the in-memory maps of the transaction cache by the `cache` label.
+    pub tx_cache_size: IntGaugeVec,
+    pub sled_disk_size_bytes: IntGauge,
+    pub insert_latency_seconds: HistogramVec,
+    pub insert_batch_size: HistogramVec,
+    pub block_processing_seconds: Histogram,
+    /// Blocks over `SLOW_BLOCK_THRESHOLD_MS`.
+    pub slow_blocks: IntCounter,
+    /// The effective number of rows to commit.
+    pub min_batch: IntGauge,
+    /// Pending transactions evicted after `STALE_TRANSACTION_MAX_AGE_BLOCKS`.
+    pub stale_transactions: IntCounter,
+    /// Late receipts of the evicted transactions that were skipped.
+    pub evicted_receipts: IntCounter,
+    /// Orphaned entries removed from the transaction cache by the `cache` label.
+    pub tx_cache_orphans: IntCounterVec,
+    /// Alerts over `MAX_ALERT_DELIVERIES` that were not delivered.
+    pub dropped_alerts: IntCounter,
+    /// Batches over the pending limit of the shadow database that were not written.
+    pub dropped_shadow_batches: IntCounter,
+}
+
+impl Metrics {
+    pub fn get() -> &'static Self {
+        static METRICS: OnceLock<Metrics> = OnceLock::new();
+        METRICS.get_or_init(Self::new)
+    }
+
+    fn new() -> Self {
+        Self {
+            tx_cache_size: register_int_gauge_vec!(
+                "tx_cache_size",
+                "Number of entries in the transaction cache",
+                &["cache"]
+            )
+            .unwrap(),
+            sled_disk_size_bytes: register_int_gauge!(
+                "sled_disk_size_bytes",
+                "Disk size of the sled database"
+            )
+            .unwrap(),
+            insert_latency_seconds: register_histogram_vec!(
+                "insert_latency_seconds",
+                "Duration of the inserts including the retries",
+                &["table"],
+                exponential_buckets(0.01, 2.0, 14).unwrap()
+            )
+            .unwrap(),
+            insert_batch_size: register_histogram_vec!(
+                "insert_batch_size",
+                "Number of rows in the inserts",
+                &["table"],
+                exponential_buckets(1.0, 4.0, 10).unwrap()
+            )
+            .unwrap(),
+            block_processing_seconds: register_histogram!(
+                "block_processing_seconds",
+                "Duration of the block processing including the commit",
+                exponential_buckets(0.001, 2.0, 16).unwrap()
+            )
+            .unwrap(),
+            slow_blocks: register_int_counter!(
+                "slow_blocks_total",
+                "Number of blocks over the slow block threshold"
+            )
+            .unwrap(),
+            min_batch: register_int_gauge!("min_batch", "Effective number of rows to commit")
+                .unwrap(),
+            stale_transactions: register_int_counter!(
+                "stale_transactions_total",
+                "Number of pending transactions evicted from the transaction cache"
+            )
+            .unwrap(),
+            evicted_receipts: register_int_counter!(
+                "evicted_receipts_total",
+                "Number of late receipts of the evicted transactions that were skipped"
+            )
+            .unwrap(),
+            tx_cache_orphans: register_int_counter_vec!(
+                "tx_cache_orphans_total",
+                "Number of orphaned entries removed from the transaction cache",
+                &["cache"]
+            )
+            .unwrap(),
+            dropped_alerts: register_int_counter!(
+                "dropped_alerts_total",
+                "Number of alerts dropped over the delivery limit"
+            )
+            .unwrap(),
+            dropped_shadow_batches: register_int_counter!(
+                "dropped_shadow_batches_total",
+                "Number of batches dropped over the pending limit of the shadow database"
+            )
+            .unwrap(),
+        }
+    }
+
+    pub fn observe_insert(&self, table: &str, num_rows: usize, duration: Duration) {
+        self.insert_latency_seconds
+            .with_label_values(&[table])
+            .observe(duration.as_secs_f64());
+        self.insert_batch_size
+            .with_label_values(&[table])
+            .observe(num_rows as f64);
+    }
+
+    pub fn set_sled_disk_size(&self, path: &str) {
+        match dir_size(Path::new(path)) {
+            Ok(size) => self.sled_disk_size_bytes.set(size as i64),
+            Err(err) => {
+                tracing::log::warn!(target: METRICS_TARGET, "Failed to get the size of {}: {}", path, err);
+            }
+        }
+    }
+}
+
+pub fn dir_size(path: &Path) -> std::io::Result<u64> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        size += if metadata.is_dir() {
+            dir_size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+    Ok(size)
+}
+
+/// Serves the metrics in the Prometheus text format on `METRICS_ADDR` (e.g. `0.0.0.0:9090`), if
+/// set. Every request gets the metrics regardless of the path.
+pub fn start_server() {
+    let Ok(addr) = env::var("METRICS_ADDR") else {
+        return;
+    };
+    tokio::spawn(async move {
+        let listener = TcpListener::bind(&addr)
+            .await
+            .expect("Failed to bind METRICS_ADDR");
+        tracing::log::info!(target: METRICS_TARGET, "Serving metrics on {}", addr);
+        loop {
+            let mut stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    tracing::log::warn!(target: METRICS_TARGET, "Failed to accept: {}", err);
+                    continue;
+                }
+            };
+            tokio::spawn(async move {
+                let mut request = [0u8; 1024];
+                let _ = stream.read(&mut request).await;
+                let mut body = vec![];
+                let encoder = TextEncoder::new();
+                encoder.encode(&prometheus::gather(), &mut body).unwrap();
+                let header = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    encoder.format_type(),
+                    body.len()
+                );
+                let _ = stream.write_all(header.as_bytes()).await;
+                let _ = stream.write_all(&body).await;
+            });
+        }
+    });
+}
+
+#[cfg(feature = "otel")]
+static OTLP_METER_PROVIDER: OnceLock<opentelemetry_sdk::metrics::SdkMeterProvider> = OnceLock::new();
+
+/// Exports the metrics with OTLP every `OTEL_METRIC_EXPORT_INTERVAL` milliseconds (defaults to
+/// 60000), if `OTEL_EXPORTER_OTLP_ENDPOINT` is set. The counters and the gauges keep their names
+/// and labels, and the histograms are exported as the `_sum` and `_count` counters.
+#[cfg(feature = "otel")]
+pub fn start_otlp_export() {
+    use opentelemetry::metrics::MeterProvider;
+    use opentelemetry_otlp::WithExportConfig;
+    use prometheus::proto::MetricType;
+
+    if env::var("OTEL_EXPORTER_OTLP_ENDPOINT").is_err() {
+        return;
+    }
+    let builder = opentelemetry_otlp::MetricExporter::builder();
+    let exporter = match crate::common::otlp_http_protocol() {
+        Some(protocol) => builder.with_http().with_protocol(protocol).build(),
+        None => builder.with_tonic().build(),
+    }
+    .expect("Failed to build the OTLP metric exporter");
+    let reader = opentelemetry_sdk::metrics::PeriodicReader::builder(
+        exporter,
+        opentelemetry_sdk::runtime::Tokio,
+    )
+    .build();
+    let provider = opentelemetry_sdk::metrics::SdkMeterProvider::builder()
+        .with_reader(reader)
+        .build();
+    let meter = provider.meter(env!("CARGO_PKG_NAME"));
+    // Registers all metrics, so the families are known before the first observation.
+    Metrics::get();
+    for family in prometheus::gather() {
+        let name = family.get_name().to_string();
+        let help = family.get_help().to_string();
+        match family.get_field_type() {
+            MetricType::COUNTER => {
+                meter
+                    .f64_observable_counter(name.clone())
+                    .with_description(help)
+                    .with_callback(move |observer| {
+                        observe_family(
+                            &name,
+                            |metric| metric.get_counter().get_value(),
+                            |value, attributes| observer.observe(value, attributes),
+                        )
+                    })
+                    .build();
+            }
+            MetricType::GAUGE => {
+                meter
+                    .f64_observable_gauge(name.clone())
+                    .with_description(help)
+                    .with_callback(move |observer| {
+                        observe_family(
+                            &name,
+                            |metric| metric.get_gauge().get_value(),
+                            |value, attributes| observer.observe(value, attributes),
+                        )
+                    })
+                    .build();
+            }
+            MetricType::HISTOGRAM => {
+                let sum_name = name.clone();
+                meter
+                    .f64_observable_counter(format!("{}_sum", name))
+                    .with_description(help.clone())
+                    .with_callback(move |observer| {
+                        observe_family(
+                            &sum_name,
+                            |metric| metric.get_histogram().get_sample_sum(),
+                            |value, attributes| observer.observe(value, attributes),
+                        )
+                    })
+                    .build();
+                meter
+                    .f64_observable_counter(format!("{}_count", name))
+                    .with_description(help)
+                    .with_callback(move |observer| {
+                        observe_family(
+                            &name,
+                            |metric| metric.get_histogram().get_sample_count() as f64,
+                            |value, attributes| observer.observe(value, attributes),
+                        )
+                    })
+                    .build();
+            }
+            _ => {}
+        }
+    }
+    tracing::log::info!(target: METRICS_TARGET, "Exporting metrics with OTLP");
+    let _ = OTLP_METER_PROVIDER.set(provider);
+}
+
+/// Calls `observe` with the value of every metric of the family with its labels.
+#[cfg(feature = "otel")]
+fn observe_family(
+    name: &str,
+    value: impl Fn(&prometheus::proto::Metric) -> f64,
+    observe: impl Fn(f64, &[opentelemetry::KeyValue]),
+) {
+    for family in prometheus::gather()
+        .iter()
+        .filter(|family| family.get_name() == name)
+    {
+        for metric in family.get_metric() {
+            let attributes: Vec<_> = metric
+                .get_label()
+                .iter()
+                .map(|label| {
+                    opentelemetry::KeyValue::new(
+                        label.get_name().to_string(),
+                        label.get_value().to_string(),
+                    )
+                })
+                .collect();
+            observe(value(metric), &attributes);
+        }
+    }
+}
+
+/// Exports the remaining metrics before the exit.
+#[cfg(feature = "otel")]
+pub fn shutdown_otlp_export() {
+    if let Some(provider) = OTLP_METER_PROVIDER.get() {
+        if let Err(err) = provider.shutdown() {
+            tracing::log::warn!(target: METRICS_TARGET, "Failed to export the metrics: {}", err);
+        }
+    }
+}
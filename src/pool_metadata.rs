@@ -0,0 +1,126 @@
+use crate::actions::{FullActionRow, ReceiptStatus};
+use crate::rpc::RpcClient;
+use std::collections::HashMap;
+use std::env;
+
+use clickhouse::Row;
+use serde::{Deserialize, Serialize};
+
+pub const POOL_METADATA_TARGET: &str = "pool_metadata";
+const DEFAULT_POOL_DETAILS_CONTRACT_ID: &str = "pool-details.near";
+const DEFAULT_SYNC_EVERY_BLOCKS: u64 = 43200;
+const PAGE_LIMIT: u64 = 100;
+
+/// Validator metadata from the pool details contract. The latest row by `block_height` wins.
+#[derive(Row, Serialize, Deserialize)]
+pub struct PoolMetadataRow {
+    pub pool_id: String,
+    pub block_height: u64,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub url: Option<String>,
+    pub logo: Option<String>,
+    pub country: Option<String>,
+    /// All fields as a JSON object.
+    pub fields: String,
+}
+
+impl PoolMetadataRow {
+    fn new(pool_id: String, block_height: u64, mut fields: HashMap<String, String>) -> Self {
+        let json = serde_json::to_string(&fields).unwrap();
+        Self {
+            pool_id,
+            block_height,
+            name: fields.remove("name"),
+            description: fields.remove("description"),
+            url: fields.remove("url"),
+            logo: fields.remove("logo"),
+            country: fields.remove("country"),
+            fields: json,
+        }
+    }
+}
+
+/// Reads validator metadata from the pool details contract with RPC view calls on `RPC_URL`.
+/// All pools are synced every `POOL_METADATA_SYNC_EVERY_BLOCKS` blocks, and a single pool is
+/// refreshed when its `update_field` call is indexed.
+pub struct PoolMetadataSync {
+    pub rpc: RpcClient,
+    pub contract_id: String,
+    pub sync_every_blocks: u64,
+    pub last_sync_block_height: Option<u64>,
+}
+
+impl PoolMetadataSync {
+    pub fn from_env() -> Option<Self> {
+        if env::var("SYNC_POOL_METADATA") != Ok("true".to_string()) {
+            return None;
+        }
+        Some(Self {
+            rpc: RpcClient::from_env().expect("Missing RPC_URL env var"),
+            contract_id: env::var("POOL_DETAILS_CONTRACT_ID")
+                .unwrap_or(DEFAULT_POOL_DETAILS_CONTRACT_ID.to_string()),
+            sync_every_blocks: env::var("POOL_METADATA_SYNC_EVERY_BLOCKS")
+                .map(|v| v.parse().expect("Invalid POOL_METADATA_SYNC_EVERY_BLOCKS"))
+                .unwrap_or(DEFAULT_SYNC_EVERY_BLOCKS),
+            last_sync_block_height: None,
+        })
+    }
+
+    pub fn is_update_call(&self, action: &FullActionRow) -> bool {
+        action.account_id == self.contract_id
+            && action.status == ReceiptStatus::Success
+            && action.method_name.as_deref() == Some("update_field")
+    }
+
+    pub fn is_sync_needed(&self, block_height: u64) -> bool {
+        self.last_sync_block_height
+            .map_or(true, |last| block_height >= last + self.sync_every_blocks)
+    }
+
+    /// Returns the metadata of all pools.
+    pub async fn sync_all(&mut self, block_height: u64) -> anyhow::Result<Vec<PoolMetadataRow>> {
+        // Not retrying failed syncs until the next interval.
+        self.last_sync_block_height = Some(block_height);
+        let mut rows = vec![];
+        let mut from_index = 0;
+        loop {
+            let page: HashMap<String, HashMap<String, String>> = self
+                .rpc
+                .view_call(
+                    &self.contract_id,
+                    "get_all_fields",
+                    serde_json::json!({"from_index": from_index, "limit": PAGE_LIMIT}),
+                )
+                .await?;
+            let page_len = page.len() as u64;
+            rows.extend(
+                page.into_iter()
+                    .map(|(pool_id, fields)| PoolMetadataRow::new(pool_id, block_height, fields)),
+            );
+            if page_len < PAGE_LIMIT {
+                break;
+            }
+            from_index += PAGE_LIMIT;
+        }
+        tracing::log::info!(target: POOL_METADATA_TARGET, "Synced metadata of {} pools", rows.len());
+        Ok(rows)
+    }
+
+    /// Returns the metadata of a single pool, if the pool has any fields.
+    pub async fn sync_pool(
+        &self,
+        pool_id: &str,
+        block_height: u64,
+    ) -> anyhow::Result<Option<PoolMetadataRow>> {
+        let fields: Option<HashMap<String, String>> = self
+            .rpc
+            .view_call(
+                &self.contract_id,
+                "get_fields_by_pool",
+                serde_json::json!({"pool_id": pool_id}),
+            )
+            .await?;
+        Ok(fields.map(|fields| PoolMetadataRow::new(pool_id.to_string(), block_height, fields)))
+    }
+}
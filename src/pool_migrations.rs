@@ -0,0 +1,105 @@
+use crate::staking_pool_logs::StakingPoolLog;
+use std::collections::HashMap;
+use std::env;
+
+use clickhouse::Row;
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_WINDOW_BLOCKS: u64 = 86400;
+
+/// A delegator that withdrew from one staking pool and staked with another one within the window.
+#[derive(Row, Serialize, Deserialize)]
+pub struct PoolMigrationRow {
+    pub delegator_id: String,
+    pub from_pool_id: String,
+    pub to_pool_id: String,
+    pub withdrawn: u128,
+    pub staked: u128,
+    pub withdraw_block_height: u64,
+    pub stake_block_height: u64,
+    pub block_timestamp: u64,
+    pub transaction_hash: String,
+}
+
+struct Withdrawal {
+    pool_id: String,
+    amount: u128,
+    block_height: u64,
+}
+
+/// Correlates staking pool withdrawals with stakes to another pool by the same delegator. A
+/// withdrawal always follows an unstake, so it marks the stake leaving the pool. Pending
+/// withdrawals are kept in memory, so a migration across a restart is not detected.
+pub struct PoolMigrations {
+    pub window_blocks: u64,
+    withdrawals: HashMap<String, Withdrawal>,
+}
+
+impl PoolMigrations {
+    pub fn from_env() -> Self {
+        Self {
+            window_blocks: env::var("POOL_MIGRATION_WINDOW_BLOCKS")
+                .map(|v| v.parse().expect("Invalid POOL_MIGRATION_WINDOW_BLOCKS"))
+                .unwrap_or(DEFAULT_WINDOW_BLOCKS),
+            withdrawals: HashMap::new(),
+        }
+    }
+
+    /// Drops the withdrawals that are outside of the window.
+    pub fn start_block(&mut self, block_height: u64) {
+        let window_blocks = self.window_blocks;
+        self.withdrawals
+            .retain(|_, withdrawal| block_height <= withdrawal.block_height + window_blocks);
+    }
+
+    pub fn process_log(
+        &mut self,
+        pool_id: &str,
+        log: &StakingPoolLog,
+        block_height: u64,
+        block_timestamp: u64,
+        transaction_hash: &str,
+    ) -> Option<PoolMigrationRow> {
+        match log {
+            StakingPoolLog::Withdrawing {
+                account_id, amount, ..
+            } => {
+                let withdrawal = self
+                    .withdrawals
+                    .entry(account_id.clone())
+                    .or_insert_with(|| Withdrawal {
+                        pool_id: pool_id.to_string(),
+                        amount: 0,
+                        block_height,
+                    });
+                if withdrawal.pool_id != pool_id {
+                    withdrawal.pool_id = pool_id.to_string();
+                    withdrawal.amount = 0;
+                }
+                withdrawal.amount += amount;
+                withdrawal.block_height = block_height;
+                None
+            }
+            StakingPoolLog::Staking {
+                account_id, amount, ..
+            } => {
+                if self.withdrawals.get(account_id)?.pool_id == pool_id {
+                    return None;
+                }
+                let withdrawal = self.withdrawals.remove(account_id)?;
+                Some(PoolMigrationRow {
+                    delegator_id: account_id.clone(),
+                    from_pool_id: withdrawal.pool_id,
+                    to_pool_id: pool_id.to_string(),
+                    withdrawn: withdrawal.amount,
+                    staked: *amount,
+                    withdraw_block_height: withdrawal.block_height,
+                    stake_block_height: block_height,
+                    block_timestamp,
+                    transaction_hash: transaction_hash.to_string(),
+                })
+            }
+            _ => None,
+        }
+    }
+}
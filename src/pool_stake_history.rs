@@ -0,0 +1,125 @@
+use crate::rpc::RpcClient;
+use std::env;
+use std::time::{Duration, Instant};
+
+use clickhouse::Row;
+use serde::{Deserialize, Serialize};
+
+pub const POOL_STAKE_HISTORY_TARGET: &str = "pool_stake_history";
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+/// The total stake of a validator pool in the epoch.
+#[derive(Row, Serialize, Deserialize)]
+pub struct PoolStakeRow {
+    pub pool_id: String,
+    pub epoch_id: String,
+    pub block_height: u64,
+    pub block_timestamp: u64,
+    pub stake: u128,
+}
+
+#[derive(Deserialize)]
+struct ValidatorsResponse {
+    current_validators: Vec<ValidatorInfo>,
+}
+
+#[derive(Deserialize)]
+struct ValidatorInfo {
+    account_id: String,
+    stake: String,
+}
+
+/// The first processed block of an epoch whose validators are not fetched yet.
+struct PendingEpoch {
+    epoch_id: String,
+    block_height: u64,
+    block_timestamp: u64,
+}
+
+/// Reads the stake of all validators with the `validators` RPC method on every new epoch. If the
+/// call fails, the epoch is retried on the next blocks with a backoff, also after the epoch ends.
+/// The pending epochs are lost on restart.
+pub struct PoolStakeHistory {
+    pub rpc: RpcClient,
+    pub last_epoch_id: Option<String>,
+    pending_epochs: Vec<PendingEpoch>,
+    retry_delay: Duration,
+    retry_at: Option<Instant>,
+}
+
+impl PoolStakeHistory {
+    pub fn from_env() -> Option<Self> {
+        if env::var("POOL_STAKE_HISTORY") != Ok("true".to_string()) {
+            return None;
+        }
+        Some(Self {
+            rpc: RpcClient::from_env().expect("Missing RPC_URL env var"),
+            last_epoch_id: None,
+            pending_epochs: vec![],
+            retry_delay: MIN_RETRY_DELAY,
+            retry_at: None,
+        })
+    }
+
+    /// Returns the validator stakes on the first processed block of every epoch, and of the
+    /// previously failed epochs once they are fetched.
+    pub async fn process_block(
+        &mut self,
+        epoch_id: &str,
+        block_height: u64,
+        block_timestamp: u64,
+    ) -> Vec<PoolStakeRow> {
+        if self.last_epoch_id.as_deref() != Some(epoch_id) {
+            self.last_epoch_id = Some(epoch_id.to_string());
+            self.pending_epochs.push(PendingEpoch {
+                epoch_id: epoch_id.to_string(),
+                block_height,
+                block_timestamp,
+            });
+        }
+        if self.pending_epochs.is_empty()
+            || self.retry_at.is_some_and(|retry_at| Instant::now() < retry_at)
+        {
+            return vec![];
+        }
+        let mut rows = vec![];
+        while let Some(epoch) = self.pending_epochs.first() {
+            match self.fetch_epoch(epoch).await {
+                Ok(epoch_rows) => {
+                    rows.extend(epoch_rows);
+                    self.pending_epochs.remove(0);
+                }
+                Err(err) => {
+                    tracing::log::error!(target: POOL_STAKE_HISTORY_TARGET, "Failed to fetch validators of epoch {}, retrying in {:?}: {}", epoch.epoch_id, self.retry_delay, err);
+                    self.retry_at = Some(Instant::now() + self.retry_delay);
+                    self.retry_delay = (self.retry_delay * 2).min(MAX_RETRY_DELAY);
+                    return rows;
+                }
+            }
+        }
+        self.retry_at = None;
+        self.retry_delay = MIN_RETRY_DELAY;
+        rows
+    }
+
+    async fn fetch_epoch(&self, epoch: &PendingEpoch) -> anyhow::Result<Vec<PoolStakeRow>> {
+        let response: ValidatorsResponse = self
+            .rpc
+            .call("validators", serde_json::json!({ "epoch_id": epoch.epoch_id }))
+            .await?;
+        Ok(response
+            .current_validators
+            .into_iter()
+            .filter_map(|validator| {
+                Some(PoolStakeRow {
+                    pool_id: validator.account_id,
+                    epoch_id: epoch.epoch_id.clone(),
+                    block_height: epoch.block_height,
+                    block_timestamp: epoch.block_timestamp,
+                    stake: validator.stake.parse().ok()?,
+                })
+            })
+            .collect())
+    }
+}
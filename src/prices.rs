@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::env;
+use std::time::{Duration, Instant};
+
+use clickhouse::Row;
+use serde::{Deserialize, Serialize};
+
+pub const PRICES_TARGET: &str = "prices";
+pub const NANOSECONDS_PER_HOUR: u64 = 3600 * 1_000_000_000;
+const YOCTO_PER_NEAR: f64 = 1e24;
+const MAX_RETRIES: usize = 5;
+
+/// NEAR/USD price for an hour, as returned by the price API.
+#[derive(Row, Serialize, Deserialize, Clone)]
+pub struct NearPriceRow {
+    pub timestamp: u64,
+    pub price: f64,
+}
+
+/// Fetches NEAR/USD prices from `PRICE_API_URL` and caches them per hour.
+/// The `{timestamp}` placeholder in the URL is replaced with the unix timestamp in seconds of the
+/// start of the hour. The price is read from the JSON response at `PRICE_API_JSON_POINTER`
+/// (defaults to `/price`) and can be either a number or a string. After the retries are exhausted,
+/// the uncached hours are skipped for `PRICE_API_FAILURE_TTL_SECONDS` (defaults to 300).
+pub struct PriceOracle {
+    pub client: reqwest::Client,
+    pub url: String,
+    pub json_pointer: String,
+    pub cache: HashMap<u64, f64>,
+    pub failure_ttl: Duration,
+    pub failed_at: Option<Instant>,
+    pub new_prices: Vec<NearPriceRow>,
+}
+
+impl PriceOracle {
+    pub fn from_env() -> Option<Self> {
+        let url = env::var("PRICE_API_URL").ok()?;
+        Some(Self {
+            client: reqwest::Client::new(),
+            url,
+            json_pointer: env::var("PRICE_API_JSON_POINTER").unwrap_or("/price".to_string()),
+            cache: HashMap::new(),
+            failure_ttl: Duration::from_secs(
+                env::var("PRICE_API_FAILURE_TTL_SECONDS")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(300),
+            ),
+            failed_at: None,
+            new_prices: vec![],
+        })
+    }
+
+    /// Returns the NEAR/USD price for the hour of the given block timestamp in nanoseconds.
+    /// Returns `None` if the price API keeps failing, so the indexing is not blocked by it.
+    pub async fn near_usd_price(&mut self, block_timestamp: u64) -> Option<f64> {
+        let hour_timestamp = block_timestamp - block_timestamp % NANOSECONDS_PER_HOUR;
+        if let Some(price) = self.cache.get(&hour_timestamp) {
+            return Some(*price);
+        }
+        if self
+            .failed_at
+            .is_some_and(|failed_at| failed_at.elapsed() < self.failure_ttl)
+        {
+            return None;
+        }
+        let url = self
+            .url
+            .replace("{timestamp}", &(hour_timestamp / 1_000_000_000).to_string());
+        let mut delay = Duration::from_millis(100);
+        for i in 0..MAX_RETRIES {
+            match self.fetch_price(&url).await {
+                Ok(price) => {
+                    self.cache.insert(hour_timestamp, price);
+                    self.failed_at = None;
+                    self.new_prices.push(NearPriceRow {
+                        timestamp: hour_timestamp,
+                        price,
+                    });
+                    return Some(price);
+                }
+                Err(err) => {
+                    tracing::log::error!(target: PRICES_TARGET, "Attempt #{}: Error fetching price \"{}\": {}", i, url, err);
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+            }
+        }
+        tracing::log::warn!(target: PRICES_TARGET, "Skipping USD values for the hour {} and the next {:?}", hour_timestamp, self.failure_ttl);
+        self.failed_at = Some(Instant::now());
+        None
+    }
+
+    async fn fetch_price(&self, url: &str) -> anyhow::Result<f64> {
+        let value: serde_json::Value = self
+            .client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let price = value
+            .pointer(&self.json_pointer)
+            .ok_or_else(|| anyhow::anyhow!("Missing price at {}", self.json_pointer))?;
+        match price {
+            serde_json::Value::Number(price) => price.as_f64(),
+            serde_json::Value::String(price) => price.parse().ok(),
+            _ => None,
+        }
+        .ok_or_else(|| anyhow::anyhow!("Invalid price {}", price))
+    }
+
+    /// Returns the prices fetched since the last call, to be stored in the `near_prices` table.
+    pub fn take_new_prices(&mut self) -> Vec<NearPriceRow> {
+        std::mem::take(&mut self.new_prices)
+    }
+}
+
+pub fn yocto_near_to_usd(amount: u128, price: f64) -> f64 {
+    amount as f64 / YOCTO_PER_NEAR * price
+}
@@ -0,0 +1,55 @@
+use crate::click::*;
+use crate::delegator_history::delegator_epochs;
+use crate::rpc::RpcClient;
+
+use serde::Serialize;
+
+/// The staked balance of the delegator at the block of the epoch rewards log.
+#[derive(Serialize)]
+pub struct ReconcileRow {
+    pub epoch_height: u64,
+    pub block_height: u64,
+    /// The delegator shares from the staking pool logs converted at the pool share price.
+    pub computed_balance: String,
+    /// The result of `get_account_staked_balance`.
+    pub on_chain_balance: String,
+    /// `on_chain_balance - computed_balance` in yoctoNEAR.
+    pub drift: String,
+}
+
+/// Compares the delegator staked balance computed from the `events` and `pool_rewards` tables with
+/// the `get_account_staked_balance` view calls at every epoch rewards block in the range.
+pub async fn reconcile(
+    db: &ClickDB,
+    rpc: &RpcClient,
+    pool_id: &str,
+    delegator_id: &str,
+    from_block_height: u64,
+    to_block_height: u64,
+) -> anyhow::Result<Vec<ReconcileRow>> {
+    let epochs = delegator_epochs(db, delegator_id, Some(pool_id), to_block_height).await?;
+    let mut rows = vec![];
+    for epoch in epochs {
+        if epoch.block_height < from_block_height {
+            continue;
+        }
+        let computed_balance = epoch.balance();
+        let on_chain_balance: String = rpc
+            .view_call_at_block(
+                pool_id,
+                "get_account_staked_balance",
+                serde_json::json!({ "account_id": delegator_id }),
+                epoch.block_height,
+            )
+            .await?;
+        let on_chain_balance: u128 = on_chain_balance.parse()?;
+        rows.push(ReconcileRow {
+            epoch_height: epoch.epoch_height,
+            block_height: epoch.block_height,
+            computed_balance: computed_balance.to_string(),
+            on_chain_balance: on_chain_balance.to_string(),
+            drift: (on_chain_balance as i128 - computed_balance as i128).to_string(),
+        });
+    }
+    Ok(rows)
+}
@@ -0,0 +1,129 @@
+use crate::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use fastnear_neardata_fetcher::fetcher;
+use fastnear_primitives::near_primitives::types::BlockHeight;
+use fastnear_primitives::types::ChainId;
+use tokio::sync::mpsc;
+
+pub struct ReindexConfig {
+    pub chain_id: ChainId,
+    pub chain_id_name: String,
+    pub num_threads: u64,
+    pub from_block_height: BlockHeight,
+    pub to_block_height: BlockHeight,
+}
+
+/// Re-runs the pipeline over the block range. The rows replace the existing ones by the keys of
+/// the `ReplacingMergeTree` tables, and the checkpoints stay at the head, since they are the max
+/// block heights of the tables. The pipeline state (e.g. the transaction cache) is warmed up over
+/// the `SAFE_CATCH_UP_OFFSET` blocks before the range without writing rows. The transactions cache
+/// is a temporary one, so the live cache of the running indexer isn't touched.
+pub async fn reindex(
+    db: ClickDB,
+    pipeline: &str,
+    config: ReindexConfig,
+    is_running: Arc<AtomicBool>,
+) {
+    let last_block_height = config.from_block_height.saturating_sub(1);
+    let start_block_height = config
+        .from_block_height
+        .saturating_sub(safe_catch_up_offset());
+    tracing::log::info!(target: PROJECT_ID, "Reindexing {} blocks #{}..=#{} from #{}", pipeline, config.from_block_height, config.to_block_height, start_block_height);
+
+    let (sender, receiver) = mpsc::channel(100);
+    let fetcher_config = fetcher::FetcherConfig {
+        num_threads: config.num_threads,
+        start_block_height,
+        chain_id: config.chain_id,
+    };
+    tokio::spawn(fetcher::start_fetcher(
+        Some(reqwest::Client::new()),
+        fetcher_config,
+        sender,
+        is_running.clone(),
+    ));
+    let receiver = Resequencer::new(&config.chain_id_name, start_block_height).spawn(receiver);
+    let receiver = take_blocks_until(receiver, config.to_block_height, is_running);
+
+    match pipeline {
+        "actions" => {
+            let actions_data = ActionsData::new();
+            listen_blocks_for_actions(receiver, db, actions_data, last_block_height, None).await;
+        }
+        "transactions" => {
+            // The range starts with an empty cache, so it doesn't have to be on disk.
+            let sled_db_path = (!is_memory_cache()).then(|| {
+                std::env::temp_dir().join(format!(
+                    "reindex-{}-{}",
+                    config.from_block_height, config.to_block_height
+                ))
+            });
+            if let Some(sled_db_path) = sled_db_path.as_ref().filter(|path| path.exists()) {
+                std::fs::remove_dir_all(sled_db_path).expect("Failed to clear the reindex cache");
+            }
+            let mut transactions_data = TransactionsData::with_cache(
+                sled_db_path
+                    .as_ref()
+                    .map(|path| path.to_string_lossy().to_string()),
+                None,
+            );
+            if transactions_data.needs_watch_list() {
+                transactions_data.watch_list = WatchList::load(&db)
+                    .await
+                    .expect("Failed to load watch list");
+            }
+            if transactions_data.store_account_daily_stats {
+                transactions_data.staking_pools = StakingPools::load(&db)
+                    .await
+                    .expect("Failed to load staking pools");
+            }
+            listen_blocks_for_transactions(
+                receiver,
+                db,
+                transactions_data,
+                last_block_height,
+                None,
+            )
+            .await;
+            if let Some(sled_db_path) = sled_db_path {
+                std::fs::remove_dir_all(sled_db_path).expect("Failed to remove the reindex cache");
+            }
+        }
+        "blocks" => {
+            let watch_list = WatchList::load(&db)
+                .await
+                .expect("Failed to load watch list");
+            let mut blocks_data = BlocksData::new(watch_list);
+            // The aggregating `epochs` table can't be rebuilt for a block range, since the
+            // reindexed blocks would be counted twice.
+            blocks_data.store_summing_tables = false;
+            listen_blocks_for_blocks(receiver, db, blocks_data, last_block_height, None).await;
+        }
+        _ => {
+            panic!("Unknown pipeline: {}", pipeline);
+        }
+    }
+}
+
+/// Passes the blocks up to the block height and stops the fetcher after it.
+fn take_blocks_until(
+    mut receiver: mpsc::Receiver<BlockWithTxHashes>,
+    to_block_height: BlockHeight,
+    is_running: Arc<AtomicBool>,
+) -> mpsc::Receiver<BlockWithTxHashes> {
+    let (sender, limited_receiver) = mpsc::channel(100);
+    tokio::spawn(async move {
+        while let Some(block) = receiver.recv().await {
+            if block.block.header.height > to_block_height {
+                break;
+            }
+            if sender.send(block).await.is_err() {
+                break;
+            }
+        }
+        is_running.store(false, Ordering::SeqCst);
+    });
+    limited_receiver
+}
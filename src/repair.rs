@@ -0,0 +1,150 @@
+use crate::*;
+
+use fastnear_primitives::near_primitives::types::BlockHeight;
+
+/// Transactions completed within the range. `{from}` and `{to}` are replaced by the range.
+const RANGE_TRANSACTIONS: &str = "SELECT transaction_hash FROM transactions WHERE last_block_height BETWEEN {from} AND {to}";
+const RANGE_FAILED_TRANSACTIONS: &str = "SELECT transaction_hash FROM failed_transactions WHERE last_block_height BETWEEN {from} AND {to}";
+
+/// The tables of the pipeline with the condition of the rows in the range, in the deletion order.
+/// The rows that are found through the checkpoint table of the pipeline are deleted before it, so
+/// an interrupted repair can be started again.
+pub fn range_conditions(
+    pipeline: &str,
+    has_failed_transactions: bool,
+) -> Vec<(&'static str, String)> {
+    let block_height = "block_height BETWEEN {from} AND {to}".to_string();
+    match pipeline {
+        "actions" => {
+            let mut deletes: Vec<_> = [
+                "events",
+                "data",
+                "stake_proposals",
+                "pool_rewards",
+                "pool_stake_history",
+                "whitelisted_pools",
+                "pool_epoch_delegators",
+                "pool_churn",
+                "pool_metadata",
+            ]
+            .into_iter()
+            .map(|table| (table, block_height.clone()))
+            .collect();
+            deletes.push((
+                "pool_migrations",
+                "stake_block_height BETWEEN {from} AND {to}".to_string(),
+            ));
+            deletes.push(("actions", block_height));
+            deletes
+        }
+        "transactions" => {
+            let transactions = if has_failed_transactions {
+                format!("{} UNION ALL {}", RANGE_TRANSACTIONS, RANGE_FAILED_TRANSACTIONS)
+            } else {
+                RANGE_TRANSACTIONS.to_string()
+            };
+            let by_transaction = format!("transaction_hash IN ({})", transactions);
+            let mut deletes = vec![
+                (
+                    "tx_search",
+                    format!(
+                        "receipt_id IN (SELECT receipt_id FROM receipt_txs WHERE {})",
+                        by_transaction
+                    ),
+                ),
+                // The beneficiaries that were added to the watch list by the deletions in the
+                // range. They are added again by the reindex if the deleted account is watched.
+                (
+                    "watch_list",
+                    format!(
+                        "account_id IN (SELECT beneficiary_id FROM account_deletions WHERE {})",
+                        by_transaction
+                    ),
+                ),
+                (
+                    "stale_transactions",
+                    "evicted_block_height BETWEEN {from} AND {to}".to_string(),
+                ),
+            ];
+            deletes.extend(
+                [
+                    "account_txs",
+                    "block_txs",
+                    "token_movements",
+                    "tx_receipts",
+                    "tx_events",
+                    "receipt_edges",
+                    "receipt_logs",
+                    "tx_actions",
+                    "account_deletions",
+                    "key_txs",
+                    "account_daily_txs",
+                    "receipt_txs",
+                ]
+                .into_iter()
+                .map(|table| (table, by_transaction.clone())),
+            );
+            if has_failed_transactions {
+                deletes.push((
+                    "failed_transactions",
+                    "last_block_height BETWEEN {from} AND {to}".to_string(),
+                ));
+            }
+            deletes.push((
+                "transactions",
+                "last_block_height BETWEEN {from} AND {to}".to_string(),
+            ));
+            deletes.push(("blocks", block_height));
+            deletes
+        }
+        "blocks" => {
+            let mut deletes: Vec<_> = [
+                "gas_prices",
+                "storage_usage",
+                "protocol_upgrades",
+                "chunks",
+                "total_supply",
+                "skipped_blocks",
+            ]
+            .into_iter()
+            .map(|table| (table, block_height.clone()))
+            .collect();
+            if crate::blocks::store_block_headers() {
+                deletes.push(("blocks", block_height));
+            }
+            deletes
+        }
+        _ => panic!("Unknown pipeline: {}", pipeline),
+    }
+}
+
+/// Deletes the rows of the pipeline within the block range from all of its tables, so they can be
+/// reindexed from scratch. The summing and aggregating tables are left as is, since their rows
+/// are not bound to a block. Tables that don't exist (e.g. the optional ones) are skipped.
+pub async fn delete_range(
+    db: &ClickDB,
+    pipeline: &str,
+    from_block_height: BlockHeight,
+    to_block_height: BlockHeight,
+) -> clickhouse::error::Result<()> {
+    let tables = db.tables().await?;
+    let has_failed_transactions = tables.iter().any(|table| table == "failed_transactions");
+    for (table, condition) in range_conditions(pipeline, has_failed_transactions) {
+        if !tables.iter().any(|name| name == table) {
+            tracing::log::info!(target: PROJECT_ID, "Skipping missing table {}", table);
+            continue;
+        }
+        let condition = format!("{} AND {}", condition, chain_filter())
+            .replace("{from}", &from_block_height.to_string())
+            .replace("{to}", &to_block_height.to_string());
+        db.client
+            .query(&format!(
+                "ALTER TABLE {} DELETE WHERE {} SETTINGS mutations_sync = 1",
+                table, condition
+            ))
+            .execute()
+            .await?;
+        tracing::log::info!(target: PROJECT_ID, "Deleted blocks #{}..=#{} from {}", from_block_height, to_block_height, table);
+    }
+    Ok(())
+}
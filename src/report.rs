@@ -0,0 +1,120 @@
+use crate::click::*;
+use crate::delegator_history::delegator_epochs;
+use crate::prices::{yocto_near_to_usd, NearPriceRow, NANOSECONDS_PER_HOUR};
+use std::collections::{BTreeMap, HashMap};
+
+use serde::Serialize;
+
+/// The staking rewards of the delegator in a pool for an epoch or a month.
+#[derive(Serialize, Default)]
+pub struct ReportRow {
+    pub pool_id: String,
+    /// The epoch height, or `None` for the monthly rows.
+    pub epoch_height: Option<u64>,
+    /// The month as `YYYY-MM`.
+    pub month: String,
+    /// The rewards in yoctoNEAR.
+    pub rewards: String,
+    /// The NEAR/USD price at the hour of the epoch rewards, or `None` for the monthly rows.
+    pub near_price: Option<f64>,
+    /// The rewards in USD at the price of every epoch. Epochs without a price are not counted.
+    pub rewards_usd: Option<f64>,
+}
+
+/// The staking income statement of the delegator.
+#[derive(Serialize)]
+pub struct Report {
+    pub delegator_id: String,
+    pub from_block_height: u64,
+    pub to_block_height: u64,
+    pub epochs: Vec<ReportRow>,
+    pub months: Vec<ReportRow>,
+}
+
+/// Builds the staking income statement of the delegator across all pools. The rewards of the epoch
+/// are the delegator shares valued at the share price growth since the previous epoch rewards log.
+/// The USD values use the `near_prices` table.
+pub async fn build_report(
+    db: &ClickDB,
+    delegator_id: &str,
+    from_block_height: u64,
+    to_block_height: u64,
+) -> anyhow::Result<Report> {
+    let epochs = delegator_epochs(db, delegator_id, None, to_block_height).await?;
+    let prices: HashMap<u64, f64> = db
+        .client
+        .query(&format!(
+            "SELECT ?fields FROM near_prices FINAL WHERE {}",
+            chain_filter()
+        ))
+        .fetch_all::<NearPriceRow>()
+        .await?
+        .into_iter()
+        .map(|row| (row.timestamp, row.price))
+        .collect();
+
+    let mut epoch_rows = vec![];
+    let mut month_rows: BTreeMap<(String, String), (u128, Option<f64>)> = BTreeMap::new();
+    for (previous, epoch) in epochs.iter().zip(epochs.iter().skip(1)) {
+        if previous.pool_id != epoch.pool_id || epoch.block_height < from_block_height {
+            continue;
+        }
+        // The shares of the epoch are valued at the price growth, so stake changes within the
+        // epoch are not rewards.
+        let rewards = epoch.rewards_since(previous);
+        let near_price = prices
+            .get(&(epoch.block_timestamp - epoch.block_timestamp % NANOSECONDS_PER_HOUR))
+            .copied();
+        let rewards_usd = near_price.map(|price| yocto_near_to_usd(rewards, price));
+        let month_row = month_rows
+            .entry((epoch.month.clone(), epoch.pool_id.clone()))
+            .or_default();
+        month_row.0 += rewards;
+        if let Some(rewards_usd) = rewards_usd {
+            month_row.1 = Some(month_row.1.unwrap_or_default() + rewards_usd);
+        }
+        epoch_rows.push(ReportRow {
+            pool_id: epoch.pool_id.clone(),
+            epoch_height: Some(epoch.epoch_height),
+            month: epoch.month.clone(),
+            rewards: rewards.to_string(),
+            near_price,
+            rewards_usd,
+        });
+    }
+    Ok(Report {
+        delegator_id: delegator_id.to_string(),
+        from_block_height,
+        to_block_height,
+        epochs: epoch_rows,
+        months: month_rows
+            .into_iter()
+            .map(|((month, pool_id), (rewards, rewards_usd))| ReportRow {
+                pool_id,
+                epoch_height: None,
+                month,
+                rewards: rewards.to_string(),
+                near_price: None,
+                rewards_usd,
+            })
+            .collect(),
+    })
+}
+
+/// Formats the report rows as CSV with a header line.
+pub fn to_csv(rows: &[ReportRow]) -> String {
+    let optional = |value: Option<String>| value.unwrap_or_default();
+    let mut csv = "pool_id,epoch_height,month,rewards,near_price,rewards_usd\n".to_string();
+    for row in rows {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{}\n",
+            row.pool_id,
+            optional(row.epoch_height.map(|v| v.to_string())),
+            row.month,
+            row.rewards,
+            optional(row.near_price.map(|v| v.to_string())),
+            optional(row.rewards_usd.map(|v| format!("{:.2}", v))),
+        ));
+    }
+    csv
+}
@@ -0,0 +1,167 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::time::Duration;
+
+use fastnear_primitives::block_with_tx_hash::BlockWithTxHashes;
+use fastnear_primitives::near_primitives::types::BlockHeight;
+use tokio::sync::mpsc;
+
+pub const RESEQUENCER_TARGET: &str = "resequencer";
+
+/// Blocks buffered after a gap before the missing blocks are refetched.
+const MAX_BUFFERED_BLOCKS: usize = 100;
+const MAX_FETCH_RETRIES: usize = 10;
+const FETCH_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Passes the fetched blocks on in the order of the heights before they are processed, since the
+/// transaction cache is corrupted by an out-of-order block. Heights can be skipped by the chain, so
+/// a block is next once its `prev_height` is already passed. Out-of-order blocks are buffered until
+/// the next block arrives, and a gap that doesn't fill up is refetched from neardata. The hashes are
+/// not verified here, a block that doesn't follow the last one is passed on to `ChainContinuity`,
+/// which stops the command.
+pub struct Resequencer {
+    pub client: reqwest::Client,
+    /// `NEARDATA_URL`, or `https://{CHAIN_ID}.neardata.xyz` by default.
+    pub neardata_url: String,
+    next_block_height: BlockHeight,
+    last_block_height: Option<BlockHeight>,
+    buffer: BTreeMap<BlockHeight, BlockWithTxHashes>,
+}
+
+impl Resequencer {
+    pub fn new(chain_id: &str, start_block_height: BlockHeight) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            neardata_url: env::var("NEARDATA_URL")
+                .unwrap_or_else(|_| format!("https://{}.neardata.xyz", chain_id)),
+            next_block_height: start_block_height,
+            last_block_height: None,
+            buffer: BTreeMap::new(),
+        }
+    }
+
+    /// Passes the blocks from the fetcher to the returned receiver in order.
+    pub fn spawn(
+        self,
+        receiver: mpsc::Receiver<BlockWithTxHashes>,
+    ) -> mpsc::Receiver<BlockWithTxHashes> {
+        let (sender, ordered_receiver) = mpsc::channel(100);
+        tokio::spawn(self.run(receiver, sender));
+        ordered_receiver
+    }
+
+    async fn run(
+        mut self,
+        mut receiver: mpsc::Receiver<BlockWithTxHashes>,
+        sender: mpsc::Sender<BlockWithTxHashes>,
+    ) {
+        while let Some(block) = receiver.recv().await {
+            let block_height = block.block.header.height;
+            if block_height < self.next_block_height {
+                tracing::log::warn!(target: RESEQUENCER_TARGET, "Dropping block #{} that is already processed", block_height);
+                continue;
+            }
+            if let Some((&last_buffered_height, _)) = self.buffer.last_key_value() {
+                if block_height < last_buffered_height {
+                    tracing::log::warn!(target: RESEQUENCER_TARGET, "Block #{} arrived after #{}", block_height, last_buffered_height);
+                }
+            }
+            self.buffer.insert(block_height, block);
+            if !self.send_ready_blocks(&sender, false).await {
+                return;
+            }
+            if self.buffer.len() >= MAX_BUFFERED_BLOCKS {
+                self.refetch_gap().await;
+                // Every height of the gap is fetched or skipped, so the first buffered block is
+                // passed on even if it doesn't follow the last one.
+                if !self.send_ready_blocks(&sender, true).await {
+                    return;
+                }
+            }
+        }
+        // The blocks after a gap are fetched again on the next start.
+        if !self.buffer.is_empty() {
+            tracing::log::warn!(target: RESEQUENCER_TARGET, "Dropping {} blocks after the gap at #{} on shutdown", self.buffer.len(), self.next_block_height);
+        }
+    }
+
+    /// Sends the buffered blocks that continue the chain, and the first buffered block with
+    /// `force`. Returns false if the receiver is closed.
+    async fn send_ready_blocks(
+        &mut self,
+        sender: &mpsc::Sender<BlockWithTxHashes>,
+        mut force: bool,
+    ) -> bool {
+        while let Some(entry) = self.buffer.first_entry() {
+            let header = &entry.get().block.header;
+            // The start height can be skipped by the chain, so the first block is the lowest
+            // buffered one. The heights below the start are dropped before they are buffered.
+            let is_next = match (self.last_block_height, header.prev_height) {
+                (Some(last_block_height), Some(prev_height)) => prev_height <= last_block_height,
+                _ => header.height >= self.next_block_height,
+            };
+            if !is_next && !force {
+                break;
+            }
+            if !is_next {
+                tracing::log::warn!(target: RESEQUENCER_TARGET, "Block #{} with prev_height {:?} doesn't follow the last block #{:?}", header.height, header.prev_height, self.last_block_height);
+            }
+            force = false;
+            let block = entry.remove();
+            self.next_block_height = block.block.header.height + 1;
+            self.last_block_height = Some(block.block.header.height);
+            if sender.send(block).await.is_err() {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Fetches the heights before the first buffered block and buffers the fetched blocks. A
+    /// height that can't be fetched is left out, so the next block is checked by `ChainContinuity`.
+    async fn refetch_gap(&mut self) {
+        let first_buffered_height = *self.buffer.first_key_value().unwrap().0;
+        tracing::log::warn!(target: RESEQUENCER_TARGET, "Refetching blocks #{}..#{}", self.next_block_height, first_buffered_height);
+        for block_height in self.next_block_height..first_buffered_height {
+            match self.fetch_block(block_height).await {
+                Ok(Some(block)) => {
+                    self.buffer.insert(block_height, block);
+                }
+                // The height is skipped by the chain.
+                Ok(None) => {}
+                Err(err) => {
+                    tracing::log::error!(target: RESEQUENCER_TARGET, "Failed to refetch block #{}: {}", block_height, err);
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn fetch_block(
+        &self,
+        block_height: BlockHeight,
+    ) -> anyhow::Result<Option<BlockWithTxHashes>> {
+        let url = format!("{}/v0/block/{}", self.neardata_url, block_height);
+        let mut attempt = 0;
+        loop {
+            let res = async {
+                self.client
+                    .get(&url)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json::<Option<BlockWithTxHashes>>()
+                    .await
+            };
+            match res.await {
+                Ok(block) => return Ok(block),
+                Err(err) if attempt + 1 < MAX_FETCH_RETRIES => {
+                    tracing::log::error!(target: RESEQUENCER_TARGET, "Attempt #{}: Failed to fetch block #{}: {}", attempt, block_height, err);
+                    tokio::time::sleep(FETCH_RETRY_DELAY).await;
+                }
+                Err(err) => return Err(err.into()),
+            }
+            attempt += 1;
+        }
+    }
+}
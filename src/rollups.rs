@@ -0,0 +1,196 @@
+use crate::click::*;
+
+use fastnear_primitives::near_primitives::types::BlockHeight;
+
+/// A summary table that is refreshed from the raw tables. The query is executed for the block
+/// range of every committed batch and recomputes every period (a day or a month) that the range
+/// touches from all rows of the period. The summary table is a `ReplacingMergeTree` keyed by the
+/// period, so refreshing a range again (e.g. after a restart) replaces the rows instead of
+/// counting them twice. The query takes the first and the last block heights of the range as the
+/// binds, and `{chain_filter}` is replaced by the condition of the configured chain.
+pub struct Rollup {
+    pub table: &'static str,
+    pub query: &'static str,
+}
+
+/// Rollups over the `actions` and `pool_rewards` tables.
+pub const ACTIONS_ROLLUPS: [Rollup; 3] = [
+    Rollup {
+        table: "pool_daily_stake_delta",
+        query: "INSERT INTO pool_daily_stake_delta
+            WITH (
+                SELECT (min(toDate(block_timestamp)), max(toDate(block_timestamp)))
+                FROM actions
+                WHERE block_height >= ? AND block_height <= ? AND {chain_filter}
+            ) AS dates
+            SELECT
+                account_id AS pool_id,
+                toDate(block_timestamp) AS date,
+                sumIf(coalesce(deposit, 0), method_name = 'deposit_and_stake')
+                    + sumIf(coalesce(args_amount, 0), method_name = 'stake') AS staked,
+                sumIf(coalesce(args_amount, 0), method_name = 'unstake') AS unstaked,
+                countIf(method_name = 'unstake_all') AS unstake_all_calls,
+                sumIf(coalesce(deposit_usd, 0), method_name = 'deposit_and_stake')
+                    + sumIf(coalesce(args_amount_usd, 0), method_name = 'stake') AS staked_usd,
+                sumIf(coalesce(args_amount_usd, 0), method_name = 'unstake') AS unstaked_usd
+            FROM actions
+            WHERE block_timestamp >= toDateTime64(dates.1, 9, 'UTC')
+                AND block_timestamp < toDateTime64(dates.2 + 1, 9, 'UTC')
+                AND action = 'FUNCTION_CALL'
+                AND status = 'SUCCESS'
+                AND method_name IN ('deposit_and_stake', 'stake', 'unstake', 'unstake_all')
+                AND {chain_filter}
+            GROUP BY pool_id, date",
+    },
+    // The delegator shares at every epoch rewards log are the total shares from the last staking
+    // or unstaking log of the delegator, and the rewards are the shares valued at the share price
+    // growth since the previous epoch rewards log of the pool, like the `report` command.
+    Rollup {
+        table: "account_monthly_rewards",
+        query: "INSERT INTO account_monthly_rewards
+            WITH
+                (
+                    SELECT groupUniqArray(toStartOfMonth(block_timestamp))
+                    FROM pool_rewards FINAL
+                    WHERE block_height >= ? AND block_height <= ? AND {chain_filter}
+                ) AS months,
+                epoch_rewards AS (
+                    SELECT
+                        pool_id,
+                        block_height,
+                        toStartOfMonth(block_timestamp) AS month,
+                        toDecimal256(total_staked_balance, 24) / toDecimal256(total_shares, 0) AS share_price,
+                        lagInFrame(share_price) OVER (
+                            PARTITION BY pool_id ORDER BY block_height
+                            ROWS BETWEEN 1 PRECEDING AND CURRENT ROW
+                        ) AS previous_share_price
+                    FROM pool_rewards FINAL
+                    WHERE total_shares > 0 AND {chain_filter}
+                ),
+                logs AS (
+                    SELECT
+                        account_id AS pool_id,
+                        extract(log, '^@([^ ]+) ') AS delegator_id,
+                        block_height,
+                        toUInt128(extract(log, ' and ([0-9]+) staking shares$')) AS shares
+                    FROM events
+                    WHERE status = 'SUCCESS'
+                        AND {chain_filter}
+                        AND match(log, '^@[^ ]+ (staking|unstaking) [0-9]+\\\\. .* and [0-9]+ staking shares$')
+                        AND account_id IN (SELECT pool_id FROM epoch_rewards WHERE has(months, month))
+                )
+            SELECT
+                delegator_epochs.delegator_id AS account_id,
+                delegator_epochs.pool_id AS pool_id,
+                delegator_epochs.month AS month,
+                sum(toUInt128(floor(toDecimal256(logs.shares, 0)
+                    * greatest(delegator_epochs.share_price - delegator_epochs.previous_share_price, 0)))) AS rewards,
+                count() AS epochs
+            FROM (
+                SELECT epoch_rewards.*, delegators.delegator_id
+                FROM epoch_rewards
+                INNER JOIN (SELECT DISTINCT pool_id, delegator_id FROM logs) AS delegators
+                    ON delegators.pool_id = epoch_rewards.pool_id
+                WHERE has(months, epoch_rewards.month) AND epoch_rewards.previous_share_price > 0
+            ) AS delegator_epochs
+            ASOF INNER JOIN logs
+                ON logs.pool_id = delegator_epochs.pool_id
+                AND logs.delegator_id = delegator_epochs.delegator_id
+                AND delegator_epochs.block_height >= logs.block_height
+            GROUP BY account_id, pool_id, month",
+    },
+    // The delegator rewards of every epoch rewards log within the range, valued like the monthly
+    // rewards. The rewards are compounded if the delegator shares didn't decrease since the
+    // previous epoch rewards log of the pool, i.e. the earlier rewards were not unstaked.
+    Rollup {
+        table: "delegator_rewards",
+        query: "INSERT INTO delegator_rewards
+            WITH
+                (?, ?) AS block_range,
+                epoch_rewards AS (
+                    SELECT
+                        pool_id,
+                        epoch_height,
+                        block_height,
+                        block_timestamp,
+                        toDecimal256(total_staked_balance, 24) / toDecimal256(total_shares, 0) AS share_price,
+                        lagInFrame(share_price) OVER (
+                            PARTITION BY pool_id ORDER BY block_height
+                            ROWS BETWEEN 1 PRECEDING AND CURRENT ROW
+                        ) AS previous_share_price,
+                        lagInFrame(block_height) OVER (
+                            PARTITION BY pool_id ORDER BY block_height
+                            ROWS BETWEEN 1 PRECEDING AND CURRENT ROW
+                        ) AS previous_block_height
+                    FROM pool_rewards FINAL
+                    WHERE total_shares > 0 AND {chain_filter}
+                ),
+                logs AS (
+                    SELECT
+                        account_id AS pool_id,
+                        extract(log, '^@([^ ]+) ') AS delegator_id,
+                        block_height,
+                        toUInt128(extract(log, ' and ([0-9]+) staking shares$')) AS shares
+                    FROM events
+                    WHERE status = 'SUCCESS'
+                        AND {chain_filter}
+                        AND match(log, '^@[^ ]+ (staking|unstaking) [0-9]+\\\\. .* and [0-9]+ staking shares$')
+                        AND account_id IN (
+                            SELECT pool_id FROM epoch_rewards
+                            WHERE block_height >= block_range.1 AND block_height <= block_range.2
+                        )
+                )
+            SELECT
+                delegator_epochs.delegator_id AS account_id,
+                delegator_epochs.pool_id AS pool_id,
+                delegator_epochs.epoch_height AS epoch_height,
+                delegator_epochs.block_height AS block_height,
+                delegator_epochs.block_timestamp AS block_timestamp,
+                logs.shares AS staking_shares,
+                toUInt128(floor(toDecimal256(logs.shares, 0)
+                    * greatest(delegator_epochs.share_price - delegator_epochs.previous_share_price, 0))) AS rewards,
+                logs.shares >= previous_logs.shares AS compounded
+            FROM (
+                SELECT epoch_rewards.*, delegators.delegator_id
+                FROM epoch_rewards
+                INNER JOIN (SELECT DISTINCT pool_id, delegator_id FROM logs) AS delegators
+                    ON delegators.pool_id = epoch_rewards.pool_id
+                WHERE epoch_rewards.block_height >= block_range.1
+                    AND epoch_rewards.block_height <= block_range.2
+                    AND epoch_rewards.previous_share_price > 0
+            ) AS delegator_epochs
+            ASOF INNER JOIN logs
+                ON logs.pool_id = delegator_epochs.pool_id
+                AND logs.delegator_id = delegator_epochs.delegator_id
+                AND delegator_epochs.block_height >= logs.block_height
+            ASOF LEFT JOIN logs AS previous_logs
+                ON previous_logs.pool_id = delegator_epochs.pool_id
+                AND previous_logs.delegator_id = delegator_epochs.delegator_id
+                AND delegator_epochs.previous_block_height >= previous_logs.block_height
+            WHERE staking_shares > 0",
+    },
+];
+
+pub async fn refresh(
+    db: &ClickDB,
+    rollups: &[Rollup],
+    from_block_height: BlockHeight,
+    to_block_height: BlockHeight,
+) -> clickhouse::error::Result<()> {
+    for rollup in rollups {
+        db.client
+            .query(&rollup.query.replace("{chain_filter}", chain_filter()))
+            .bind(from_block_height)
+            .bind(to_block_height)
+            .execute()
+            .await?;
+        tracing::log::info!(
+            target: CLICKHOUSE_TARGET,
+            "Refreshed rollup {} for blocks {}..={}",
+            rollup.table,
+            from_block_height,
+            to_block_height
+        );
+    }
+    Ok(())
+}
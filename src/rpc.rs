@@ -0,0 +1,101 @@
+use std::env;
+
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+/// JSON RPC client of a NEAR node at `RPC_URL`.
+#[derive(Clone)]
+pub struct RpcClient {
+    pub client: reqwest::Client,
+    pub url: String,
+}
+
+impl RpcClient {
+    pub fn from_env() -> Option<Self> {
+        Some(Self {
+            client: reqwest::Client::new(),
+            url: env::var("RPC_URL").ok()?,
+        })
+    }
+
+    pub async fn call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> anyhow::Result<T> {
+        let mut response: Value = self
+            .client
+            .post(&self.url)
+            .json(&serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": "dontcare",
+                "method": method,
+                "params": params,
+            }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if let Some(error) = response.get("error") {
+            anyhow::bail!("RPC error: {}", error);
+        }
+        let result = response
+            .get_mut("result")
+            .map(Value::take)
+            .ok_or_else(|| anyhow::anyhow!("Missing result"))?;
+        Ok(serde_json::from_value(result)?)
+    }
+
+    /// Calls a view method of the contract at the final block and parses the JSON result.
+    pub async fn view_call<T: DeserializeOwned>(
+        &self,
+        contract_id: &str,
+        method_name: &str,
+        args: Value,
+    ) -> anyhow::Result<T> {
+        self.call_function(contract_id, method_name, args, None)
+            .await
+    }
+
+    /// Calls a view method of the contract at the block height. Old blocks need an archival node.
+    pub async fn view_call_at_block<T: DeserializeOwned>(
+        &self,
+        contract_id: &str,
+        method_name: &str,
+        args: Value,
+        block_height: u64,
+    ) -> anyhow::Result<T> {
+        self.call_function(contract_id, method_name, args, Some(block_height))
+            .await
+    }
+
+    async fn call_function<T: DeserializeOwned>(
+        &self,
+        contract_id: &str,
+        method_name: &str,
+        args: Value,
+        block_height: Option<u64>,
+    ) -> anyhow::Result<T> {
+        let mut params = serde_json::json!({
+            "request_type": "call_function",
+            "account_id": contract_id,
+            "method_name": method_name,
+            "args_base64": BASE64_STANDARD.encode(args.to_string()),
+        });
+        match block_height {
+            Some(block_height) => params["block_id"] = block_height.into(),
+            None => params["finality"] = "final".into(),
+        }
+        let response: Value = self.call("query", params).await?;
+        let result: Vec<u8> = serde_json::from_value(
+            response
+                .get("result")
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("Missing view call result"))?,
+        )?;
+        Ok(serde_json::from_slice(&result)?)
+    }
+}
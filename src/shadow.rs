@@ -0,0 +1,109 @@
+use crate::click::*;
+use crate::metrics::Metrics;
+use crate::spill::SpillQueue;
+use std::env;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::OnceLock;
+
+use clickhouse::{Client, Row};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+pub const SHADOW_TARGET: &str = "shadow";
+
+/// Batches queued for the shadow database before the new ones are dropped.
+const MAX_PENDING_BATCHES: usize = 100;
+
+type ShadowInsert = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// A secondary database that receives the same batches as the primary one after they are
+/// committed, e.g. to populate a new cluster before cutting over. The writes are best-effort:
+/// failures are logged and never fail the commit. With `SHADOW_SPILL_DIR` set, the batches are
+/// spilled to disk during an outage of the shadow database and replayed later. The batches are
+/// written in order by a background task, so a slow shadow database doesn't hold up the commits.
+/// The batches over `MAX_PENDING_BATCHES` are dropped, as well as the pending ones on shutdown.
+pub struct ShadowDB {
+    pub client: Client,
+    spill_queue: Option<SpillQueue>,
+    sender: mpsc::Sender<ShadowInsert>,
+}
+
+impl ShadowDB {
+    /// Returns the shadow database if `SHADOW_DATABASE_URL` is set.
+    pub fn get() -> Option<&'static Self> {
+        static SHADOW_DB: OnceLock<Option<ShadowDB>> = OnceLock::new();
+        SHADOW_DB.get_or_init(Self::from_env).as_ref()
+    }
+
+    /// The user, password and database default to the ones of the primary database.
+    fn from_env() -> Option<Self> {
+        let url = env::var("SHADOW_DATABASE_URL").ok()?;
+        let var = |name: &str| {
+            env::var(format!("SHADOW_{}", name))
+                .or_else(|_| env::var(name))
+                .unwrap()
+        };
+        tracing::log::info!(target: SHADOW_TARGET, "Shadow writing to {}", url);
+        let (sender, mut receiver) = mpsc::channel::<ShadowInsert>(MAX_PENDING_BATCHES);
+        tokio::spawn(async move {
+            while let Some(insert) = receiver.recv().await {
+                insert.await;
+            }
+        });
+        Some(Self {
+            client: with_chain_id(
+                Client::default()
+                    .with_url(url)
+                    .with_user(var("DATABASE_USER"))
+                    .with_password(var("DATABASE_PASSWORD"))
+                    .with_database(var("DATABASE_DATABASE")),
+            ),
+            spill_queue: env::var("SHADOW_SPILL_DIR")
+                .ok()
+                .map(|dir| SpillQueue::new(PathBuf::from(dir))),
+            sender,
+        })
+    }
+
+    /// Queues a copy of the rows for the background writer.
+    pub fn insert<T>(&'static self, rows: &[T], table: &str)
+    where
+        T: Row + Serialize + DeserializeOwned + Send + Sync + 'static,
+    {
+        // The rows are copied through JSON like the spilled batches, since they aren't `Clone`.
+        let rows = match rows
+            .iter()
+            .map(|row| serde_json::to_string(row).and_then(|row| serde_json::from_str(&row)))
+            .collect::<Result<Vec<T>, _>>()
+        {
+            Ok(rows) => rows,
+            Err(err) => {
+                tracing::log::error!(target: SHADOW_TARGET, "Dropping {} rows of \"{}\" for the shadow database: {}", rows.len(), table, err);
+                return;
+            }
+        };
+        let table = table.to_string();
+        let num_rows = rows.len();
+        let insert = Box::pin(async move { self.write(&rows, &table).await });
+        if self.sender.try_send(insert).is_err() {
+            tracing::log::error!(target: SHADOW_TARGET, "Dropping {} rows of \"{}\" for the shadow database over {} pending batches", num_rows, table, MAX_PENDING_BATCHES);
+            Metrics::get().dropped_shadow_batches.inc();
+        }
+    }
+
+    async fn write<T>(&self, rows: &Vec<T>, table: &str)
+    where
+        T: Row + Serialize + DeserializeOwned,
+    {
+        let res = match &self.spill_queue {
+            Some(spill_queue) => spill_queue.insert(&self.client, rows, table).await,
+            None => insert_rows(&self.client, rows, table).await,
+        };
+        if let Err(err) = res {
+            tracing::log::error!(target: SHADOW_TARGET, "Dropping {} rows of \"{}\" for the shadow database: {}", rows.len(), table, err);
+        }
+    }
+}
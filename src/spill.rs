@@ -0,0 +1,167 @@
+use crate::click::*;
+use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use clickhouse::{Client, Row};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+const DEFAULT_SPILL_RETRY_SECONDS: u64 = 60;
+
+/// A circuit breaker with a local disk queue for the inserts. When an insert fails with a
+/// transient error after all retries, the rows are spilled to `SPILL_DIR` and the circuit opens
+/// for `SPILL_RETRY_SECONDS`, so the following inserts are spilled right away and the indexing
+/// continues. The next insert into the table after that replays the spilled batches in order.
+pub struct SpillQueue {
+    pub dir: PathBuf,
+    pub retry_interval: Duration,
+    open_until: Mutex<Option<Instant>>,
+    /// Serializes the replays and the inserts per table, to keep the spilled batches in order.
+    table_locks: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+    batch_index: AtomicU64,
+}
+
+impl SpillQueue {
+    /// Returns the spill queue if `SPILL_DIR` is set.
+    pub fn get() -> Option<&'static Self> {
+        static SPILL_QUEUE: OnceLock<Option<SpillQueue>> = OnceLock::new();
+        SPILL_QUEUE.get_or_init(Self::from_env).as_ref()
+    }
+
+    fn from_env() -> Option<Self> {
+        Some(Self::new(PathBuf::from(env::var("SPILL_DIR").ok()?)))
+    }
+
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            retry_interval: Duration::from_secs(
+                env::var("SPILL_RETRY_SECONDS")
+                    .map(|v| v.parse().expect("Invalid SPILL_RETRY_SECONDS"))
+                    .unwrap_or(DEFAULT_SPILL_RETRY_SECONDS),
+            ),
+            open_until: Mutex::new(None),
+            table_locks: Mutex::new(HashMap::new()),
+            batch_index: AtomicU64::new(0),
+        }
+    }
+
+    pub async fn insert<T>(
+        &self,
+        client: &Client,
+        rows: &Vec<T>,
+        table: &str,
+    ) -> clickhouse::error::Result<()>
+    where
+        T: Row + Serialize + DeserializeOwned,
+    {
+        let table_lock = self
+            .table_locks
+            .lock()
+            .unwrap()
+            .entry(table.to_string())
+            .or_default()
+            .clone();
+        let _guard = table_lock.lock().await;
+        if self.is_open() {
+            return self.spill(rows, table);
+        }
+        let res = match self.replay::<T>(client, table).await {
+            Ok(()) => insert_rows(client, rows, table).await,
+            Err(err) => Err(err),
+        };
+        match res {
+            Ok(()) => {
+                *self.open_until.lock().unwrap() = None;
+                Ok(())
+            }
+            Err(err) if is_transient_error(&err) => {
+                tracing::log::warn!(target: CLICKHOUSE_TARGET, "Spilling {} rows of \"{}\" to disk for {:?}", rows.len(), table, self.retry_interval);
+                *self.open_until.lock().unwrap() = Some(Instant::now() + self.retry_interval);
+                self.spill(rows, table)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        self.open_until
+            .lock()
+            .unwrap()
+            .is_some_and(|open_until| Instant::now() < open_until)
+    }
+
+    /// Writes the rows as JSON lines into a new batch file of the table. The file names sort in
+    /// the spill order.
+    fn spill<T: Serialize>(&self, rows: &[T], table: &str) -> clickhouse::error::Result<()> {
+        let res = || -> anyhow::Result<()> {
+            let table_dir = self.dir.join(table);
+            std::fs::create_dir_all(&table_dir)?;
+            let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+            let batch_index = self.batch_index.fetch_add(1, Ordering::SeqCst);
+            let path = table_dir.join(format!("{:024}-{:010}.jsonl", timestamp, batch_index));
+            let mut data = String::new();
+            for row in rows {
+                data.push_str(&serde_json::to_string(row)?);
+                data.push('\n');
+            }
+            // Renaming makes the batch visible to the replay only when it's complete.
+            let tmp_path = path.with_extension("tmp");
+            std::fs::write(&tmp_path, data)?;
+            std::fs::rename(&tmp_path, &path)?;
+            Ok(())
+        };
+        res().map_err(|err| {
+            clickhouse::error::Error::Custom(format!("Failed to spill \"{}\": {}", table, err))
+        })
+    }
+
+    /// Inserts the spilled batches of the table in order, removing every inserted batch file.
+    async fn replay<T>(&self, client: &Client, table: &str) -> clickhouse::error::Result<()>
+    where
+        T: Row + Serialize + DeserializeOwned,
+    {
+        let read_error = |err: std::io::Error| {
+            clickhouse::error::Error::Custom(format!(
+                "Failed to read spill of \"{}\": {}",
+                table, err
+            ))
+        };
+        let table_dir = self.dir.join(table);
+        let mut paths = match std::fs::read_dir(&table_dir) {
+            Ok(entries) => entries
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(read_error)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(read_error(err)),
+        };
+        paths.retain(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "jsonl")
+        });
+        paths.sort();
+        for path in paths {
+            let data = std::fs::read_to_string(&path).map_err(read_error)?;
+            let rows = data
+                .lines()
+                .map(serde_json::from_str)
+                .collect::<Result<Vec<T>, _>>()
+                .map_err(|err| {
+                    clickhouse::error::Error::Custom(format!(
+                        "Invalid spilled batch {}: {}",
+                        path.display(),
+                        err
+                    ))
+                })?;
+            insert_rows(client, &rows, table).await?;
+            std::fs::remove_file(&path).map_err(read_error)?;
+            tracing::log::info!(target: CLICKHOUSE_TARGET, "Replayed {} spilled rows of \"{}\"", rows.len(), table);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+/// Parsed logs of the core staking pool contract. Amounts are in yoctoNEAR.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StakingPoolLog {
+    /// `@{account_id} deposited {amount}. New unstaked balance is {unstaked_balance}`
+    Deposited {
+        account_id: String,
+        amount: u128,
+        unstaked_balance: u128,
+    },
+    /// `@{account_id} withdrawing {amount}. New unstaked balance is {unstaked_balance}`
+    Withdrawing {
+        account_id: String,
+        amount: u128,
+        unstaked_balance: u128,
+    },
+    /// `@{account_id} staking {amount}. Received {shares} new staking shares. Total
+    /// {unstaked_balance} unstaked balance and {total_shares} staking shares`
+    Staking {
+        account_id: String,
+        amount: u128,
+        shares: u128,
+        unstaked_balance: u128,
+        total_shares: u128,
+    },
+    /// `@{account_id} unstaking {amount}. Spent {shares} staking shares. Total
+    /// {unstaked_balance} unstaked balance and {total_shares} staking shares`
+    Unstaking {
+        account_id: String,
+        amount: u128,
+        shares: u128,
+        unstaked_balance: u128,
+        total_shares: u128,
+    },
+    /// `Epoch {epoch_height}: Contract received total rewards of {rewards} tokens. New total
+    /// staked balance is {total_staked_balance}. Total number of shares {total_shares}`
+    EpochRewards {
+        epoch_height: u64,
+        rewards: u128,
+        total_staked_balance: u128,
+        total_shares: u128,
+    },
+}
+
+impl StakingPoolLog {
+    pub fn parse(log: &str) -> Option<Self> {
+        let words: Vec<&str> = log.split(' ').collect();
+        let number = |index: usize| -> Option<u128> {
+            words
+                .get(index)?
+                .trim_end_matches(|c| c == '.' || c == ':')
+                .parse()
+                .ok()
+        };
+        if let Some(account_id) = words.first()?.strip_prefix('@') {
+            let account_id = account_id.to_string();
+            return match (words.get(1).copied()?, words.len()) {
+                ("deposited", 8) if log.contains(". New unstaked balance is ") => {
+                    Some(Self::Deposited {
+                        account_id,
+                        amount: number(2)?,
+                        unstaked_balance: number(7)?,
+                    })
+                }
+                ("withdrawing", 8) if log.contains(". New unstaked balance is ") => {
+                    Some(Self::Withdrawing {
+                        account_id,
+                        amount: number(2)?,
+                        unstaked_balance: number(7)?,
+                    })
+                }
+                ("staking", 16) if words[5..9] == ["new", "staking", "shares.", "Total"] => {
+                    Some(Self::Staking {
+                        account_id,
+                        amount: number(2)?,
+                        shares: number(4)?,
+                        unstaked_balance: number(9)?,
+                        total_shares: number(13)?,
+                    })
+                }
+                ("unstaking", 15) if words[5..8] == ["staking", "shares.", "Total"] => {
+                    Some(Self::Unstaking {
+                        account_id,
+                        amount: number(2)?,
+                        shares: number(4)?,
+                        unstaked_balance: number(8)?,
+                        total_shares: number(12)?,
+                    })
+                }
+                _ => None,
+            };
+        }
+        if words.len() == 20
+            && words[0] == "Epoch"
+            && log.contains("Contract received total rewards")
+        {
+            return Some(Self::EpochRewards {
+                epoch_height: u64::try_from(number(1)?).ok()?,
+                rewards: number(7)?,
+                total_staked_balance: number(14)?,
+                total_shares: number(19)?,
+            });
+        }
+        None
+    }
+}
@@ -0,0 +1,49 @@
+use crate::click::*;
+use crate::staking_pool_logs::StakingPoolLog;
+use std::collections::HashSet;
+
+/// Known staking pools: the pools that were ever added to the lockup whitelist (from the
+/// `whitelisted_pools` table of the `actions` mode) and the accounts that emitted the core staking
+/// pool logs since the start. Contracts that only expose staking-like methods (e.g. `wrap.near` or
+/// DeFi vaults) are not pools.
+#[derive(Default)]
+pub struct StakingPools {
+    pub pool_ids: HashSet<String>,
+}
+
+impl StakingPools {
+    /// Loads the whitelisted pools, if the `whitelisted_pools` table exists.
+    pub async fn load(db: &ClickDB) -> clickhouse::error::Result<Self> {
+        if !db
+            .tables()
+            .await?
+            .iter()
+            .any(|table| table == "whitelisted_pools")
+        {
+            return Ok(Self::default());
+        }
+        let pool_ids = db
+            .client
+            .query(&format!(
+                "SELECT DISTINCT pool_id FROM whitelisted_pools WHERE {}",
+                chain_filter()
+            ))
+            .fetch_all::<String>()
+            .await?;
+        tracing::log::info!(target: CLICKHOUSE_TARGET, "Loaded {} whitelisted staking pools", pool_ids.len());
+        Ok(Self {
+            pool_ids: pool_ids.into_iter().collect(),
+        })
+    }
+
+    pub fn contains(&self, account_id: &str) -> bool {
+        self.pool_ids.contains(account_id)
+    }
+
+    /// Adds the account if the log is a core staking pool log.
+    pub fn observe_log(&mut self, account_id: &str, log: &str) {
+        if !self.pool_ids.contains(account_id) && StakingPoolLog::parse(log).is_some() {
+            self.pool_ids.insert(account_id.to_string());
+        }
+    }
+}
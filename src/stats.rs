@@ -0,0 +1,131 @@
+use crate::click::*;
+use crate::metrics::dir_size;
+use crate::transactions::{is_memory_cache, TxCache};
+use crate::watch_list::WatchList;
+use std::env;
+use std::path::Path;
+
+use clickhouse::Row;
+use fastnear_primitives::near_primitives::types::BlockHeight;
+use serde::{Deserialize, Serialize};
+
+/// The tables and the columns whose latest block is the checkpoint of a pipeline.
+const CHECKPOINT_TABLES: [(&str, &str); 3] = [
+    ("actions", "block_height"),
+    ("blocks", "block_height"),
+    ("epochs", "end_block_height"),
+];
+
+#[derive(Row, Deserialize, Serialize)]
+pub struct TableStats {
+    pub name: String,
+    /// `None` for the tables whose engine doesn't track the rows, e.g. the views.
+    pub total_rows: Option<u64>,
+    pub total_bytes: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct Checkpoint {
+    pub table: &'static str,
+    pub block_height: BlockHeight,
+}
+
+#[derive(Serialize)]
+pub struct CacheStats {
+    pub last_block_height: BlockHeight,
+    pub transactions: usize,
+    pub shed_transactions: usize,
+    pub receipt_to_tx: usize,
+    pub data_receipts: usize,
+    /// The inclusion block of the oldest pending transaction in memory.
+    pub oldest_tx_block_height: Option<BlockHeight>,
+    pub sled_disk_size_bytes: u64,
+}
+
+#[derive(Serialize)]
+pub struct WatchListStats {
+    pub accounts: usize,
+    pub public_keys: usize,
+    pub receipt_rules: usize,
+}
+
+/// The state of the transaction cache and the database, without starting a pipeline.
+#[derive(Serialize)]
+pub struct Stats {
+    /// `None` without an existing `SLED_DB_PATH`, or with `CACHE_BACKEND=memory`.
+    pub tx_cache: Option<CacheStats>,
+    pub checkpoints: Vec<Checkpoint>,
+    pub tables: Vec<TableStats>,
+    /// `None` without the `watch_list` table.
+    pub watch_list: Option<WatchListStats>,
+}
+
+pub async fn stats(db: &ClickDB) -> anyhow::Result<Stats> {
+    let tables = db
+        .client
+        .query(
+            "SELECT ?fields FROM system.tables WHERE database = currentDatabase() ORDER BY name",
+        )
+        .fetch_all::<TableStats>()
+        .await?;
+    let mut checkpoints = vec![];
+    for (table, column) in CHECKPOINT_TABLES {
+        if tables.iter().any(|t| t.name == table) {
+            checkpoints.push(Checkpoint {
+                table,
+                block_height: db.max(column, table).await?,
+            });
+        }
+    }
+    let watch_list = if tables.iter().any(|t| t.name == "watch_list") {
+        let watch_list = WatchList::load(db).await?;
+        Some(WatchListStats {
+            accounts: watch_list.accounts.len(),
+            public_keys: watch_list.public_keys.len(),
+            receipt_rules: watch_list.receipt_rules.len(),
+        })
+    } else {
+        None
+    };
+    Ok(Stats {
+        tx_cache: cache_stats()?,
+        checkpoints,
+        tables,
+        watch_list,
+    })
+}
+
+/// Nothing is written to the cache, but sled locks the directory, so the cache can't be opened
+/// while the `transactions` command is running.
+fn cache_stats() -> anyhow::Result<Option<CacheStats>> {
+    if is_memory_cache() {
+        return Ok(None);
+    }
+    let Ok(sled_db_path) = env::var("SLED_DB_PATH") else {
+        return Ok(None);
+    };
+    if !Path::new(&sled_db_path).exists() {
+        return Ok(None);
+    }
+    let sled_db = sled::open(&sled_db_path).map_err(|err| {
+        anyhow::anyhow!(
+            "Failed to open {}, is the transactions command running? {}",
+            sled_db_path,
+            err
+        )
+    })?;
+    let tx_cache = TxCache::new(sled_db);
+    Ok(Some(CacheStats {
+        last_block_height: tx_cache.last_block_height,
+        transactions: tx_cache.transactions.len(),
+        shed_transactions: tx_cache.num_shed_transactions(),
+        receipt_to_tx: tx_cache.receipt_to_tx.len(),
+        data_receipts: tx_cache.data_receipts.len(),
+        oldest_tx_block_height: tx_cache
+            .transactions
+            .values()
+            .map(|transaction| transaction.tx_block_height)
+            .min(),
+        sled_disk_size_bytes: dir_size(Path::new(&sled_db_path))?,
+    }))
+}
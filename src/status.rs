@@ -0,0 +1,154 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use fastnear_primitives::block_with_tx_hash::BlockWithTxHashes;
+use fastnear_primitives::near_primitives::types::BlockHeight;
+use serde::{Deserialize, Serialize};
+
+pub const STATUS_TARGET: &str = "status";
+
+/// The status file is rewritten at most this often, since the blocks are processed much faster
+/// during a catch-up.
+const STATUS_WRITE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Unix seconds of the last committed batch, or 0 before the first commit.
+static LAST_COMMIT_AT: AtomicU64 = AtomicU64::new(0);
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Called by the pipelines once a batch is committed.
+pub fn record_commit() {
+    LAST_COMMIT_AT.store(unix_now(), Ordering::Relaxed);
+}
+
+/// The state of the running pipeline, written to the status file.
+#[derive(Serialize, Deserialize)]
+pub struct PipelineStatus {
+    pub command: String,
+    pub block_height: BlockHeight,
+    pub block_timestamp: u64,
+    /// The rows of the main table of the pipeline that are not committed yet.
+    pub rows_buffered: usize,
+    /// Unix seconds of the last committed batch, or 0 before the first commit.
+    pub last_commit_at: u64,
+    /// The blocks waiting in the channel from the fetcher to the pipeline.
+    pub channel_len: usize,
+    pub channel_capacity: usize,
+    /// Unix seconds of the status update.
+    pub updated_at: u64,
+}
+
+/// The status with the chain head, as printed by the `status` command.
+#[derive(Serialize)]
+pub struct StatusReport {
+    #[serde(flatten)]
+    pub status: PipelineStatus,
+    /// `None` if the latest block couldn't be fetched from neardata.
+    pub head_block_height: Option<BlockHeight>,
+    pub lag_blocks: Option<u64>,
+    pub lag_seconds: u64,
+    pub seconds_since_commit: Option<u64>,
+    /// A stale status means that the pipeline is stuck or stopped.
+    pub seconds_since_update: u64,
+}
+
+/// Keeps the status of the running pipeline in the file at `STATUS_FILE`, so the `status` command
+/// can show it without a server.
+pub struct StatusFile {
+    pub path: PathBuf,
+    pub command: String,
+    last_write: Option<Instant>,
+}
+
+impl StatusFile {
+    pub fn from_env(command: &str) -> Option<Self> {
+        let path = env::var("STATUS_FILE").ok()?;
+        Some(Self {
+            path: PathBuf::from(path),
+            command: command.to_string(),
+            last_write: None,
+        })
+    }
+
+    pub fn update(
+        &mut self,
+        block_height: BlockHeight,
+        block_timestamp: u64,
+        rows_buffered: usize,
+        channel_len: usize,
+        channel_capacity: usize,
+    ) {
+        if self
+            .last_write
+            .is_some_and(|last_write| last_write.elapsed() < STATUS_WRITE_INTERVAL)
+        {
+            return;
+        }
+        self.last_write = Some(Instant::now());
+        let status = PipelineStatus {
+            command: self.command.clone(),
+            block_height,
+            block_timestamp,
+            rows_buffered,
+            last_commit_at: LAST_COMMIT_AT.load(Ordering::Relaxed),
+            channel_len,
+            channel_capacity,
+            updated_at: unix_now(),
+        };
+        if let Err(err) = self.write(&status) {
+            tracing::log::warn!(target: STATUS_TARGET, "Failed to write {}: {}", self.path.display(), err);
+        }
+    }
+
+    /// Replaces the file atomically, so the reader never sees a partial status.
+    fn write(&self, status: &PipelineStatus) -> anyhow::Result<()> {
+        let tmp_path = self.path.with_extension("tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec(status)?)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Reads the status file of the running pipeline and compares it with the latest final block of
+/// neardata.
+pub async fn status(path: &Path, chain_id: &str) -> anyhow::Result<StatusReport> {
+    let status: PipelineStatus = serde_json::from_slice(&std::fs::read(path)?)?;
+    let neardata_url =
+        env::var("NEARDATA_URL").unwrap_or_else(|_| format!("https://{}.neardata.xyz", chain_id));
+    let head_block_height = match fetch_last_block_height(&neardata_url).await {
+        Ok(block_height) => Some(block_height),
+        Err(err) => {
+            tracing::log::warn!(target: STATUS_TARGET, "Failed to fetch the last block: {}", err);
+            None
+        }
+    };
+    let now = unix_now();
+    Ok(StatusReport {
+        head_block_height,
+        lag_blocks: head_block_height
+            .map(|head_block_height| head_block_height.saturating_sub(status.block_height)),
+        lag_seconds: now.saturating_sub(status.block_timestamp / 1_000_000_000),
+        seconds_since_commit: (status.last_commit_at > 0)
+            .then(|| now.saturating_sub(status.last_commit_at)),
+        seconds_since_update: now.saturating_sub(status.updated_at),
+        status,
+    })
+}
+
+async fn fetch_last_block_height(neardata_url: &str) -> anyhow::Result<BlockHeight> {
+    let block = reqwest::Client::new()
+        .get(format!("{}/v0/last_block/final", neardata_url))
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<BlockWithTxHashes>()
+        .await?;
+    Ok(block.block.header.height)
+}
@@ -0,0 +1,106 @@
+#[cfg(target_os = "linux")]
+use std::env;
+#[cfg(target_os = "linux")]
+use std::os::linux::net::SocketAddrExt;
+#[cfg(target_os = "linux")]
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+#[cfg(target_os = "linux")]
+use std::sync::{Mutex, OnceLock};
+#[cfg(target_os = "linux")]
+use std::time::{Duration, Instant};
+
+use fastnear_primitives::near_primitives::types::BlockHeight;
+
+pub const SYSTEMD_TARGET: &str = "systemd";
+
+/// Notifies systemd with the `sd_notify` protocol, when the service runs with `Type=notify`. With
+/// `WatchdogSec=`, the watchdog is only pinged when a block is processed, so systemd restarts the
+/// indexer that stops making progress, e.g. with a stuck fetcher or a deadlocked commit.
+#[cfg(target_os = "linux")]
+pub struct Systemd {
+    socket: UnixDatagram,
+    addr: SocketAddr,
+    /// Half of `WATCHDOG_USEC`, as recommended by `sd_watchdog_enabled`.
+    watchdog_interval: Option<Duration>,
+    last_watchdog_ping: Mutex<Option<Instant>>,
+}
+
+#[cfg(target_os = "linux")]
+impl Systemd {
+    pub fn get() -> Option<&'static Self> {
+        static SYSTEMD: OnceLock<Option<Systemd>> = OnceLock::new();
+        SYSTEMD.get_or_init(Self::from_env).as_ref()
+    }
+
+    /// Returns `None` without `NOTIFY_SOCKET`, i.e. when not started by systemd.
+    fn from_env() -> Option<Self> {
+        let path = env::var("NOTIFY_SOCKET").ok()?;
+        // A leading `@` is a socket in the abstract namespace.
+        let addr = match path.strip_prefix('@') {
+            Some(name) => SocketAddr::from_abstract_name(name.as_bytes()),
+            None => SocketAddr::from_pathname(&path),
+        }
+        .unwrap_or_else(|err| panic!("Invalid NOTIFY_SOCKET {}: {}", path, err));
+        let socket = UnixDatagram::unbound().expect("Failed to create the notify socket");
+        // The watchdog may be meant for another process, e.g. a wrapper script.
+        let is_watchdog_pid = env::var("WATCHDOG_PID")
+            .map(|pid| pid == std::process::id().to_string())
+            .unwrap_or(true);
+        let watchdog_interval = env::var("WATCHDOG_USEC")
+            .ok()
+            .filter(|_| is_watchdog_pid)
+            .map(|usec| Duration::from_micros(usec.parse().expect("Invalid WATCHDOG_USEC")) / 2);
+        tracing::log::info!(target: SYSTEMD_TARGET, "Notifying systemd at {} with the watchdog interval {:?}", path, watchdog_interval);
+        Some(Self {
+            socket,
+            addr,
+            watchdog_interval,
+            last_watchdog_ping: Mutex::new(None),
+        })
+    }
+
+    fn notify(&self, state: &str) {
+        if let Err(err) = self.socket.send_to_addr(state.as_bytes(), &self.addr) {
+            tracing::log::warn!(target: SYSTEMD_TARGET, "Failed to notify {}: {}", state, err);
+        }
+    }
+
+    /// The pipeline is started and about to process the blocks.
+    pub fn notify_ready(&self) {
+        self.notify("READY=1");
+    }
+
+    pub fn notify_stopping(&self) {
+        self.notify("STOPPING=1");
+    }
+
+    /// Pings the watchdog at most every half of its timeout.
+    pub fn notify_block(&self, block_height: BlockHeight) {
+        let Some(watchdog_interval) = self.watchdog_interval else {
+            return;
+        };
+        let mut last_watchdog_ping = self.last_watchdog_ping.lock().unwrap();
+        if last_watchdog_ping.is_some_and(|last_ping| last_ping.elapsed() < watchdog_interval) {
+            return;
+        }
+        *last_watchdog_ping = Some(Instant::now());
+        self.notify(&format!("WATCHDOG=1\nSTATUS=Processed block #{}", block_height));
+    }
+}
+
+/// systemd only runs on Linux, so the notifications are no-ops elsewhere.
+#[cfg(not(target_os = "linux"))]
+pub struct Systemd;
+
+#[cfg(not(target_os = "linux"))]
+impl Systemd {
+    pub fn get() -> Option<&'static Self> {
+        None
+    }
+
+    pub fn notify_ready(&self) {}
+
+    pub fn notify_stopping(&self) {}
+
+    pub fn notify_block(&self, _block_height: BlockHeight) {}
+}
@@ -1,67 +1,88 @@
 use crate::*;
 use std::collections::{HashMap, HashSet};
 use std::env;
+use std::path::Path;
 use std::str::FromStr;
+use std::time::{Duration, Instant};
 
 use clickhouse::Row;
 use fastnear_primitives::near_indexer_primitives::IndexerTransactionWithOutcome;
 use fastnear_primitives::near_primitives::borsh::BorshDeserialize;
+use fastnear_primitives::near_primitives::errors::TxExecutionError;
 use fastnear_primitives::near_primitives::hash::CryptoHash;
 use fastnear_primitives::near_primitives::types::{AccountId, BlockHeight};
 use fastnear_primitives::near_primitives::views::{
-    ActionView, ReceiptEnumView, SignedTransactionView,
+    ActionView, ExecutionStatusView, ReceiptEnumView, SignedTransactionView,
 };
 use fastnear_primitives::near_primitives::{borsh, views};
 
+use crate::abi::AbiRegistry;
+use crate::account_args::AccountArgs;
+use crate::actions::{extract_args_json, ActionKind, ReceiptStatus, STAKING_POOL_AMOUNT_METHODS};
+use crate::alerts::{ActionAmount, AlertCandidate, AlertEvent, Alerts};
+use crate::blob_storage::BlobStorage;
+use crate::blocks::BlockRow;
+use crate::implicit::AccountKind;
+use crate::staking_pools::StakingPools;
+use crate::metrics::Metrics;
+use crate::trim::TrimPolicy;
+use crate::tx_cache_snapshots::{TxCacheSnapshots, TX_CACHE_SNAPSHOTS_TARGET};
+use crate::tx_wal::TxWal;
 use crate::types::{BlockInfo, ImprovedExecutionOutcome, ImprovedExecutionOutcomeWithReceipt};
+use crate::verify::Verifier;
+use crate::watch_list::{WatchList, WatchListRow};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use serde_repr::{Deserialize_repr, Serialize_repr};
+use tracing::Instrument;
 
 const LAST_BLOCK_HEIGHT_KEY: &str = "last_block_height";
 
 const RECEIPT_TO_TX_KEY: &str = "receipt_to_tx";
 const DATA_RECEIPTS_KEY: &str = "data_receipts";
 const TRANSACTIONS_KEY: &str = "transactions";
+const SHED_TRANSACTION_KEY_PREFIX: &str = "shed_transaction:";
+const EVICTED_RECEIPT_IDS_KEY: &str = "evicted_receipt_ids";
+/// The magic number of the zstd frames. JSON values never start with it.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
 
 const EVENT_JSON_PREFIX: &str = "EVENT_JSON:";
-
-const POTENTIAL_ACCOUNT_ARGS: [&str; 19] = [
-    "receiver_id",
-    "account_id",
-    "sender_id",
-    "new_account_id",
-    "predecessor_account_id",
-    "contract_id",
-    "owner_id",
-    "token_owner_id",
-    "nft_contract_id",
-    "token_account_id",
-    "creator_id",
-    "referral_id",
-    "previous_owner_id",
-    "seller_id",
-    "buyer_id",
-    "user_id",
-    "beneficiary_id",
-    "staking_pool_account_id",
-    "owner_account_id",
-];
-
-const POTENTIAL_EVENTS_ARGS: [&str; 10] = [
-    "account_id",
-    "owner_id",
-    "old_owner_id",
-    "new_owner_id",
-    "payer_id",
-    "farmer_id",
-    "validator_id",
-    "liquidation_account_id",
-    "contract_id",
-    "nft_contract_id",
+const FT_EVENT_STANDARD: &str = "nep141";
+
+const ZSTD_COMPRESSION_LEVEL: i32 = 3;
+
+const MAX_SEARCH_TEXT_LENGTH: usize = 8192;
+
+const NANOSECONDS_PER_DAY: u64 = 86_400_000_000_000;
+
+const DEFAULT_SLOW_BLOCK_THRESHOLD_MS: u64 = 1000;
+
+/// Pending transactions without new receipts for this many blocks can be shed from memory.
+const STALE_PENDING_BLOCKS: u64 = 100;
+const MAX_SHED_TRANSACTIONS: usize = 10000;
+/// Blocks to wait after relieving the memory pressure, since the allocator may not return the
+/// memory right away.
+const MEMORY_GUARD_COOLDOWN_BLOCKS: u64 = 100;
+/// Data receipts are normally matched within a few blocks, but a yielded promise can wait for its
+/// data for a while.
+const DEFAULT_ORPHAN_DATA_RECEIPT_MAX_AGE_BLOCKS: u64 = 10000;
+
+/// Contracts that execute intents signed by the users and submitted by solvers or relayers.
+const INTENT_CONTRACTS: [&str; 1] = ["intents.near"];
+const EXECUTE_INTENTS_METHOD: &str = "execute_intents";
+
+const STAKING_METHODS: [&str; 8] = [
+    "deposit_and_stake",
+    "stake",
+    "stake_all",
+    "unstake",
+    "unstake_all",
+    "withdraw",
+    "withdraw_all",
+    "deposit",
 ];
 
-#[allow(dead_code)]
 #[derive(Deserialize)]
 pub struct EventJson {
     pub version: String,
@@ -70,24 +91,90 @@ pub struct EventJson {
     pub data: Vec<Value>,
 }
 
-#[derive(Row, Serialize)]
+#[derive(Row, Serialize, Deserialize)]
 pub struct TransactionRow {
     pub transaction_hash: String,
     pub signer_id: String,
+    pub real_sender_id: String,
     pub tx_block_height: u64,
     pub tx_block_hash: String,
     pub tx_block_timestamp: u64,
-    pub transaction: String,
+    #[serde(with = "serde_bytes")]
+    pub transaction: Vec<u8>,
+    pub transaction_encoding: TransactionEncoding,
+    pub transaction_blob_key: Option<String>,
     pub last_block_height: u64,
+    pub deposit: u128,
+    pub gas_burnt: u64,
+    pub tokens_burnt: u128,
+    pub status: ReceiptStatus,
+    pub failure_kind: Option<String>,
+    pub failed_receipt_id: Option<String>,
+    pub tags: Vec<String>,
+}
+
+#[derive(Row, Deserialize)]
+pub struct TransactionBlobRow {
+    #[serde(with = "serde_bytes")]
+    pub transaction: Vec<u8>,
+    pub transaction_encoding: TransactionEncoding,
+    pub transaction_blob_key: Option<String>,
+}
+
+/// How the transaction view is stored in the `transaction` column, configured with
+/// `TRANSACTION_ENCODING` env var.
+#[derive(Copy, Clone, Debug, Serialize_repr, Deserialize_repr, PartialEq)]
+#[repr(u8)]
+pub enum TransactionEncoding {
+    /// Plain JSON (default).
+    Json = 1,
+    /// Zstd-compressed JSON.
+    Zstd = 2,
+}
+
+impl FromStr for TransactionEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "zstd" => Ok(Self::Zstd),
+            _ => Err(format!("Unknown transaction encoding: {}", s)),
+        }
+    }
+}
+
+impl TransactionEncoding {
+    pub fn encode(&self, transaction: &TransactionView) -> Vec<u8> {
+        let json = serde_json::to_vec(transaction).unwrap();
+        match self {
+            TransactionEncoding::Json => json,
+            TransactionEncoding::Zstd => {
+                zstd::encode_all(json.as_slice(), ZSTD_COMPRESSION_LEVEL).unwrap()
+            }
+        }
+    }
+
+    pub fn decode(&self, data: &[u8]) -> anyhow::Result<TransactionView> {
+        Ok(match self {
+            TransactionEncoding::Json => serde_json::from_slice(data)?,
+            TransactionEncoding::Zstd => serde_json::from_slice(&zstd::decode_all(data)?)?,
+        })
+    }
 }
 
-#[derive(Row, Serialize)]
+#[derive(Row, Serialize, Deserialize)]
 pub struct AccountTxRow {
     pub account_id: String,
+    pub account_kind: AccountKind,
     pub transaction_hash: String,
     pub signer_id: String,
+    pub real_sender_id: String,
     pub tx_block_height: u64,
     pub tx_block_timestamp: u64,
+    pub methods: Vec<String>,
+    pub action_kinds: Vec<ActionKind>,
+    pub tags: Vec<String>,
 }
 
 #[derive(Row, Serialize, Deserialize, Clone, Debug)]
@@ -100,29 +187,212 @@ pub struct BlockTxRow {
     pub tx_block_height: u64,
 }
 
-#[derive(Row, Serialize)]
+#[derive(Row, Serialize, Deserialize)]
 pub struct ReceiptTxRow {
     pub receipt_id: String,
     pub transaction_hash: String,
     pub signer_id: String,
     pub tx_block_height: u64,
     pub tx_block_timestamp: u64,
+    pub predecessor_id: String,
+    pub receiver_id: String,
 }
 
-/// Simplified block view in case there a block with no associated transactions.
-/// Also includes some extra metadata.
-#[derive(Row, Serialize, Deserialize, Clone, Debug)]
-pub struct BlockRow {
+#[derive(Row, Serialize, Deserialize)]
+pub struct TxReceiptRow {
+    pub receipt_id: String,
+    pub transaction_hash: String,
+    pub receipt_index: u16,
+    pub predecessor_id: String,
+    pub receiver_id: String,
+    pub block_height: u64,
+    pub block_timestamp: u64,
+    pub tx_block_height: u64,
+    pub status: ReceiptStatus,
+    pub methods: Vec<String>,
+    pub action_kinds: Vec<ActionKind>,
+    pub logs: Vec<String>,
+    pub gas_burnt: u64,
+    pub tokens_burnt: u128,
+}
+
+#[derive(Row, Serialize, Deserialize)]
+pub struct TxEventRow {
+    pub transaction_hash: String,
+    pub receipt_id: String,
+    pub log_index: u16,
+    pub contract_id: String,
+    pub standard: String,
+    pub version: String,
+    pub event: String,
+    pub data: String,
+    pub block_height: u64,
+    pub block_timestamp: u64,
+    pub tx_block_height: u64,
+}
+
+/// An edge of the execution tree: the transaction or a receipt created the child receipt.
+#[derive(Row, Serialize, Deserialize)]
+pub struct ReceiptEdgeRow {
+    pub transaction_hash: String,
+    /// The transaction hash for the first receipt, otherwise the parent receipt ID.
+    pub parent_id: String,
+    pub child_receipt_id: String,
+    pub block_height: u64,
+    pub block_timestamp: u64,
+    pub tx_block_height: u64,
+}
+
+#[derive(Row, Serialize, Deserialize)]
+pub struct ReceiptLogRow {
+    pub transaction_hash: String,
+    pub receipt_id: String,
+    pub account_id: String,
+    pub log_index: u16,
+    pub log: String,
+    pub is_event_json: bool,
+    pub block_height: u64,
+    pub block_timestamp: u64,
+    pub tx_block_height: u64,
+}
+
+#[derive(Row, Serialize, Deserialize)]
+pub struct TxActionRow {
+    pub transaction_hash: String,
+    pub receipt_id: String,
+    pub action_index: u16,
+    pub predecessor_id: String,
+    pub receiver_id: String,
+    pub action_kind: ActionKind,
+    pub method_name: Option<String>,
+    pub args_json: Option<String>,
+    pub deposit: u128,
+    pub gas: u64,
+    pub status: ReceiptStatus,
+    pub block_height: u64,
+    pub block_timestamp: u64,
+    pub tx_block_height: u64,
+}
+
+#[derive(Row, Serialize, Deserialize)]
+pub struct AccountDeletionRow {
+    pub account_id: String,
+    pub beneficiary_id: String,
+    pub amount: u128,
+    pub transaction_hash: String,
+    pub receipt_id: String,
+    pub block_height: u64,
+    pub block_timestamp: u64,
+    pub tx_block_height: u64,
+}
+
+/// A transaction signed with a watched public key.
+#[derive(Row, Serialize, Deserialize)]
+pub struct KeyTxRow {
+    pub public_key: String,
+    pub signer_id: String,
+    pub transaction_hash: String,
+    pub tx_block_height: u64,
+    pub tx_block_timestamp: u64,
+    pub tags: Vec<String>,
+}
+
+/// A pending transaction that was evicted from the cache before all of its receipts arrived.
+#[derive(Row, Serialize, Deserialize)]
+pub struct StaleTransactionRow {
+    pub transaction_hash: String,
+    pub signer_id: String,
+    pub tx_block_height: u64,
+    pub tx_block_timestamp: u64,
+    /// The last block with a receipt of the transaction.
+    pub last_block_height: u64,
+    pub evicted_block_height: u64,
+    pub pending_receipt_ids: Vec<String>,
+    /// The JSON of the partial transaction view.
+    pub transaction: String,
+}
+
+#[derive(Copy, Clone, Debug, Serialize_repr, Deserialize_repr, PartialEq)]
+#[repr(u8)]
+pub enum SearchTextKind {
+    Args = 1,
+    Log = 2,
+}
+
+#[derive(Row, Serialize, Deserialize)]
+pub struct TxSearchRow {
+    pub transaction_hash: String,
+    pub receipt_id: String,
+    pub account_id: String,
+    pub kind: SearchTextKind,
+    pub index: u16,
+    pub text: String,
+    pub block_height: u64,
+    pub block_timestamp: u64,
+}
+
+/// A transaction of a watched account or a staking pool on the day. The daily counters of the
+/// `account_daily_stats` view are aggregated from these rows, so a replayed transaction replaces
+/// its row instead of being counted twice.
+#[derive(Row, Serialize, Deserialize)]
+pub struct AccountDailyTxRow {
+    pub account_id: String,
+    pub date: u16,
+    pub transaction_hash: String,
+    pub is_signer: bool,
+    /// The gas, the tokens burnt and the deposit are only counted for the signer.
+    pub gas_burnt: u64,
+    pub tokens_burnt: u128,
+    pub deposit: u128,
+    pub is_staking_op: bool,
+}
+
+/// The first and the last transaction of a watched account. Rows are aggregated within a commit
+/// batch and merged by the `AggregatingMergeTree` engine across batches.
+#[derive(Row, Serialize, Deserialize)]
+pub struct AccountActivityRow {
+    pub account_id: String,
+    pub first_tx_block_height: u64,
+    pub first_tx_block_timestamp: u64,
+    pub last_tx_block_height: u64,
+    pub last_tx_block_timestamp: u64,
+}
+
+impl AccountActivityRow {
+    fn merge(&mut self, other: &AccountActivityRow) {
+        if other.first_tx_block_height < self.first_tx_block_height {
+            self.first_tx_block_height = other.first_tx_block_height;
+            self.first_tx_block_timestamp = other.first_tx_block_timestamp;
+        }
+        if other.last_tx_block_height > self.last_tx_block_height {
+            self.last_tx_block_height = other.last_tx_block_height;
+            self.last_tx_block_timestamp = other.last_tx_block_timestamp;
+        }
+    }
+}
+
+#[derive(Row, Serialize, Deserialize)]
+pub struct TokenMovementRow {
+    pub token_id: String,
+    pub event: String,
+    pub from_account_id: Option<String>,
+    pub to_account_id: Option<String>,
+    pub amount: u128,
+    pub transaction_hash: String,
+    pub receipt_id: String,
+    pub log_index: u16,
+    pub data_index: u16,
     pub block_height: u64,
-    pub block_hash: String,
     pub block_timestamp: u64,
-    pub prev_block_height: Option<u64>,
-    pub epoch_id: String,
-    pub chunks_included: u64,
-    pub prev_block_hash: String,
-    pub author_id: String,
-    pub signature: String,
-    pub protocol_version: u32,
+    pub tx_block_height: u64,
+}
+
+#[derive(Deserialize)]
+struct FtEventData {
+    old_owner_id: Option<AccountId>,
+    new_owner_id: Option<AccountId>,
+    owner_id: Option<AccountId>,
+    amount: String,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -143,13 +413,111 @@ pub struct PendingTransaction {
     pub pending_receipt_ids: Vec<CryptoHash>,
 }
 
-#[derive(Default)]
+#[derive(Default, Serialize, Deserialize)]
 pub struct TxRows {
     pub transactions: Vec<TransactionRow>,
+    pub failed_transactions: Vec<TransactionRow>,
     pub account_txs: Vec<AccountTxRow>,
     pub block_txs: Vec<BlockTxRow>,
     pub receipt_txs: Vec<ReceiptTxRow>,
     pub blocks: Vec<BlockRow>,
+    pub token_movements: Vec<TokenMovementRow>,
+    pub tx_receipts: Vec<TxReceiptRow>,
+    pub tx_events: Vec<TxEventRow>,
+    pub tx_search: Vec<TxSearchRow>,
+    #[serde(default)]
+    pub receipt_edges: Vec<ReceiptEdgeRow>,
+    #[serde(default)]
+    pub receipt_logs: Vec<ReceiptLogRow>,
+    #[serde(default)]
+    pub tx_actions: Vec<TxActionRow>,
+    #[serde(default)]
+    pub account_deletions: Vec<AccountDeletionRow>,
+    /// Beneficiaries of the deleted watched accounts that are added to the watch list.
+    #[serde(default)]
+    pub watch_list: Vec<WatchListRow>,
+    #[serde(default)]
+    pub key_txs: Vec<KeyTxRow>,
+    #[serde(default)]
+    pub stale_transactions: Vec<StaleTransactionRow>,
+    #[serde(default)]
+    pub account_daily_txs: Vec<AccountDailyTxRow>,
+    #[serde(default)]
+    pub account_activity: HashMap<String, AccountActivityRow>,
+    /// Encoded transaction views by their blob key, to be uploaded to the blob storage.
+    pub transaction_blobs: Vec<(String, Vec<u8>)>,
+}
+
+impl TxRows {
+    /// Moves the rows of the other batch into this one, merging the account activity.
+    pub fn append(&mut self, mut other: TxRows) {
+        self.transactions.append(&mut other.transactions);
+        self.failed_transactions.append(&mut other.failed_transactions);
+        self.account_txs.append(&mut other.account_txs);
+        self.block_txs.append(&mut other.block_txs);
+        self.receipt_txs.append(&mut other.receipt_txs);
+        self.blocks.append(&mut other.blocks);
+        self.token_movements.append(&mut other.token_movements);
+        self.tx_receipts.append(&mut other.tx_receipts);
+        self.tx_events.append(&mut other.tx_events);
+        self.tx_search.append(&mut other.tx_search);
+        self.receipt_edges.append(&mut other.receipt_edges);
+        self.receipt_logs.append(&mut other.receipt_logs);
+        self.tx_actions.append(&mut other.tx_actions);
+        self.account_deletions.append(&mut other.account_deletions);
+        self.watch_list.append(&mut other.watch_list);
+        self.key_txs.append(&mut other.key_txs);
+        self.stale_transactions.append(&mut other.stale_transactions);
+        self.account_daily_txs.append(&mut other.account_daily_txs);
+        for (account_id, other_activity) in other.account_activity {
+            match self.account_activity.get_mut(&account_id) {
+                Some(activity) => activity.merge(&other_activity),
+                None => {
+                    self.account_activity.insert(account_id, other_activity);
+                }
+            }
+        }
+        self.transaction_blobs.append(&mut other.transaction_blobs);
+    }
+}
+
+impl TxReceiptRow {
+    pub fn new(
+        receipt: &ImprovedExecutionOutcomeWithReceipt,
+        tx_hash: &str,
+        receipt_index: u16,
+        tx_block_height: BlockHeight,
+    ) -> Self {
+        let outcome = &receipt.execution_outcome;
+        let mut methods = vec![];
+        let mut action_kinds = vec![];
+        add_methods_and_action_kinds(&mut methods, &mut action_kinds, &receipt.receipt);
+        Self {
+            receipt_id: receipt.receipt.receipt_id.to_string(),
+            transaction_hash: tx_hash.to_string(),
+            receipt_index,
+            predecessor_id: receipt.receipt.predecessor_id.to_string(),
+            receiver_id: receipt.receipt.receiver_id.to_string(),
+            block_height: outcome.block_height,
+            block_timestamp: outcome.block_timestamp,
+            tx_block_height,
+            status: receipt_status(&outcome.outcome.status),
+            methods,
+            action_kinds,
+            logs: outcome.outcome.logs.clone(),
+            gas_burnt: outcome.outcome.gas_burnt,
+            tokens_burnt: outcome.outcome.tokens_burnt,
+        }
+    }
+}
+
+fn receipt_status(status: &ExecutionStatusView) -> ReceiptStatus {
+    match status {
+        ExecutionStatusView::Unknown | ExecutionStatusView::Failure(_) => ReceiptStatus::Failure,
+        ExecutionStatusView::SuccessValue(_) | ExecutionStatusView::SuccessReceiptId(_) => {
+            ReceiptStatus::Success
+        }
+    }
 }
 
 impl PendingTransaction {
@@ -158,34 +526,208 @@ impl PendingTransaction {
     }
 }
 
+/// How to store transactions that failed, configured with `FAILED_TRANSACTIONS` env var.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FailedTransactionsMode {
+    /// Store failed transactions in the `transactions` table (default).
+    Store,
+    /// Store failed transactions in the `failed_transactions` table.
+    Separate,
+    /// Don't store failed transactions at all, including `account_txs`, `block_txs` and
+    /// `receipt_txs` rows.
+    Skip,
+}
+
+impl FromStr for FailedTransactionsMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "store" => Ok(Self::Store),
+            "separate" => Ok(Self::Separate),
+            "skip" => Ok(Self::Skip),
+            _ => Err(format!("Unknown failed transactions mode: {}", s)),
+        }
+    }
+}
+
 pub struct TransactionsData {
     pub commit_every_block: bool,
+    pub store_token_movements: bool,
+    pub store_tx_receipts: bool,
+    pub store_tx_events: bool,
+    pub store_tx_search: bool,
+    pub store_receipt_edges: bool,
+    pub store_receipt_logs: bool,
+    pub store_tx_actions: bool,
+    pub failed_transactions: FailedTransactionsMode,
+    pub transaction_encoding: TransactionEncoding,
+    pub trim_policy: Option<TrimPolicy>,
+    pub blob_storage: Option<BlobStorage>,
+    pub alerts: Option<Alerts>,
+    /// Tags of the watched accounts are written to the `account_txs` and `transactions` rows.
+    pub watch_list: WatchList,
+    /// Beneficiaries of the deleted watched accounts are added to the watch list with their tags.
+    pub watch_deletion_beneficiaries: bool,
+    /// The first and the last transactions of the watched accounts are written to
+    /// `account_activity`.
+    pub store_account_activity: bool,
+    /// The transactions of the watched accounts and the staking pools are written to
+    /// `account_daily_txs`.
+    pub store_account_daily_stats: bool,
+    pub staking_pools: StakingPools,
+    /// The transactions signed with the watched public keys are written to `key_txs`.
+    pub store_key_txs: bool,
+    /// Blocks since the inclusion, after which the pending transactions are evicted from the cache
+    /// to `stale_transactions`. It must stay above the longest legitimate transaction, since the
+    /// evicted transactions are never completed.
+    pub stale_transaction_max_age: Option<BlockHeight>,
+    /// Blocks between the sweeps of the orphaned `receipt_to_tx` and `data_receipts` entries.
+    pub cache_cleanup_interval: Option<BlockHeight>,
+    /// Blocks after which an unmatched data receipt is considered orphaned.
+    pub orphan_data_receipt_max_age: BlockHeight,
     pub tx_cache: TxCache,
+    /// `None` for the in-memory cache.
+    pub sled_db_path: Option<String>,
+    /// Blocks that take longer to process are logged with a breakdown of the stages.
+    pub slow_block_threshold: Duration,
+    /// Process RSS in bytes, over which the rows are committed early and the cache is shed.
+    pub memory_budget: Option<u64>,
+    pub last_memory_relief_block_height: Option<BlockHeight>,
     pub rows: TxRows,
-    pub commit_handlers: Vec<tokio::task::JoinHandle<Result<(), clickhouse::error::Error>>>,
+    pub tx_wal: Option<TxWal>,
+    pub commit_handlers: Vec<tokio::task::JoinHandle<anyhow::Result<()>>>,
+    pub tx_cache_snapshots: Option<TxCacheSnapshots>,
+    /// Blocks between the flushes of the cache to sled. Otherwise it's only flushed on shutdown.
+    pub sled_flush_interval: Option<BlockHeight>,
+    snapshot_upload: Option<tokio::task::JoinHandle<()>>,
 }
 
 impl TransactionsData {
     pub fn new() -> Self {
+        if is_memory_cache() {
+            return Self::with_cache(None, TxWal::from_env());
+        }
+        let sled_db_path = env::var("SLED_DB_PATH").expect("Missing SLED_DB_PATH env var");
+        let mut this = Self::with_cache(Some(sled_db_path), TxWal::from_env());
+        this.tx_cache_snapshots = TxCacheSnapshots::from_env();
+        this
+    }
+
+    /// Whether the watch list has to be loaded on start.
+    pub fn needs_watch_list(&self) -> bool {
+        env::var("WATCH_LIST_TAGS") == Ok("true".to_string())
+            || self.watch_deletion_beneficiaries
+            || self.store_account_activity
+            || self.store_account_daily_stats
+            || self.store_key_txs
+    }
+
+    /// Uses the transaction cache at the path (or only in memory without it) and the optional WAL,
+    /// e.g. to reindex a range without touching the live cache.
+    pub fn with_cache(sled_db_path: Option<String>, mut tx_wal: Option<TxWal>) -> Self {
         let commit_every_block = env::var("COMMIT_EVERY_BLOCK")
             .map(|v| v == "true")
             .unwrap_or(false);
-        let sled_db_path = env::var("SLED_DB_PATH").expect("Missing SLED_DB_PATH env var");
-        if !std::path::Path::new(&sled_db_path).exists() {
-            std::fs::create_dir_all(&sled_db_path)
-                .expect(format!("Failed to create {}", sled_db_path).as_str());
-        }
-        let sled_db = sled::open(&sled_db_path).expect("Failed to open sled_db_path");
-        let tx_cache = TxCache::new(sled_db);
+        let store_token_movements = env::var("STORE_TOKEN_MOVEMENTS")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+        let store_tx_receipts = env::var("STORE_TX_RECEIPTS")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+        let store_tx_events = env::var("STORE_TX_EVENTS")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+        let store_tx_search = env::var("STORE_TX_SEARCH")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+        let store_receipt_edges = env::var("STORE_RECEIPT_EDGES")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+        let store_receipt_logs = env::var("STORE_RECEIPT_LOGS")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+        let store_tx_actions = env::var("STORE_TX_ACTIONS")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+        let failed_transactions = env::var("FAILED_TRANSACTIONS")
+            .map(|v| v.parse().expect("Invalid FAILED_TRANSACTIONS"))
+            .unwrap_or(FailedTransactionsMode::Store);
+        let transaction_encoding = env::var("TRANSACTION_ENCODING")
+            .map(|v| v.parse().expect("Invalid TRANSACTION_ENCODING"))
+            .unwrap_or(TransactionEncoding::Json);
+        let tx_cache = match &sled_db_path {
+            Some(sled_db_path) => {
+                if !std::path::Path::new(sled_db_path).exists() {
+                    std::fs::create_dir_all(sled_db_path)
+                        .unwrap_or_else(|_| panic!("Failed to create {}", sled_db_path));
+                }
+                let sled_db = sled::open(sled_db_path).expect("Failed to open sled_db_path");
+                TxCache::new(sled_db)
+            }
+            None => TxCache::in_memory(),
+        };
+        let rows = match &mut tx_wal {
+            Some(tx_wal) => tx_wal.recover().expect("Failed to recover TX_WAL_DIR"),
+            None => TxRows::default(),
+        };
 
         Self {
             commit_every_block,
+            store_token_movements,
+            store_tx_receipts,
+            store_tx_events,
+            store_tx_search,
+            store_receipt_edges,
+            store_receipt_logs,
+            store_tx_actions,
+            failed_transactions,
+            transaction_encoding,
+            trim_policy: TrimPolicy::from_env(),
+            blob_storage: BlobStorage::from_env(),
+            alerts: None,
+            watch_list: WatchList::default(),
+            watch_deletion_beneficiaries: env::var("WATCH_DELETION_BENEFICIARIES")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            store_account_activity: env::var("STORE_ACCOUNT_ACTIVITY")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            store_account_daily_stats: env::var("STORE_ACCOUNT_DAILY_STATS")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            staking_pools: StakingPools::default(),
+            store_key_txs: env::var("STORE_KEY_TXS")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            stale_transaction_max_age: positive_env_var("STALE_TRANSACTION_MAX_AGE_BLOCKS"),
+            cache_cleanup_interval: positive_env_var("CACHE_CLEANUP_INTERVAL"),
+            orphan_data_receipt_max_age: positive_env_var("ORPHAN_DATA_RECEIPT_MAX_AGE_BLOCKS")
+                .unwrap_or(DEFAULT_ORPHAN_DATA_RECEIPT_MAX_AGE_BLOCKS),
             tx_cache,
-            rows: TxRows::default(),
+            sled_db_path,
+            slow_block_threshold: Duration::from_millis(
+                env::var("SLOW_BLOCK_THRESHOLD_MS")
+                    .map(|v| v.parse().expect("Invalid SLOW_BLOCK_THRESHOLD_MS"))
+                    .unwrap_or(DEFAULT_SLOW_BLOCK_THRESHOLD_MS),
+            ),
+            memory_budget: env::var("MEMORY_BUDGET_MB")
+                .ok()
+                .map(|v| v.parse::<u64>().expect("Invalid MEMORY_BUDGET_MB") * 1024 * 1024),
+            last_memory_relief_block_height: None,
+            rows,
+            tx_wal,
             commit_handlers: vec![],
+            tx_cache_snapshots: None,
+            sled_flush_interval: positive_env_var("SLED_FLUSH_INTERVAL"),
+            snapshot_upload: None,
         }
     }
 
+    #[tracing::instrument(
+        skip_all,
+        fields(block_height = block.block.header.height, complete_txs = tracing::field::Empty)
+    )]
     pub async fn process_block(
         &mut self,
         db: &ClickDB,
@@ -193,6 +735,7 @@ impl TransactionsData {
         last_db_block_height: BlockHeight,
         prev_block_hash: Option<CryptoHash>,
     ) -> anyhow::Result<CryptoHash> {
+        let start = Instant::now();
         let block_height = block.block.header.height;
         let block_hash = block.block.header.hash;
         let block_timestamp = block.block.header.timestamp;
@@ -205,21 +748,10 @@ impl TransactionsData {
         }
         let block_info = BlockInfo {
             block_height,
-            block_hash: block_hash.clone(),
-            block_timestamp,
-        };
-        let block_row = BlockRow {
-            block_height,
-            block_hash: block_hash.to_string(),
+            block_hash,
             block_timestamp,
-            prev_block_height: block.block.header.prev_height,
-            epoch_id: block.block.header.epoch_id.to_string(),
-            chunks_included: block.block.header.chunks_included,
-            prev_block_hash: block.block.header.prev_hash.to_string(),
-            author_id: block.block.author.to_string(),
-            signature: block.block.header.signature.to_string(),
-            protocol_version: block.block.header.latest_protocol_version,
         };
+        let block_row = BlockRow::from_block_view(&block.block);
 
         let skip_missing_receipts = block_height <= last_db_block_height;
 
@@ -228,6 +760,13 @@ impl TransactionsData {
         let mut shards = block.shards;
         for shard in &mut shards {
             if let Some(chunk) = shard.chunk.take() {
+                let _span = tracing::debug_span!(
+                    "chunk",
+                    shard_id = %shard.shard_id,
+                    txs = chunk.transactions.len(),
+                    receipts = chunk.receipts.len()
+                )
+                .entered();
                 for IndexerTransactionWithOutcome {
                     transaction,
                     outcome,
@@ -261,14 +800,23 @@ impl TransactionsData {
                             // skipping here, since we'll get one with execution
                         }
                         ReceiptEnumView::Data { data_id, .. } => {
-                            self.tx_cache.insert_data_receipt(&data_id, receipt);
+                            self.tx_cache
+                                .insert_data_receipt(&data_id, receipt, block_height);
                         }
                     }
                 }
             }
         }
 
+        let mut num_receipts = 0;
         for shard in shards {
+            num_receipts += shard.receipt_execution_outcomes.len();
+            let _span = tracing::debug_span!(
+                "match_receipts",
+                shard_id = %shard.shard_id,
+                outcomes = shard.receipt_execution_outcomes.len()
+            )
+            .entered();
             for outcome in shard.receipt_execution_outcomes {
                 let receipt = outcome.receipt;
                 let execution_outcome = outcome.execution_outcome;
@@ -276,6 +824,19 @@ impl TransactionsData {
                 let tx_hash = match self.tx_cache.get_and_remove_receipt_to_tx(&receipt_id) {
                     Some(tx_hash) => tx_hash,
                     None => {
+                        if let Some(evicted_block_height) =
+                            self.tx_cache.evicted_receipt_ids.remove(&receipt_id)
+                        {
+                            tracing::log::warn!(target: PROJECT_ID, "Skipping receipt_id {} of a transaction evicted at block {}", receipt_id, evicted_block_height);
+                            Metrics::get().evicted_receipts.inc();
+                            // The receipts it produces belong to the evicted transaction too.
+                            for receipt_id in &execution_outcome.outcome.receipt_ids {
+                                self.tx_cache
+                                    .evicted_receipt_ids
+                                    .insert(*receipt_id, evicted_block_height);
+                            }
+                            continue;
+                        }
                         if skip_missing_receipts {
                             tracing::log::warn!(target: PROJECT_ID, "Missing tx_hash for action receipt_id: {}", receipt_id);
                             continue;
@@ -365,24 +926,137 @@ impl TransactionsData {
             }
         }
 
+        let match_duration = start.elapsed();
+        let num_complete_transactions = complete_transactions.len();
+
         self.tx_cache.set_u64(LAST_BLOCK_HEIGHT_KEY, block_height);
         // self.tx_cache.flush();
+        self.maybe_remove_cache_orphans(block_height);
+        self.tx_cache.update_metrics();
+        if let Some(sled_db_path) = &self.sled_db_path {
+            if block_height % save_step() == 0 {
+                Metrics::get().set_sled_disk_size(sled_db_path);
+            }
+        }
 
+        tracing::Span::current().record("complete_txs", complete_transactions.len());
         tracing::log::info!(target: PROJECT_ID, "#{}: Complete {} transactions. Pending {}", block_height, complete_transactions.len(), self.tx_cache.stats());
 
+        let process_start = Instant::now();
         if block_height > last_db_block_height {
-            self.rows.blocks.push(block_row);
+            // Collecting the rows of the block separately to append them to the WAL.
+            let mut rows = std::mem::take(&mut self.rows);
+            self.rows.blocks.push(BlockRow {
+                oldest_pending_tx_block_height: self.tx_cache.oldest_tx_block_height(),
+                ..block_row
+            });
             for transaction in complete_transactions {
                 self.process_transaction(transaction).await?;
             }
+            self.maybe_evict_stale_transactions(block_height);
+            if let Some(tx_wal) = &mut self.tx_wal {
+                tx_wal.append(block_height, &self.rows)?;
+            }
+            rows.append(std::mem::take(&mut self.rows));
+            self.rows = rows;
+        }
+        let process_duration = process_start.elapsed();
+
+        let commit_start = Instant::now();
+        self.maybe_commit(db, block_height, block_timestamp).await?;
+        self.maybe_relieve_memory_pressure(db, block_height).await?;
+        let commit_duration = commit_start.elapsed();
+
+        let duration = start.elapsed();
+        Metrics::get().block_processing_seconds.observe(duration.as_secs_f64());
+        if duration >= self.slow_block_threshold {
+            Metrics::get().slow_blocks.inc();
+            tracing::warn!(
+                target: PROJECT_ID,
+                block_height,
+                duration_ms = duration.as_millis() as u64,
+                receipts = num_receipts,
+                complete_txs = num_complete_transactions,
+                pending_txs = self.tx_cache.transactions.len(),
+                receipt_to_tx = self.tx_cache.receipt_to_tx.len(),
+                data_receipts = self.tx_cache.data_receipts.len(),
+                match_ms = match_duration.as_millis() as u64,
+                process_ms = process_duration.as_millis() as u64,
+                commit_ms = commit_duration.as_millis() as u64,
+                "Slow block"
+            );
         }
-
-        self.maybe_commit(db, block_height).await?;
 
         Ok(block_hash)
     }
 
+    /// Evicts the pending transactions older than `STALE_TRANSACTION_MAX_AGE_BLOCKS`, e.g. the ones
+    /// whose receipts were lost with a provider gap, every `SAVE_STEP` blocks.
+    fn maybe_evict_stale_transactions(&mut self, block_height: BlockHeight) {
+        let Some(max_age) = self.stale_transaction_max_age else {
+            return;
+        };
+        if block_height % save_step() != 0 {
+            return;
+        }
+        let stale_transactions = self
+            .tx_cache
+            .evict_stale_transactions(block_height.saturating_sub(max_age), block_height);
+        if stale_transactions.is_empty() {
+            return;
+        }
+        tracing::log::warn!(target: PROJECT_ID, "#{}: Evicted {} pending transactions older than {} blocks", block_height, stale_transactions.len(), max_age);
+        Metrics::get()
+            .stale_transactions
+            .inc_by(stale_transactions.len() as u64);
+        for transaction in stale_transactions {
+            self.rows.stale_transactions.push(StaleTransactionRow {
+                transaction_hash: transaction.transaction_hash().to_string(),
+                signer_id: transaction.transaction.transaction.signer_id.to_string(),
+                tx_block_height: transaction.tx_block_height,
+                tx_block_timestamp: transaction.tx_block_timestamp,
+                last_block_height: transaction.blocks.last().unwrap().block_height,
+                evicted_block_height: block_height,
+                pending_receipt_ids: transaction
+                    .pending_receipt_ids
+                    .iter()
+                    .map(|receipt_id| receipt_id.to_string())
+                    .collect(),
+                transaction: serde_json::to_string(&transaction.transaction).unwrap(),
+            });
+        }
+    }
+
+    /// Removes the cache entries that are left behind by the skipped receipts every
+    /// `CACHE_CLEANUP_INTERVAL` blocks.
+    fn maybe_remove_cache_orphans(&mut self, block_height: BlockHeight) {
+        let Some(cache_cleanup_interval) = self.cache_cleanup_interval else {
+            return;
+        };
+        if block_height % cache_cleanup_interval != 0 {
+            return;
+        }
+        let (num_receipts, num_data_receipts) = self
+            .tx_cache
+            .remove_orphans(block_height, self.orphan_data_receipt_max_age);
+        let tx_cache_orphans = &Metrics::get().tx_cache_orphans;
+        tx_cache_orphans
+            .with_label_values(&["receipt_to_tx"])
+            .inc_by(num_receipts as u64);
+        tx_cache_orphans
+            .with_label_values(&["data_receipts"])
+            .inc_by(num_data_receipts as u64);
+        tracing::log::info!(target: PROJECT_ID, "#{}: Removed {} orphaned receipt_to_tx and {} orphaned data_receipts. Pending {}", block_height, num_receipts, num_data_receipts, self.tx_cache.stats());
+    }
+
     async fn process_transaction(&mut self, transaction: PendingTransaction) -> anyhow::Result<()> {
+        let status = final_status(&transaction.transaction);
+        if status == ReceiptStatus::Failure
+            && self.failed_transactions == FailedTransactionsMode::Skip
+        {
+            return Ok(());
+        }
+        let failure = find_failure(&transaction.transaction);
         let tx_hash = transaction.transaction_hash().to_string();
         let last_block_info = transaction.blocks.last().cloned().unwrap();
         let signer_id = transaction
@@ -391,6 +1065,11 @@ impl TransactionsData {
             .signer_id
             .clone()
             .to_string();
+        let real_sender = extract_real_sender_id(&transaction.transaction.transaction);
+        let real_sender_id = real_sender
+            .as_ref()
+            .map(|account_id| account_id.to_string())
+            .unwrap_or_else(|| signer_id.clone());
 
         for block_info in transaction.blocks {
             self.rows.block_txs.push(BlockTxRow {
@@ -404,8 +1083,21 @@ impl TransactionsData {
         }
 
         let mut accounts = HashSet::new();
+        // Tags of the receivers of the receipts that match the watch list receipt rules.
+        let mut receipt_tags: HashMap<AccountId, Vec<String>> = HashMap::new();
         accounts.insert(transaction.transaction.transaction.signer_id.clone());
-        for receipt in &transaction.transaction.receipts {
+        accounts.extend(real_sender);
+        // The events are also matched by the alerts, even if they are not stored.
+        let mut tx_events = vec![];
+        for (receipt_index, receipt) in transaction.transaction.receipts.iter().enumerate() {
+            if self.store_tx_receipts {
+                self.rows.tx_receipts.push(TxReceiptRow::new(
+                    receipt,
+                    &tx_hash,
+                    u16::try_from(receipt_index).expect("Receipt index overflow"),
+                    transaction.tx_block_height,
+                ));
+            }
             let receipt_id = receipt.receipt.receipt_id.to_string();
             self.rows.receipt_txs.push(ReceiptTxRow {
                 receipt_id,
@@ -413,9 +1105,57 @@ impl TransactionsData {
                 signer_id: signer_id.clone(),
                 tx_block_height: transaction.tx_block_height,
                 tx_block_timestamp: transaction.tx_block_timestamp,
+                predecessor_id: receipt.receipt.predecessor_id.to_string(),
+                receiver_id: receipt.receipt.receiver_id.to_string(),
             });
             add_accounts_from_receipt(&mut accounts, &receipt.receipt);
             add_accounts_from_logs(&mut accounts, &receipt.execution_outcome.outcome.logs);
+            for tag in self
+                .watch_list
+                .receipt_tags(&receipt.receipt.predecessor_id, &receipt.receipt.receiver_id)
+            {
+                let tags = receipt_tags
+                    .entry(receipt.receipt.receiver_id.clone())
+                    .or_default();
+                if !tags.contains(tag) {
+                    tags.push(tag.clone());
+                }
+            }
+            if self.store_token_movements {
+                add_token_movements_from_receipt(
+                    &mut self.rows.token_movements,
+                    receipt,
+                    &tx_hash,
+                    transaction.tx_block_height,
+                );
+            }
+            if self.store_tx_events || self.alerts.is_some() {
+                add_events_from_receipt(
+                    &mut tx_events,
+                    receipt,
+                    &tx_hash,
+                    transaction.tx_block_height,
+                );
+            }
+            if self.store_tx_search {
+                add_search_texts_from_receipt(&mut self.rows.tx_search, receipt, &tx_hash);
+            }
+            if self.store_receipt_logs {
+                add_logs_from_receipt(
+                    &mut self.rows.receipt_logs,
+                    receipt,
+                    &tx_hash,
+                    transaction.tx_block_height,
+                );
+            }
+            if self.store_tx_actions {
+                add_actions_from_receipt(
+                    &mut self.rows.tx_actions,
+                    receipt,
+                    &tx_hash,
+                    transaction.tx_block_height,
+                );
+            }
         }
         for data_receipt in &transaction.transaction.data_receipts {
             let receipt_id = data_receipt.receipt_id.to_string();
@@ -425,28 +1165,219 @@ impl TransactionsData {
                 signer_id: signer_id.clone(),
                 tx_block_height: transaction.tx_block_height,
                 tx_block_timestamp: transaction.tx_block_timestamp,
+                predecessor_id: data_receipt.predecessor_id.to_string(),
+                receiver_id: data_receipt.receiver_id.to_string(),
             });
         }
 
-        for account_id in accounts {
-            self.rows.account_txs.push(AccountTxRow {
-                account_id: account_id.to_string(),
-                transaction_hash: tx_hash.clone(),
-                signer_id: signer_id.clone(),
-                tx_block_height: transaction.tx_block_height,
-                tx_block_timestamp: transaction.tx_block_timestamp,
-            });
+        let num_account_deletions = self.rows.account_deletions.len();
+        add_account_deletions(
+            &mut self.rows.account_deletions,
+            &transaction.transaction,
+            &tx_hash,
+            transaction.tx_block_height,
+        );
+        if self.watch_deletion_beneficiaries {
+            for deletion in &self.rows.account_deletions[num_account_deletions..] {
+                let account_id = AccountId::from_str(&deletion.account_id).unwrap();
+                let beneficiary_id = AccountId::from_str(&deletion.beneficiary_id).unwrap();
+                if !self.watch_list.contains(&account_id)
+                    || self.watch_list.contains(&beneficiary_id)
+                {
+                    continue;
+                }
+                let tags = self.watch_list.tags(&account_id).to_vec();
+                tracing::log::info!(target: PROJECT_ID, "Watching {}, the beneficiary of the deleted {}", beneficiary_id, account_id);
+                self.rows.watch_list.push(WatchListRow {
+                    account_id: beneficiary_id.to_string(),
+                    muted_until: None,
+                    tags: tags.clone(),
+                });
+                self.watch_list.insert(beneficiary_id, tags);
+            }
         }
 
-        self.rows.transactions.push(TransactionRow {
+        if self.store_receipt_edges {
+            add_receipt_edges(
+                &mut self.rows.receipt_edges,
+                &transaction.transaction,
+                &tx_hash,
+                transaction.tx_block_height,
+            );
+        }
+
+        let (methods, action_kinds) = extract_methods_and_action_kinds(&transaction.transaction);
+        let signer_public_key = transaction.transaction.transaction.public_key.to_string();
+        let mut tags = {
+            let _span = tracing::debug_span!("watch_list", accounts = accounts.len()).entered();
+            let mut tags = vec![];
+            for account_id in &accounts {
+                let mut account_tags = self.watch_list.tags(account_id).to_vec();
+                for tag in receipt_tags.get(account_id).into_iter().flatten() {
+                    if !account_tags.contains(tag) {
+                        account_tags.push(tag.clone());
+                    }
+                }
+                for tag in &account_tags {
+                    if !tags.contains(tag) {
+                        tags.push(tag.clone());
+                    }
+                }
+                self.rows.account_txs.push(AccountTxRow {
+                    account_id: account_id.to_string(),
+                    account_kind: AccountKind::from(account_id),
+                    transaction_hash: tx_hash.clone(),
+                    signer_id: signer_id.clone(),
+                    real_sender_id: real_sender_id.clone(),
+                    tx_block_height: transaction.tx_block_height,
+                    tx_block_timestamp: transaction.tx_block_timestamp,
+                    methods: methods.clone(),
+                    action_kinds: action_kinds.clone(),
+                    tags: account_tags,
+                });
+            }
+            tags
+        };
+        if let Some(key_tags) = self.watch_list.key_tags(&signer_public_key) {
+            for tag in key_tags {
+                if !tags.contains(tag) {
+                    tags.push(tag.clone());
+                }
+            }
+            if self.store_key_txs {
+                self.rows.key_txs.push(KeyTxRow {
+                    public_key: signer_public_key.clone(),
+                    signer_id: signer_id.clone(),
+                    transaction_hash: tx_hash.clone(),
+                    tx_block_height: transaction.tx_block_height,
+                    tx_block_timestamp: transaction.tx_block_timestamp,
+                    tags: key_tags.to_vec(),
+                });
+            }
+        }
+
+        let deposit = total_deposit(&transaction.transaction.transaction.actions);
+        let (gas_burnt, tokens_burnt) = total_gas_and_tokens_burnt(&transaction.transaction);
+        let date = u16::try_from(transaction.tx_block_timestamp / NANOSECONDS_PER_DAY)
+            .expect("Date overflow");
+        let is_staking_op = methods
+            .iter()
+            .any(|method| STAKING_METHODS.contains(&method.as_str()));
+        if self.store_account_daily_stats {
+            for receipt in &transaction.transaction.receipts {
+                for log in &receipt.execution_outcome.outcome.logs {
+                    self.staking_pools
+                        .observe_log(receipt.receipt.receiver_id.as_str(), log);
+                }
+            }
+            for account_id in accounts.iter().filter(|account_id| {
+                self.watch_list.contains(account_id)
+                    || self.staking_pools.contains(account_id.as_str())
+            }) {
+                let is_signer = account_id.as_str() == signer_id;
+                self.rows.account_daily_txs.push(AccountDailyTxRow {
+                    account_id: account_id.to_string(),
+                    date,
+                    transaction_hash: tx_hash.clone(),
+                    is_signer,
+                    gas_burnt: if is_signer { gas_burnt } else { 0 },
+                    tokens_burnt: if is_signer { tokens_burnt } else { 0 },
+                    deposit: if is_signer { deposit } else { 0 },
+                    is_staking_op,
+                });
+            }
+        }
+        if self.store_account_activity {
+            for account_id in accounts
+                .iter()
+                .filter(|account_id| self.watch_list.contains(account_id))
+            {
+                let activity = AccountActivityRow {
+                    account_id: account_id.to_string(),
+                    first_tx_block_height: transaction.tx_block_height,
+                    first_tx_block_timestamp: transaction.tx_block_timestamp,
+                    last_tx_block_height: transaction.tx_block_height,
+                    last_tx_block_timestamp: transaction.tx_block_timestamp,
+                };
+                match self.rows.account_activity.get_mut(&activity.account_id) {
+                    Some(existing) => existing.merge(&activity),
+                    None => {
+                        self.rows
+                            .account_activity
+                            .insert(activity.account_id.clone(), activity);
+                    }
+                }
+            }
+        }
+        let mut transaction_data = match &self.trim_policy {
+            Some(trim_policy) => {
+                let mut transaction_view = transaction.transaction.clone();
+                trim_policy.apply(&mut transaction_view);
+                self.transaction_encoding.encode(&transaction_view)
+            }
+            None => self.transaction_encoding.encode(&transaction.transaction),
+        };
+        let transaction_blob_key = self.blob_storage.as_ref().map(|_| {
+            self.rows
+                .transaction_blobs
+                .push((tx_hash.clone(), std::mem::take(&mut transaction_data)));
+            tx_hash.clone()
+        });
+        let transaction_row = TransactionRow {
             transaction_hash: tx_hash.clone(),
             signer_id: signer_id.clone(),
+            real_sender_id,
             tx_block_height: transaction.tx_block_height,
             tx_block_hash: transaction.tx_block_hash.to_string(),
             tx_block_timestamp: transaction.tx_block_timestamp,
-            transaction: serde_json::to_string(&transaction.transaction).unwrap(),
+            transaction: transaction_data,
+            transaction_encoding: self.transaction_encoding,
+            transaction_blob_key,
             last_block_height: last_block_info.block_height,
-        });
+            deposit,
+            gas_burnt,
+            tokens_burnt,
+            status,
+            failure_kind: failure.as_ref().map(|(_, kind)| kind.clone()),
+            failed_receipt_id: failure.map(|(id, _)| id.to_string()),
+            tags,
+        };
+        if let Some(alerts) = &self.alerts {
+            let events: Vec<AlertEvent> = tx_events
+                .iter()
+                .map(|event| AlertEvent {
+                    contract_id: event.contract_id.clone(),
+                    standard: event.standard.clone(),
+                    version: event.version.clone(),
+                    event: event.event.clone(),
+                    data: serde_json::from_str(&event.data).unwrap(),
+                })
+                .collect();
+            let action_amounts = extract_action_amounts(&transaction.transaction);
+            alerts.process(&AlertCandidate {
+                transaction_hash: &tx_hash,
+                signer_id: &signer_id,
+                signer_public_key: &signer_public_key,
+                accounts: &accounts,
+                methods: &methods,
+                deposit,
+                action_amounts: &action_amounts,
+                status: transaction_row.status,
+                tx_block_height: transaction.tx_block_height,
+                tx_block_timestamp: transaction.tx_block_timestamp,
+                events: &events,
+            });
+        }
+        if self.store_tx_events {
+            self.rows.tx_events.extend(tx_events);
+        }
+        if transaction_row.status == ReceiptStatus::Failure
+            && self.failed_transactions == FailedTransactionsMode::Separate
+        {
+            self.rows.failed_transactions.push(transaction_row);
+        } else {
+            self.rows.transactions.push(transaction_row);
+        }
 
         // TODO: Save TX to redis
 
@@ -457,28 +1388,87 @@ impl TransactionsData {
         &mut self,
         db: &ClickDB,
         block_height: BlockHeight,
+        block_timestamp: u64,
     ) -> anyhow::Result<()> {
-        let is_round_block = block_height % SAVE_STEP == 0;
+        let is_round_block = block_height % save_step() == 0;
         if is_round_block {
             tracing::log::info!(
                 target: CLICKHOUSE_TARGET,
-                "#{}: Having {} transactions, {} account_txs, {} block_txs, {} receipts_txs, {} blocks",
+                "#{}: Having {} transactions, {} failed_transactions, {} account_txs, {} block_txs, {} receipts_txs, {} blocks, {} token_movements, {} tx_receipts, {} tx_events, {} tx_search, {} receipt_edges, {} receipt_logs, {} tx_actions, {} account_deletions, {} key_txs, {} stale_transactions, {} account_daily_txs, {} account_activity",
                 block_height,
                 self.rows.transactions.len(),
+                self.rows.failed_transactions.len(),
                 self.rows.account_txs.len(),
                 self.rows.block_txs.len(),
                 self.rows.receipt_txs.len(),
                 self.rows.blocks.len(),
+                self.rows.token_movements.len(),
+                self.rows.tx_receipts.len(),
+                self.rows.tx_events.len(),
+                self.rows.tx_search.len(),
+                self.rows.receipt_edges.len(),
+                self.rows.receipt_logs.len(),
+                self.rows.tx_actions.len(),
+                self.rows.account_deletions.len(),
+                self.rows.key_txs.len(),
+                self.rows.stale_transactions.len(),
+                self.rows.account_daily_txs.len(),
+                self.rows.account_activity.len(),
             );
         }
-        if self.rows.transactions.len() >= db.min_batch || is_round_block || self.commit_every_block
+        let is_snapshot_block = self
+            .tx_cache_snapshots
+            .as_ref()
+            .is_some_and(|tx_cache_snapshots| tx_cache_snapshots.is_snapshot_block(block_height));
+        let is_flush_block = self
+            .sled_flush_interval
+            .is_some_and(|sled_flush_interval| block_height % sled_flush_interval == 0);
+        if self.rows.transactions.len() + self.rows.failed_transactions.len()
+            >= db.effective_min_batch(block_timestamp)
+            || is_round_block
+            || is_snapshot_block
+            || is_flush_block
+            || self.commit_every_block
         {
             self.commit(db).await?;
         }
+        if is_flush_block {
+            // The cache is flushed only after the rows are committed, since a restart continues
+            // from the flushed block.
+            self.wait_for_commits().await?;
+            self.tx_cache.flush();
+        }
+        if is_snapshot_block {
+            self.snapshot_tx_cache(block_height).await?;
+        }
 
         Ok(())
     }
 
+    /// Waits for the commits, so the snapshot never misses a completed transaction, and uploads
+    /// the snapshot of the cache in the background. Skipped while the previous upload is running.
+    async fn snapshot_tx_cache(&mut self, block_height: BlockHeight) -> anyhow::Result<()> {
+        let Some(tx_cache_snapshots) = self.tx_cache_snapshots.clone() else {
+            return Ok(());
+        };
+        if self
+            .snapshot_upload
+            .as_ref()
+            .is_some_and(|snapshot_upload| !snapshot_upload.is_finished())
+        {
+            tracing::log::warn!(target: TX_CACHE_SNAPSHOTS_TARGET, "#{}: Skipping the cache snapshot, since the previous one is still uploading", block_height);
+            return Ok(());
+        }
+        self.wait_for_commits().await?;
+        let archive = self.tx_cache.export_archive()?;
+        self.snapshot_upload = Some(tokio::spawn(async move {
+            if let Err(err) = tx_cache_snapshots.upload(block_height, archive).await {
+                tracing::log::error!(target: TX_CACHE_SNAPSHOTS_TARGET, "Failed to upload the cache snapshot at block #{}: {}", block_height, err);
+            }
+        }));
+        Ok(())
+    }
+
     pub async fn commit(&mut self, db: &ClickDB) -> anyhow::Result<()> {
         let mut rows = TxRows::default();
         std::mem::swap(&mut rows, &mut self.rows);
@@ -486,10 +1476,39 @@ impl TransactionsData {
             self.commit_handlers.remove(0).await??;
         }
         let db = db.clone();
-        let handler = tokio::spawn(async move {
+        // The blobs are only referenced by the rows, since the `verify` command doesn't write.
+        let blob_storage = self
+            .blob_storage
+            .clone()
+            .filter(|_| Verifier::get().is_none());
+        let wal_segments = self
+            .tx_wal
+            .as_mut()
+            .map(|tx_wal| tx_wal.seal())
+            .unwrap_or_default();
+        let span = tracing::info_span!(
+            "commit",
+            transactions = rows.transactions.len() + rows.failed_transactions.len(),
+            blocks = rows.blocks.len()
+        );
+        let commit = async move {
+            // Uploading blobs first, so the stored rows never point to missing blobs.
+            if let Some(blob_storage) = blob_storage {
+                blob_storage
+                    .put_all(std::mem::take(&mut rows.transaction_blobs))
+                    .await?;
+            }
             if !rows.transactions.is_empty() {
                 insert_rows_with_retry(&db.client, &rows.transactions, "transactions").await?;
             }
+            if !rows.failed_transactions.is_empty() {
+                insert_rows_with_retry(
+                    &db.client,
+                    &rows.failed_transactions,
+                    "failed_transactions",
+                )
+                .await?;
+            }
             if !rows.account_txs.is_empty() {
                 insert_rows_with_retry(&db.client, &rows.account_txs, "account_txs").await?;
             }
@@ -502,26 +1521,158 @@ impl TransactionsData {
             if !rows.blocks.is_empty() {
                 insert_rows_with_retry(&db.client, &rows.blocks, "blocks").await?;
             }
+            if !rows.token_movements.is_empty() {
+                insert_rows_with_retry(&db.client, &rows.token_movements, "token_movements")
+                    .await?;
+            }
+            if !rows.tx_receipts.is_empty() {
+                insert_rows_with_retry(&db.client, &rows.tx_receipts, "tx_receipts").await?;
+            }
+            if !rows.tx_events.is_empty() {
+                insert_rows_with_retry(&db.client, &rows.tx_events, "tx_events").await?;
+            }
+            if !rows.tx_search.is_empty() {
+                insert_rows_with_retry(&db.client, &rows.tx_search, "tx_search").await?;
+            }
+            if !rows.receipt_edges.is_empty() {
+                insert_rows_with_retry(&db.client, &rows.receipt_edges, "receipt_edges").await?;
+            }
+            if !rows.receipt_logs.is_empty() {
+                insert_rows_with_retry(&db.client, &rows.receipt_logs, "receipt_logs").await?;
+            }
+            if !rows.tx_actions.is_empty() {
+                insert_rows_with_retry(&db.client, &rows.tx_actions, "tx_actions").await?;
+            }
+            if !rows.account_deletions.is_empty() {
+                insert_rows_with_retry(&db.client, &rows.account_deletions, "account_deletions")
+                    .await?;
+            }
+            if !rows.watch_list.is_empty() {
+                insert_rows_with_retry(&db.client, &rows.watch_list, "watch_list").await?;
+            }
+            if !rows.key_txs.is_empty() {
+                insert_rows_with_retry(&db.client, &rows.key_txs, "key_txs").await?;
+            }
+            if !rows.stale_transactions.is_empty() {
+                insert_rows_with_retry(&db.client, &rows.stale_transactions, "stale_transactions")
+                    .await?;
+            }
+            if !rows.account_daily_txs.is_empty() {
+                insert_rows_with_retry(&db.client, &rows.account_daily_txs, "account_daily_txs")
+                    .await?;
+            }
+            let account_activity: Vec<_> = rows.account_activity.into_values().collect();
+            if !account_activity.is_empty() {
+                insert_rows_with_retry(&db.client, &account_activity, "account_activity").await?;
+            }
             tracing::log::info!(
                 target: CLICKHOUSE_TARGET,
-                "Committed {} transactions, {} account_txs, {} block_txs, {} receipts_txs, {} blocks",
+                "Committed {} transactions, {} failed_transactions, {} account_txs, {} block_txs, {} receipts_txs, {} blocks, {} token_movements, {} tx_receipts, {} tx_events, {} tx_search, {} receipt_edges, {} receipt_logs, {} tx_actions, {} account_deletions, {} key_txs, {} stale_transactions, {} account_daily_txs, {} account_activity",
                 rows.transactions.len(),
+                rows.failed_transactions.len(),
                 rows.account_txs.len(),
                 rows.block_txs.len(),
                 rows.receipt_txs.len(),
                 rows.blocks.len(),
+                rows.token_movements.len(),
+                rows.tx_receipts.len(),
+                rows.tx_events.len(),
+                rows.tx_search.len(),
+                rows.receipt_edges.len(),
+                rows.receipt_logs.len(),
+                rows.tx_actions.len(),
+                rows.account_deletions.len(),
+                rows.key_txs.len(),
+                rows.stale_transactions.len(),
+                rows.account_daily_txs.len(),
+                account_activity.len(),
             );
-            Ok::<(), clickhouse::error::Error>(())
-        });
+            status::record_commit();
+            TxWal::remove_segments(&wal_segments)?;
+            Ok::<(), anyhow::Error>(())
+        };
+        let handler = tokio::spawn(commit.instrument(span));
         self.commit_handlers.push(handler);
 
         Ok(())
     }
 
+    /// Commits the rows early and sheds the stale pending transactions to sled when the process
+    /// RSS is over `MEMORY_BUDGET_MB`.
+    async fn maybe_relieve_memory_pressure(
+        &mut self,
+        db: &ClickDB,
+        block_height: BlockHeight,
+    ) -> anyhow::Result<()> {
+        let Some(memory_budget) = self.memory_budget else {
+            return Ok(());
+        };
+        if self
+            .last_memory_relief_block_height
+            .is_some_and(|h| block_height < h + MEMORY_GUARD_COOLDOWN_BLOCKS)
+        {
+            return Ok(());
+        }
+        let Some(rss) = common::process_rss() else {
+            return Ok(());
+        };
+        if rss <= memory_budget {
+            return Ok(());
+        }
+        tracing::log::warn!(target: PROJECT_ID, "#{}: RSS {} MB is over the budget of {} MB. Committing early and shedding the cache", block_height, rss / 1024 / 1024, memory_budget / 1024 / 1024);
+        self.commit(db).await?;
+        // Same as the flush block, the cache is flushed only after the rows are committed.
+        self.wait_for_commits().await?;
+        let num_shed = self
+            .tx_cache
+            .shed_stale_transactions(block_height, MAX_SHED_TRANSACTIONS);
+        self.tx_cache.flush();
+        tracing::log::warn!(target: PROJECT_ID, "#{}: Shed {} stale pending transactions to sled. Pending {}", block_height, num_shed, self.tx_cache.stats());
+        self.last_memory_relief_block_height = Some(block_height);
+        Ok(())
+    }
+
     pub async fn last_block_height(&mut self, db: &ClickDB) -> BlockHeight {
         let db_block = db.max("block_height", "blocks").await.unwrap_or(0);
         let cache_block = self.tx_cache.get_u64(LAST_BLOCK_HEIGHT_KEY).unwrap_or(0);
-        db_block.max(cache_block)
+        // The recovered WAL rows are committed with the next batch, so their blocks are done.
+        let wal_block = self
+            .tx_wal
+            .as_ref()
+            .and_then(|tx_wal| tx_wal.last_block_height)
+            .unwrap_or(0);
+        db_block.max(cache_block).max(wal_block)
+    }
+
+    /// The block to replay from to rebuild the cache that is not ready. The committed `blocks` row
+    /// of the checkpoint records the block of the oldest pending transaction, so the replay starts
+    /// from it, or `SAFE_CATCH_UP_OFFSET` blocks back if that's earlier.
+    pub async fn catch_up_start_block_height(
+        &self,
+        db: &ClickDB,
+        last_block_height: BlockHeight,
+    ) -> BlockHeight {
+        let from_block_height = last_block_height.saturating_sub(safe_catch_up_offset());
+        let oldest_pending_tx_block_height = db
+            .client
+            .query(&format!(
+                "SELECT oldest_pending_tx_block_height FROM blocks WHERE block_height = ? AND {}",
+                chain_filter()
+            ))
+            .bind(last_block_height)
+            .fetch_optional::<Option<u64>>()
+            .await;
+        match oldest_pending_tx_block_height {
+            Ok(Some(Some(oldest_pending_tx_block_height))) => {
+                tracing::log::info!(target: PROJECT_ID, "Oldest pending transaction at #{} was included at #{}", last_block_height, oldest_pending_tx_block_height);
+                from_block_height.min(oldest_pending_tx_block_height)
+            }
+            Ok(_) => from_block_height,
+            Err(err) => {
+                tracing::log::warn!(target: PROJECT_ID, "Failed to get the oldest pending transaction: {}", err);
+                from_block_height
+            }
+        }
     }
 
     pub fn is_cache_ready(&self, last_block_height: BlockHeight) -> bool {
@@ -531,6 +1682,13 @@ impl TransactionsData {
 
     pub async fn flush(&mut self) -> anyhow::Result<()> {
         self.tx_cache.flush();
+        if let Some(alerts) = &mut self.alerts {
+            alerts.shutdown().await;
+        }
+        self.wait_for_commits().await
+    }
+
+    async fn wait_for_commits(&mut self) -> anyhow::Result<()> {
         while let Some(handler) = self.commit_handlers.pop() {
             handler.await??;
         }
@@ -538,43 +1696,554 @@ impl TransactionsData {
     }
 }
 
-fn extract_accounts(accounts: &mut HashSet<AccountId>, value: &Value, keys: &[&str]) {
-    for arg in keys {
-        if let Some(account_id) = value.get(arg) {
-            if let Some(account_id) = account_id.as_str() {
-                if let Ok(account_id) = AccountId::from_str(account_id) {
-                    accounts.insert(account_id);
+/// Fetches the transaction from the `transactions` table (or the blob storage, if the transaction
+/// was offloaded) and decodes its view.
+pub async fn fetch_transaction(db: &ClickDB, tx_hash: &str) -> anyhow::Result<TransactionView> {
+    let row = db
+        .client
+        .query(&format!(
+            "SELECT ?fields FROM transactions FINAL WHERE transaction_hash = ? AND {}",
+            chain_filter()
+        ))
+        .bind(tx_hash)
+        .fetch_one::<TransactionBlobRow>()
+        .await?;
+    match row.transaction_blob_key {
+        Some(key) => {
+            let blob_storage = BlobStorage::from_env()
+                .ok_or_else(|| anyhow::anyhow!("BLOB_STORAGE_URL is not set"))?;
+            let data = blob_storage.get(&key).await?;
+            row.transaction_encoding.decode(&data)
+        }
+        None => row.transaction_encoding.decode(&row.transaction),
+    }
+}
+
+fn parse_event_log(log: &str) -> Option<EventJson> {
+    serde_json::from_str(log.strip_prefix(EVENT_JSON_PREFIX)?).ok()
+}
+
+/// Extracts all `EVENT_JSON` events emitted by a successful receipt.
+fn add_events_from_receipt(
+    tx_events: &mut Vec<TxEventRow>,
+    receipt: &ImprovedExecutionOutcomeWithReceipt,
+    tx_hash: &str,
+    tx_block_height: BlockHeight,
+) {
+    let outcome = &receipt.execution_outcome;
+    if matches!(
+        outcome.outcome.status,
+        ExecutionStatusView::Failure(_) | ExecutionStatusView::Unknown
+    ) {
+        return;
+    }
+    for (log_index, log) in outcome.outcome.logs.iter().enumerate() {
+        let Some(event) = parse_event_log(log) else {
+            continue;
+        };
+        tx_events.push(TxEventRow {
+            transaction_hash: tx_hash.to_string(),
+            receipt_id: receipt.receipt.receipt_id.to_string(),
+            log_index: u16::try_from(log_index).expect("Log index overflow"),
+            contract_id: receipt.receipt.receiver_id.to_string(),
+            standard: event.standard,
+            version: event.version,
+            event: event.event,
+            data: serde_json::to_string(&event.data).unwrap(),
+            block_height: outcome.block_height,
+            block_timestamp: outcome.block_timestamp,
+            tx_block_height,
+        });
+    }
+}
+
+/// Adds the successful `DeleteAccount` actions of the transaction. The balance of the deleted
+/// account is sent by a `Transfer` receipt to the beneficiary, which is produced by the receipt
+/// with the deletion.
+fn add_account_deletions(
+    account_deletions: &mut Vec<AccountDeletionRow>,
+    transaction: &TransactionView,
+    tx_hash: &str,
+    tx_block_height: BlockHeight,
+) {
+    for receipt in &transaction.receipts {
+        let ReceiptEnumView::Action { actions, .. } = &receipt.receipt.receipt else {
+            continue;
+        };
+        let outcome = &receipt.execution_outcome;
+        if matches!(
+            outcome.outcome.status,
+            ExecutionStatusView::Failure(_) | ExecutionStatusView::Unknown
+        ) {
+            continue;
+        }
+        for action in actions {
+            let ActionView::DeleteAccount { beneficiary_id } = action else {
+                continue;
+            };
+            let amount = transaction
+                .receipts
+                .iter()
+                .filter(|child| {
+                    outcome.outcome.receipt_ids.contains(&child.receipt.receipt_id)
+                        && child.receipt.receiver_id == *beneficiary_id
+                })
+                .map(|child| match &child.receipt.receipt {
+                    ReceiptEnumView::Action { actions, .. } => {
+                        actions.iter().map(action_deposit).sum::<u128>()
+                    }
+                    _ => 0,
+                })
+                .sum();
+            account_deletions.push(AccountDeletionRow {
+                account_id: receipt.receipt.receiver_id.to_string(),
+                beneficiary_id: beneficiary_id.to_string(),
+                amount,
+                transaction_hash: tx_hash.to_string(),
+                receipt_id: receipt.receipt.receipt_id.to_string(),
+                block_height: outcome.block_height,
+                block_timestamp: outcome.block_timestamp,
+                tx_block_height,
+            });
+        }
+    }
+}
+
+/// Adds every action of the receipt. Gas refunds from `system` are included. The successful
+/// deployments are tracked by the ABI registry to decode the following calls.
+fn add_actions_from_receipt(
+    tx_actions: &mut Vec<TxActionRow>,
+    receipt: &ImprovedExecutionOutcomeWithReceipt,
+    tx_hash: &str,
+    tx_block_height: BlockHeight,
+) {
+    let ReceiptEnumView::Action { actions, .. } = &receipt.receipt.receipt else {
+        return;
+    };
+    let outcome = &receipt.execution_outcome;
+    let status = receipt_status(&outcome.outcome.status);
+    for (action_index, action) in actions.iter().enumerate() {
+        if let (ActionView::DeployContract { code }, Some(abi_registry)) =
+            (action, AbiRegistry::get())
+        {
+            if status == ReceiptStatus::Success {
+                abi_registry.set_code_hash(
+                    receipt.receipt.receiver_id.as_str(),
+                    CryptoHash::hash_bytes(code).to_string(),
+                );
+            }
+        }
+        let (method_name, args_json, gas) = match action {
+            ActionView::FunctionCall {
+                method_name,
+                args,
+                gas,
+                ..
+            } => (
+                Some(method_name.clone()),
+                extract_args_json(receipt.receipt.receiver_id.as_str(), method_name, args),
+                *gas,
+            ),
+            _ => (None, None, 0),
+        };
+        tx_actions.push(TxActionRow {
+            transaction_hash: tx_hash.to_string(),
+            receipt_id: receipt.receipt.receipt_id.to_string(),
+            action_index: u16::try_from(action_index).expect("Action index overflow"),
+            predecessor_id: receipt.receipt.predecessor_id.to_string(),
+            receiver_id: receipt.receipt.receiver_id.to_string(),
+            action_kind: ActionKind::from(action),
+            method_name,
+            args_json,
+            deposit: action_deposit(action),
+            gas,
+            status,
+            block_height: outcome.block_height,
+            block_timestamp: outcome.block_timestamp,
+            tx_block_height,
+        });
+    }
+}
+
+/// Adds every log line of the receipt, including the failed receipts.
+fn add_logs_from_receipt(
+    receipt_logs: &mut Vec<ReceiptLogRow>,
+    receipt: &ImprovedExecutionOutcomeWithReceipt,
+    tx_hash: &str,
+    tx_block_height: BlockHeight,
+) {
+    let outcome = &receipt.execution_outcome;
+    for (log_index, log) in outcome.outcome.logs.iter().enumerate() {
+        receipt_logs.push(ReceiptLogRow {
+            transaction_hash: tx_hash.to_string(),
+            receipt_id: receipt.receipt.receipt_id.to_string(),
+            account_id: receipt.receipt.receiver_id.to_string(),
+            log_index: u16::try_from(log_index).expect("Log index overflow"),
+            log: log.clone(),
+            is_event_json: log.starts_with(EVENT_JSON_PREFIX),
+            block_height: outcome.block_height,
+            block_timestamp: outcome.block_timestamp,
+            tx_block_height,
+        });
+    }
+}
+
+/// Adds the edges from the transaction to its first receipt and from every receipt to the
+/// receipts it created.
+fn add_receipt_edges(
+    receipt_edges: &mut Vec<ReceiptEdgeRow>,
+    transaction: &TransactionView,
+    tx_hash: &str,
+    tx_block_height: BlockHeight,
+) {
+    let outcomes = std::iter::once(&transaction.execution_outcome).chain(
+        transaction
+            .receipts
+            .iter()
+            .map(|receipt| &receipt.execution_outcome),
+    );
+    for outcome in outcomes {
+        for child_receipt_id in &outcome.outcome.receipt_ids {
+            receipt_edges.push(ReceiptEdgeRow {
+                transaction_hash: tx_hash.to_string(),
+                parent_id: outcome.id.to_string(),
+                child_receipt_id: child_receipt_id.to_string(),
+                block_height: outcome.block_height,
+                block_timestamp: outcome.block_timestamp,
+                tx_block_height,
+            });
+        }
+    }
+}
+
+/// Extracts UTF-8 function call args and logs of the receipt for the full-text search.
+fn add_search_texts_from_receipt(
+    tx_search: &mut Vec<TxSearchRow>,
+    receipt: &ImprovedExecutionOutcomeWithReceipt,
+    tx_hash: &str,
+) {
+    let outcome = &receipt.execution_outcome;
+    let mut push = |kind: SearchTextKind, index: usize, text: &str| {
+        if text.is_empty() {
+            return;
+        }
+        tx_search.push(TxSearchRow {
+            transaction_hash: tx_hash.to_string(),
+            receipt_id: receipt.receipt.receipt_id.to_string(),
+            account_id: receipt.receipt.receiver_id.to_string(),
+            kind,
+            index: u16::try_from(index).expect("Search text index overflow"),
+            text: truncate_text(text, MAX_SEARCH_TEXT_LENGTH).to_string(),
+            block_height: outcome.block_height,
+            block_timestamp: outcome.block_timestamp,
+        });
+    };
+    if let ReceiptEnumView::Action { actions, .. } = &receipt.receipt.receipt {
+        for (action_index, action) in actions.iter().enumerate() {
+            if let ActionView::FunctionCall { args, .. } = action {
+                if let Ok(args) = std::str::from_utf8(args) {
+                    push(SearchTextKind::Args, action_index, args);
                 }
             }
         }
     }
+    for (log_index, log) in outcome.outcome.logs.iter().enumerate() {
+        push(SearchTextKind::Log, log_index, log);
+    }
+}
+
+fn truncate_text(text: &str, max_length: usize) -> &str {
+    if text.len() <= max_length {
+        return text;
+    }
+    let mut end = max_length;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
 }
 
 fn add_accounts_from_logs(accounts: &mut HashSet<AccountId>, logs: &[String]) {
     for log in logs {
-        if log.starts_with(EVENT_JSON_PREFIX) {
-            let event_json = &log[EVENT_JSON_PREFIX.len()..];
-            if let Ok(event) = serde_json::from_str::<EventJson>(event_json) {
+        match parse_event_log(log) {
+            Some(event) => {
                 for data in &event.data {
-                    extract_accounts(accounts, data, &POTENTIAL_EVENTS_ARGS);
+                    AccountArgs::get().extract_from_event(accounts, data);
+                }
+            }
+            None => AccountArgs::get().extract_from_log(accounts, log),
+        }
+    }
+}
+
+/// Returns unique function call method names and action kinds across all receipts of the
+/// transaction, in the order they were first seen.
+fn extract_methods_and_action_kinds(
+    transaction: &TransactionView,
+) -> (Vec<String>, Vec<ActionKind>) {
+    let mut methods = vec![];
+    let mut action_kinds = vec![];
+    for receipt in &transaction.receipts {
+        add_methods_and_action_kinds(&mut methods, &mut action_kinds, &receipt.receipt);
+    }
+    (methods, action_kinds)
+}
+
+fn add_methods_and_action_kinds(
+    methods: &mut Vec<String>,
+    action_kinds: &mut Vec<ActionKind>,
+    receipt: &views::ReceiptView,
+) {
+    if let ReceiptEnumView::Action { actions, .. } = &receipt.receipt {
+        for action in actions {
+            let action_kind = ActionKind::from(action);
+            if !action_kinds.contains(&action_kind) {
+                action_kinds.push(action_kind);
+            }
+            if let ActionView::FunctionCall { method_name, .. } = action {
+                if !methods.contains(method_name) {
+                    methods.push(method_name.clone());
                 }
             }
         }
     }
 }
 
+/// Returns the attached deposits and the `amount` args of the actions of all receipts. The `amount`
+/// args of the staking pool methods are in yoctoNEAR, the other ones are token amounts. Gas refunds
+/// from `system` are excluded.
+fn extract_action_amounts(transaction: &TransactionView) -> Vec<ActionAmount> {
+    let mut amounts = vec![];
+    for receipt in &transaction.receipts {
+        if receipt.receipt.predecessor_id.as_str() == "system" {
+            continue;
+        }
+        let ReceiptEnumView::Action { actions, .. } = &receipt.receipt.receipt else {
+            continue;
+        };
+        let mut push = |method_name: Option<&String>, amount: u128, is_token: bool| {
+            amounts.push(ActionAmount {
+                predecessor_id: receipt.receipt.predecessor_id.clone(),
+                receiver_id: receipt.receipt.receiver_id.clone(),
+                method_name: method_name.cloned(),
+                amount,
+                is_token,
+            })
+        };
+        for action in actions {
+            match action {
+                ActionView::Transfer { deposit } => push(None, *deposit, false),
+                ActionView::FunctionCall {
+                    method_name,
+                    args,
+                    deposit,
+                    ..
+                } => {
+                    if *deposit > 0 {
+                        push(Some(method_name), *deposit, false);
+                    }
+                    let amount = serde_json::from_slice::<Value>(args)
+                        .ok()
+                        .and_then(|args| args.get("amount")?.as_str()?.parse().ok());
+                    if let Some(amount) = amount {
+                        let is_token =
+                            !STAKING_POOL_AMOUNT_METHODS.contains(&method_name.as_str());
+                        push(Some(method_name), amount, is_token);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    amounts
+}
+
+/// Returns the user behind a relayed transaction: the sender of a meta-transaction (NEP-366), or
+/// the single signer of the NEP-413 intents executed on an intent contract. Returns `None` if the
+/// transaction is not relayed or the signers are ambiguous.
+fn extract_real_sender_id(transaction: &SignedTransactionView) -> Option<AccountId> {
+    for action in &transaction.actions {
+        if let ActionView::Delegate {
+            delegate_action, ..
+        } = action
+        {
+            return Some(delegate_action.sender_id.clone());
+        }
+    }
+    if !INTENT_CONTRACTS.contains(&transaction.receiver_id.as_str()) {
+        return None;
+    }
+    let mut signer_ids = HashSet::new();
+    for action in &transaction.actions {
+        let ActionView::FunctionCall {
+            method_name, args, ..
+        } = action
+        else {
+            continue;
+        };
+        if method_name != EXECUTE_INTENTS_METHOD {
+            continue;
+        }
+        let args = serde_json::from_slice::<Value>(args).ok()?;
+        for intent in args.get("signed")?.as_array()? {
+            if intent.get("standard").and_then(Value::as_str) != Some("nep413") {
+                return None;
+            }
+            let message = intent.pointer("/payload/message")?.as_str()?;
+            let message = serde_json::from_str::<Value>(message).ok()?;
+            signer_ids.insert(AccountId::from_str(message.get("signer_id")?.as_str()?).ok()?);
+        }
+    }
+    if signer_ids.len() == 1 {
+        signer_ids.into_iter().next()
+    } else {
+        None
+    }
+}
+
+/// Returns the total deposit attached to the transaction actions.
+fn total_deposit(actions: &[ActionView]) -> u128 {
+    actions.iter().map(action_deposit).sum()
+}
+
+fn action_deposit(action: &ActionView) -> u128 {
+    match action {
+        ActionView::Transfer { deposit } => *deposit,
+        ActionView::FunctionCall { deposit, .. } => *deposit,
+        _ => 0,
+    }
+}
+
+/// Returns the total gas and tokens burnt by the transaction and all its receipts.
+fn total_gas_and_tokens_burnt(transaction: &TransactionView) -> (u64, u128) {
+    let mut gas_burnt = transaction.execution_outcome.outcome.gas_burnt;
+    let mut tokens_burnt = transaction.execution_outcome.outcome.tokens_burnt;
+    for receipt in &transaction.receipts {
+        gas_burnt += receipt.execution_outcome.outcome.gas_burnt;
+        tokens_burnt += receipt.execution_outcome.outcome.tokens_burnt;
+    }
+    (gas_burnt, tokens_burnt)
+}
+
+/// Extracts fungible token transfers, mints and burns from the NEP-141 events of a successful
+/// receipt.
+fn add_token_movements_from_receipt(
+    token_movements: &mut Vec<TokenMovementRow>,
+    receipt: &ImprovedExecutionOutcomeWithReceipt,
+    tx_hash: &str,
+    tx_block_height: BlockHeight,
+) {
+    let outcome = &receipt.execution_outcome;
+    if matches!(
+        outcome.outcome.status,
+        ExecutionStatusView::Failure(_) | ExecutionStatusView::Unknown
+    ) {
+        return;
+    }
+    for (log_index, log) in outcome.outcome.logs.iter().enumerate() {
+        let Some(event) = parse_event_log(log) else {
+            continue;
+        };
+        if event.standard != FT_EVENT_STANDARD {
+            continue;
+        }
+        for (data_index, data) in event.data.into_iter().enumerate() {
+            let Ok(data) = serde_json::from_value::<FtEventData>(data) else {
+                continue;
+            };
+            let Ok(amount) = data.amount.parse::<u128>() else {
+                continue;
+            };
+            let (from_account_id, to_account_id) = match event.event.as_str() {
+                "ft_transfer" => (data.old_owner_id, data.new_owner_id),
+                "ft_mint" => (None, data.owner_id),
+                "ft_burn" => (data.owner_id, None),
+                _ => continue,
+            };
+            token_movements.push(TokenMovementRow {
+                token_id: receipt.receipt.receiver_id.to_string(),
+                event: event.event.clone(),
+                from_account_id: from_account_id.map(|account_id| account_id.to_string()),
+                to_account_id: to_account_id.map(|account_id| account_id.to_string()),
+                amount,
+                transaction_hash: tx_hash.to_string(),
+                receipt_id: receipt.receipt.receipt_id.to_string(),
+                log_index: u16::try_from(log_index).expect("Log index overflow"),
+                data_index: u16::try_from(data_index).expect("Data index overflow"),
+                block_height: outcome.block_height,
+                block_timestamp: outcome.block_timestamp,
+                tx_block_height,
+            });
+        }
+    }
+}
+
+/// Returns the final status of the transaction, following the transaction outcome through the
+/// `SuccessReceiptId` chain to the last receipt. A failed receipt outside of the chain, e.g. a
+/// cross-contract call that is handled by a callback, doesn't fail the transaction.
+fn final_status(transaction: &TransactionView) -> ReceiptStatus {
+    let mut status = &transaction.execution_outcome.outcome.status;
+    // The chain can't be longer than the number of receipts.
+    for _ in 0..=transaction.receipts.len() {
+        let receipt_id = match status {
+            ExecutionStatusView::SuccessReceiptId(receipt_id) => receipt_id,
+            _ => return receipt_status(status),
+        };
+        match transaction
+            .receipts
+            .iter()
+            .find(|receipt| &receipt.execution_outcome.id == receipt_id)
+        {
+            Some(receipt) => status = &receipt.execution_outcome.outcome.status,
+            None => break,
+        }
+    }
+    tracing::log::warn!(target: PROJECT_ID, "Broken receipt chain of transaction {}", transaction.transaction.hash);
+    ReceiptStatus::Failure
+}
+
+/// Returns the ID and the error kind of the first failed outcome of the transaction, if any.
+/// The ID is the transaction hash if the transaction itself failed to convert into a receipt.
+/// It's informational only, the status of the transaction comes from [final_status].
+fn find_failure(transaction: &TransactionView) -> Option<(CryptoHash, String)> {
+    std::iter::once(&transaction.execution_outcome)
+        .chain(
+            transaction
+                .receipts
+                .iter()
+                .map(|receipt| &receipt.execution_outcome),
+        )
+        .find_map(|outcome| match &outcome.outcome.status {
+            ExecutionStatusView::Failure(error) => Some((outcome.id, failure_kind(error))),
+            ExecutionStatusView::Unknown => Some((outcome.id, "Unknown".to_string())),
+            _ => None,
+        })
+}
+
+/// Classifies the execution error by the name of its innermost kind, e.g. `FunctionCallError`
+/// for action errors or `InvalidTxError` for invalid transactions.
+fn failure_kind(error: &TxExecutionError) -> String {
+    match error {
+        TxExecutionError::ActionError(action_error) => {
+            match serde_json::to_value(&action_error.kind).ok() {
+                Some(Value::String(kind)) => kind,
+                Some(Value::Object(kind)) => kind.keys().next().cloned().unwrap_or_default(),
+                _ => "ActionError".to_string(),
+            }
+        }
+        TxExecutionError::InvalidTxError(_) => "InvalidTxError".to_string(),
+    }
+}
+
 fn add_accounts_from_receipt(accounts: &mut HashSet<AccountId>, receipt: &views::ReceiptView) {
     accounts.insert(receipt.receiver_id.clone());
     match &receipt.receipt {
         ReceiptEnumView::Action { actions, .. } => {
             for action in actions {
-                match action {
-                    ActionView::FunctionCall { args, .. } => {
-                        if let Ok(args) = serde_json::from_slice::<Value>(&args) {
-                            extract_accounts(accounts, &args, &POTENTIAL_ACCOUNT_ARGS);
-                        }
+                if let ActionView::FunctionCall { args, .. } = action {
+                    if let Ok(args) = serde_json::from_slice::<Value>(args) {
+                        AccountArgs::get().extract_from_args(accounts, &args);
                     }
-                    _ => {}
                 }
             }
         }
@@ -582,33 +2251,124 @@ fn add_accounts_from_receipt(accounts: &mut HashSet<AccountId>, receipt: &views:
     }
 }
 
+/// Whether the transaction cache is only kept in memory, with `CACHE_BACKEND=memory`, e.g. for
+/// short backfills and CI runs. `CACHE_BACKEND=sled` (the default) requires `SLED_DB_PATH`.
+pub fn is_memory_cache() -> bool {
+    match env::var("CACHE_BACKEND").as_deref() {
+        Ok("memory") => true,
+        Ok("sled") | Err(_) => false,
+        Ok(backend) => panic!("Unknown CACHE_BACKEND: {}", backend),
+    }
+}
+
 pub struct TxCache {
-    pub sled_db: sled::Db,
+    /// `None` for the in-memory cache with `CACHE_BACKEND=memory`, which starts empty and is lost
+    /// on exit.
+    pub sled_db: Option<sled::Db>,
 
     pub receipt_to_tx: HashMap<CryptoHash, CryptoHash>,
     pub data_receipts: HashMap<CryptoHash, views::ReceiptView>,
+    /// The block heights when the data receipts were received, to find the orphaned ones. It's not
+    /// persisted, so the data receipts loaded from sled count from the first sweep.
+    pub data_receipt_block_heights: HashMap<CryptoHash, BlockHeight>,
     pub transactions: HashMap<CryptoHash, PendingTransaction>,
+    /// The pending receipt IDs of the evicted transactions by the block height of the eviction, so
+    /// their late receipts are skipped instead of failing the block. They are kept for the
+    /// `STALE_TRANSACTION_MAX_AGE_BLOCKS` after the eviction.
+    pub evicted_receipt_ids: HashMap<CryptoHash, BlockHeight>,
+    /// The `tx_block_height` of the transactions shed to sled, to find the oldest pending
+    /// transaction without reading them.
+    pub shed_tx_block_heights: HashMap<CryptoHash, BlockHeight>,
     pub last_block_height: BlockHeight,
+    /// The JSON values are written to sled compressed with zstd, with `TX_CACHE_COMPRESSION=true`.
+    /// Both plain and compressed values are read, so the flag can be switched on an existing cache.
+    pub compress: bool,
 }
 
 impl TxCache {
     pub fn new(sled: sled::Db) -> Self {
-        let mut this = Self {
-            sled_db: sled,
-            receipt_to_tx: Default::default(),
-            data_receipts: Default::default(),
-            transactions: Default::default(),
-            last_block_height: 0,
-        };
+        let mut this = Self::in_memory();
+        this.sled_db = Some(sled);
         this.last_block_height = this.get_u64(LAST_BLOCK_HEIGHT_KEY).unwrap_or(0);
 
         this.receipt_to_tx = this.get_json(RECEIPT_TO_TX_KEY).unwrap_or_default();
         this.data_receipts = this.get_json(DATA_RECEIPTS_KEY).unwrap_or_default();
         this.transactions = this.get_json(TRANSACTIONS_KEY).unwrap_or_default();
+        this.evicted_receipt_ids = this.get_json(EVICTED_RECEIPT_IDS_KEY).unwrap_or_default();
+        let shed_keys: Vec<String> = this
+            .sled_db
+            .iter()
+            .flat_map(|sled_db| sled_db.scan_prefix(SHED_TRANSACTION_KEY_PREFIX).keys())
+            .map(|key| {
+                String::from_utf8(key.expect("Failed to scan").to_vec()).expect("Invalid key")
+            })
+            .collect();
+        for key in shed_keys {
+            let transaction: PendingTransaction =
+                this.get_json(&key).expect("Missing shed transaction");
+            this.shed_tx_block_heights
+                .insert(transaction.transaction_hash(), transaction.tx_block_height);
+        }
 
         this
     }
 
+    pub fn in_memory() -> Self {
+        Self {
+            sled_db: None,
+            receipt_to_tx: Default::default(),
+            data_receipts: Default::default(),
+            data_receipt_block_heights: Default::default(),
+            transactions: Default::default(),
+            evicted_receipt_ids: Default::default(),
+            shed_tx_block_heights: Default::default(),
+            last_block_height: 0,
+            compress: env::var("TX_CACHE_COMPRESSION")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+        }
+    }
+
+    /// Replaces the empty cache with the entries of an archive written by `export`.
+    pub fn import(sled_db: sled::Db, path: &Path) -> anyhow::Result<Self> {
+        Self::import_archive(sled_db, &std::fs::read(path)?)
+    }
+
+    pub fn import_archive(sled_db: sled::Db, archive: &[u8]) -> anyhow::Result<Self> {
+        if sled_db.iter().next().is_some() {
+            anyhow::bail!("The transaction cache is not empty");
+        }
+        let data = zstd::decode_all(archive)?;
+        let entries = Vec::<(Vec<u8>, Vec<u8>)>::try_from_slice(&data)?;
+        for (key, value) in entries {
+            sled_db.insert(key, value)?;
+        }
+        sled_db.flush()?;
+        Ok(Self::new(sled_db))
+    }
+
+    /// Writes the archive of the cache to the file.
+    pub fn export(&self, path: &Path) -> anyhow::Result<()> {
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, self.export_archive()?)?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Encodes all entries of the cache, including the shed transactions and the last block
+    /// height, to a portable archive: the zstd-compressed borsh encoding of the key-value pairs.
+    pub fn export_archive(&self) -> anyhow::Result<Vec<u8>> {
+        let Some(sled_db) = &self.sled_db else {
+            anyhow::bail!("The in-memory transaction cache can't be exported");
+        };
+        self.flush();
+        let entries = sled_db
+            .iter()
+            .map(|entry| entry.map(|(key, value)| (key.to_vec(), value.to_vec())))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(zstd::encode_all(borsh::to_vec(&entries)?.as_slice(), 0)?)
+    }
+
     pub fn stats(&self) -> String {
         format!(
             "mem: {} tx, {} r, {} dr",
@@ -618,30 +2378,75 @@ impl TxCache {
         )
     }
 
+    /// The block height of the oldest pending transaction, including the shed ones.
+    pub fn oldest_tx_block_height(&self) -> Option<BlockHeight> {
+        self.transactions
+            .values()
+            .map(|transaction| transaction.tx_block_height)
+            .chain(self.shed_tx_block_heights.values().copied())
+            .min()
+    }
+
+    /// The number of the pending transactions shed to sled.
+    pub fn num_shed_transactions(&self) -> usize {
+        self.sled_db
+            .as_ref()
+            .map_or(0, |sled_db| sled_db.scan_prefix(SHED_TRANSACTION_KEY_PREFIX).count())
+    }
+
+    pub fn update_metrics(&self) {
+        let tx_cache_size = &Metrics::get().tx_cache_size;
+        tx_cache_size
+            .with_label_values(&["transactions"])
+            .set(self.transactions.len() as i64);
+        tx_cache_size
+            .with_label_values(&["receipt_to_tx"])
+            .set(self.receipt_to_tx.len() as i64);
+        tx_cache_size
+            .with_label_values(&["data_receipts"])
+            .set(self.data_receipts.len() as i64);
+    }
+
     pub fn flush(&self) {
+        let Some(sled_db) = &self.sled_db else {
+            return;
+        };
         self.set_json(RECEIPT_TO_TX_KEY, &self.receipt_to_tx);
         self.set_json(DATA_RECEIPTS_KEY, &self.data_receipts);
         self.set_json(TRANSACTIONS_KEY, &self.transactions);
+        self.set_json(EVICTED_RECEIPT_IDS_KEY, &self.evicted_receipt_ids);
 
-        self.sled_db.flush().expect("Failed to flush");
+        sled_db.flush().expect("Failed to flush");
     }
 
     fn get_json<T>(&self, key: &str) -> Option<T>
     where
         T: DeserializeOwned,
     {
-        self.sled_db
-            .get(key)
-            .expect("Failed to get")
-            .map(|v| serde_json::from_slice(&v).expect("Failed to deserialize"))
+        let sled_db = self.sled_db.as_ref()?;
+        sled_db.get(key).expect("Failed to get").map(|v| {
+            if v.starts_with(&ZSTD_MAGIC) {
+                let data = zstd::decode_all(v.as_ref()).expect("Failed to decompress");
+                serde_json::from_slice(&data).expect("Failed to deserialize")
+            } else {
+                serde_json::from_slice(&v).expect("Failed to deserialize")
+            }
+        })
     }
 
     fn set_json<T>(&self, key: &str, value: T) -> bool
     where
         T: Serialize,
     {
-        self.sled_db
-            .insert(key, serde_json::to_vec(&value).unwrap())
+        let Some(sled_db) = &self.sled_db else {
+            return false;
+        };
+        let mut data = serde_json::to_vec(&value).unwrap();
+        if self.compress {
+            data = zstd::encode_all(data.as_slice(), 0).expect("Failed to compress");
+        }
+        sled_db
+            .insert(key, data)
             .expect("Failed to set")
             .is_some()
     }
@@ -667,8 +2472,14 @@ impl TxCache {
         self.receipt_to_tx.remove(receipt_id);
     }
 
-    fn insert_data_receipt(&mut self, data_id: &CryptoHash, receipt: views::ReceiptView) {
+    fn insert_data_receipt(
+        &mut self,
+        data_id: &CryptoHash,
+        receipt: views::ReceiptView,
+        block_height: BlockHeight,
+    ) {
         let receipt_id = receipt.receipt_id;
+        self.data_receipt_block_heights.insert(*data_id, block_height);
         let old_receipt = self.data_receipts.insert(*data_id, receipt);
         // In-memory insert.
         if let Some(old_receipt) = old_receipt {
@@ -682,9 +2493,53 @@ impl TxCache {
     }
 
     fn get_and_remove_data_receipt(&mut self, data_id: &CryptoHash) -> Option<views::ReceiptView> {
+        self.data_receipt_block_heights.remove(data_id);
         self.data_receipts.remove(data_id)
     }
 
+    /// Removes the `receipt_to_tx` entries of the transactions that are no longer pending, and the
+    /// data receipts received more than `data_receipt_max_age` blocks ago. Returns the numbers of
+    /// the removed entries.
+    pub fn remove_orphans(
+        &mut self,
+        block_height: BlockHeight,
+        data_receipt_max_age: BlockHeight,
+    ) -> (usize, usize) {
+        let shed_tx_hashes: HashSet<CryptoHash> = match &self.sled_db {
+            Some(sled_db) => sled_db
+                .scan_prefix(SHED_TRANSACTION_KEY_PREFIX)
+                .keys()
+                .map(|key| {
+                    let key = key.expect("Failed to scan");
+                    let tx_hash = std::str::from_utf8(&key[SHED_TRANSACTION_KEY_PREFIX.len()..])
+                        .expect("Invalid key");
+                    tx_hash.parse().expect("Invalid shed transaction hash")
+                })
+                .collect(),
+            None => HashSet::new(),
+        };
+        let transactions = &self.transactions;
+        let num_receipts = self.receipt_to_tx.len();
+        self.receipt_to_tx.retain(|_, tx_hash| {
+            transactions.contains_key(tx_hash) || shed_tx_hashes.contains(tx_hash)
+        });
+        let num_receipts = num_receipts - self.receipt_to_tx.len();
+
+        let num_data_receipts = self.data_receipts.len();
+        for data_id in self.data_receipts.keys() {
+            self.data_receipt_block_heights
+                .entry(*data_id)
+                .or_insert(block_height);
+        }
+        self.data_receipt_block_heights
+            .retain(|_, h| *h + data_receipt_max_age >= block_height);
+        let data_receipt_block_heights = &self.data_receipt_block_heights;
+        self.data_receipts
+            .retain(|data_id, _| data_receipt_block_heights.contains_key(data_id));
+        let num_data_receipts = num_data_receipts - self.data_receipts.len();
+        (num_receipts, num_data_receipts)
+    }
+
     fn insert_transaction(
         &mut self,
         pending_transaction: PendingTransaction,
@@ -699,18 +2554,108 @@ impl TxCache {
     }
 
     fn get_and_remove_transaction(&mut self, tx_hash: &CryptoHash) -> Option<PendingTransaction> {
-        self.transactions.remove(tx_hash)
+        self.transactions.remove(tx_hash).or_else(|| {
+            let key = format!("{}{}", SHED_TRANSACTION_KEY_PREFIX, tx_hash);
+            let transaction = self.get_json(&key)?;
+            self.shed_tx_block_heights.remove(tx_hash);
+            self.sled_db
+                .as_ref()?
+                .remove(key.as_str())
+                .expect("Failed to remove");
+            Some(transaction)
+        })
+    }
+
+    /// Removes the pending transactions included before the block, including the shed ones, with
+    /// their pending receipts. The pending receipt IDs are kept as the evicted ones at the block
+    /// height, and the ones evicted before the block are dropped.
+    pub fn evict_stale_transactions(
+        &mut self,
+        before_block_height: BlockHeight,
+        block_height: BlockHeight,
+    ) -> Vec<PendingTransaction> {
+        self.evicted_receipt_ids
+            .retain(|_, evicted_block_height| *evicted_block_height >= before_block_height);
+        let mut tx_hashes: Vec<CryptoHash> = self
+            .transactions
+            .iter()
+            .filter(|(_, transaction)| transaction.tx_block_height < before_block_height)
+            .map(|(tx_hash, _)| *tx_hash)
+            .collect();
+        if let Some(sled_db) = &self.sled_db {
+            for entry in sled_db.scan_prefix(SHED_TRANSACTION_KEY_PREFIX) {
+                let (key, _) = entry.expect("Failed to scan");
+                let key = String::from_utf8(key.to_vec()).expect("Invalid key");
+                let transaction: PendingTransaction =
+                    self.get_json(&key).expect("Missing shed transaction");
+                if transaction.tx_block_height < before_block_height {
+                    tx_hashes.push(transaction.transaction_hash());
+                }
+            }
+        }
+        let mut stale_transactions = vec![];
+        for tx_hash in tx_hashes {
+            let Some(transaction) = self.get_and_remove_transaction(&tx_hash) else {
+                continue;
+            };
+            for receipt_id in &transaction.pending_receipt_ids {
+                self.remove_receipt_to_tx(receipt_id);
+                self.evicted_receipt_ids.insert(*receipt_id, block_height);
+            }
+            stale_transactions.push(transaction);
+        }
+        stale_transactions
+    }
+
+    /// Moves the largest pending transactions without new receipts for `STALE_PENDING_BLOCKS`
+    /// from memory to sled. They are loaded back when their next receipt arrives.
+    pub fn shed_stale_transactions(
+        &mut self,
+        block_height: BlockHeight,
+        max_count: usize,
+    ) -> usize {
+        if self.sled_db.is_none() {
+            return 0;
+        }
+        let mut stale_transactions: Vec<_> = self
+            .transactions
+            .iter()
+            .filter(|(_, transaction)| {
+                transaction.blocks.last().unwrap().block_height + STALE_PENDING_BLOCKS
+                    < block_height
+            })
+            .map(|(tx_hash, transaction)| {
+                let size = transaction.transaction.receipts.len()
+                    + transaction.transaction.data_receipts.len();
+                (size, *tx_hash)
+            })
+            .collect();
+        stale_transactions.sort_by(|a, b| b.0.cmp(&a.0));
+        stale_transactions.truncate(max_count);
+        for (_, tx_hash) in &stale_transactions {
+            let transaction = self.transactions.remove(tx_hash).unwrap();
+            self.shed_tx_block_heights.insert(*tx_hash, transaction.tx_block_height);
+            self.set_json(
+                &format!("{}{}", SHED_TRANSACTION_KEY_PREFIX, tx_hash),
+                &transaction,
+            );
+        }
+        stale_transactions.len()
     }
 
     pub fn get_u64(&self, key: &str) -> Option<u64> {
         self.sled_db
+            .as_ref()?
             .get(key)
             .expect("Failed to get")
             .map(|v| u64::try_from_slice(&v).expect("Failed to deserialize"))
     }
 
     pub fn set_u64(&self, key: &str, value: u64) -> bool {
-        self.sled_db
+        let Some(sled_db) = &self.sled_db else {
+            return false;
+        };
+        sled_db
             .insert(key, borsh::to_vec(&value).unwrap())
             .expect("Failed to set")
             .is_some()
@@ -0,0 +1,59 @@
+use crate::click::positive_env_var;
+use crate::transactions::TransactionView;
+use std::env;
+
+use fastnear_primitives::near_primitives::views::{ActionView, ReceiptEnumView};
+
+/// Parts of the transaction view that are dropped from the stored `transaction` column (or blob)
+/// to save storage. The rows of the other tables are still extracted from the full view.
+pub struct TrimPolicy {
+    /// Function call args over the size are replaced with empty args.
+    pub max_args_bytes: Option<u64>,
+    pub drop_logs: bool,
+    pub drop_data_receipts: bool,
+}
+
+impl TrimPolicy {
+    /// Returns `None` if none of `TRIM_ARGS_OVER_BYTES`, `TRIM_LOGS` and `TRIM_DATA_RECEIPTS` is
+    /// set.
+    pub fn from_env() -> Option<Self> {
+        let flag = |name: &str| env::var(name).map(|v| v == "true").unwrap_or(false);
+        let policy = Self {
+            max_args_bytes: positive_env_var("TRIM_ARGS_OVER_BYTES"),
+            drop_logs: flag("TRIM_LOGS"),
+            drop_data_receipts: flag("TRIM_DATA_RECEIPTS"),
+        };
+        (policy.max_args_bytes.is_some() || policy.drop_logs || policy.drop_data_receipts)
+            .then_some(policy)
+    }
+
+    pub fn apply(&self, transaction: &mut TransactionView) {
+        if let Some(max_args_bytes) = self.max_args_bytes {
+            self.trim_args(&mut transaction.transaction.actions, max_args_bytes);
+            for receipt in &mut transaction.receipts {
+                if let ReceiptEnumView::Action { actions, .. } = &mut receipt.receipt.receipt {
+                    self.trim_args(actions, max_args_bytes);
+                }
+            }
+        }
+        if self.drop_logs {
+            transaction.execution_outcome.outcome.logs.clear();
+            for receipt in &mut transaction.receipts {
+                receipt.execution_outcome.outcome.logs.clear();
+            }
+        }
+        if self.drop_data_receipts {
+            transaction.data_receipts.clear();
+        }
+    }
+
+    fn trim_args(&self, actions: &mut [ActionView], max_args_bytes: u64) {
+        for action in actions {
+            if let ActionView::FunctionCall { args, .. } = action {
+                if args.len() as u64 > max_args_bytes {
+                    *args = Vec::new().into();
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use crate::blob_storage::BlobStorage;
+use crate::transactions::TxCache;
+use std::env;
+use std::path::Path;
+
+use fastnear_primitives::near_primitives::types::BlockHeight;
+
+pub const TX_CACHE_SNAPSHOTS_TARGET: &str = "tx_cache_snapshots";
+
+/// The key of the blob with the block height of the latest snapshot, since the blob storage
+/// can't list the snapshots.
+const LATEST_SNAPSHOT_KEY: &str = "tx_cache/latest";
+
+/// Uploads an archive of the transaction cache to the blob storage every
+/// `TX_CACHE_SNAPSHOT_INTERVAL` blocks, so a host with an empty `SLED_DB_PATH` can restore the
+/// cache at startup instead of needing a persistent disk. A snapshot is only taken after all rows
+/// up to its block are committed, so the restored cache never misses a completed transaction.
+#[derive(Clone)]
+pub struct TxCacheSnapshots {
+    pub blob_storage: BlobStorage,
+    pub interval: BlockHeight,
+}
+
+impl TxCacheSnapshots {
+    /// Requires both `TX_CACHE_SNAPSHOT_INTERVAL` and `BLOB_STORAGE_URL`.
+    pub fn from_env() -> Option<Self> {
+        let interval = env::var("TX_CACHE_SNAPSHOT_INTERVAL")
+            .ok()?
+            .parse()
+            .expect("Invalid TX_CACHE_SNAPSHOT_INTERVAL");
+        assert!(interval > 0, "TX_CACHE_SNAPSHOT_INTERVAL must be positive");
+        let blob_storage =
+            BlobStorage::from_env().expect("TX_CACHE_SNAPSHOT_INTERVAL requires BLOB_STORAGE_URL");
+        Some(Self {
+            blob_storage,
+            interval,
+        })
+    }
+
+    pub fn is_snapshot_block(&self, block_height: BlockHeight) -> bool {
+        block_height % self.interval == 0
+    }
+
+    fn snapshot_key(block_height: BlockHeight) -> String {
+        format!("tx_cache/{:020}.zst", block_height)
+    }
+
+    /// Uploads the archive, and then points the latest snapshot to it.
+    pub async fn upload(&self, block_height: BlockHeight, archive: Vec<u8>) -> anyhow::Result<()> {
+        let size = archive.len();
+        self.blob_storage
+            .put_with_retry(&Self::snapshot_key(block_height), archive)
+            .await?;
+        self.blob_storage
+            .put_with_retry(LATEST_SNAPSHOT_KEY, block_height.to_string().into_bytes())
+            .await?;
+        tracing::log::info!(target: TX_CACHE_SNAPSHOTS_TARGET, "Uploaded the cache snapshot at block #{} ({} bytes)", block_height, size);
+        Ok(())
+    }
+
+    /// Restores the latest snapshot into the sled database at the path, if it's empty. Returns the
+    /// block height of the restored snapshot.
+    pub async fn restore_if_empty(
+        &self,
+        sled_db_path: &str,
+    ) -> anyhow::Result<Option<BlockHeight>> {
+        let path = Path::new(sled_db_path);
+        if path.exists() && path.read_dir()?.next().is_some() {
+            return Ok(None);
+        }
+        let latest = self.blob_storage.get(LATEST_SNAPSHOT_KEY).await;
+        let block_height: BlockHeight = match latest {
+            Ok(data) => String::from_utf8(data)?.trim().parse()?,
+            Err(err) => {
+                tracing::log::warn!(target: TX_CACHE_SNAPSHOTS_TARGET, "No cache snapshot to restore: {}", err);
+                return Ok(None);
+            }
+        };
+        let archive = self
+            .blob_storage
+            .get(&Self::snapshot_key(block_height))
+            .await?;
+        std::fs::create_dir_all(path)?;
+        let tx_cache = TxCache::import_archive(sled::open(path)?, &archive)?;
+        tracing::log::info!(target: TX_CACHE_SNAPSHOTS_TARGET, "Restored the cache snapshot at block #{}: {}", block_height, tx_cache.stats());
+        Ok(Some(block_height))
+    }
+}
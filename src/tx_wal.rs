@@ -0,0 +1,108 @@
+use crate::transactions::TxRows;
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
+use fastnear_primitives::near_primitives::types::BlockHeight;
+use serde::{Deserialize, Serialize};
+
+pub const TX_WAL_TARGET: &str = "tx_wal";
+
+/// Rows produced by a single block.
+#[derive(Serialize, Deserialize)]
+struct TxWalEntry<R> {
+    block_height: BlockHeight,
+    rows: R,
+}
+
+/// A write-ahead log of the uncommitted `TxRows` in `TX_WAL_DIR`. The rows of every block are
+/// appended as a JSON line to the current segment. A commit seals the segments and removes them
+/// once all rows are inserted, so after a crash the remaining segments hold every uncommitted row.
+pub struct TxWal {
+    pub dir: PathBuf,
+    file: Option<File>,
+    /// Segments with rows that are not committed yet, in the block order.
+    segments: Vec<PathBuf>,
+    pub last_block_height: Option<BlockHeight>,
+}
+
+impl TxWal {
+    pub fn from_env() -> Option<Self> {
+        let dir = PathBuf::from(env::var("TX_WAL_DIR").ok()?);
+        std::fs::create_dir_all(&dir)
+            .unwrap_or_else(|_| panic!("Failed to create {}", dir.display()));
+        Some(Self {
+            dir,
+            file: None,
+            segments: vec![],
+            last_block_height: None,
+        })
+    }
+
+    /// Reads the rows of all segments left by the previous run. The segments are kept until the
+    /// next commit.
+    pub fn recover(&mut self) -> anyhow::Result<TxRows> {
+        let mut paths = std::fs::read_dir(&self.dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        paths.retain(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "jsonl")
+        });
+        paths.sort();
+        let mut rows = TxRows::default();
+        let mut num_blocks = 0;
+        for path in paths {
+            let data = std::fs::read_to_string(&path)?;
+            for line in data.lines() {
+                match serde_json::from_str::<TxWalEntry<TxRows>>(line) {
+                    Ok(entry) => {
+                        self.last_block_height = Some(entry.block_height);
+                        rows.append(entry.rows);
+                        num_blocks += 1;
+                    }
+                    Err(err) => {
+                        // Only the last line of a segment can be torn by a crash.
+                        tracing::log::warn!(target: TX_WAL_TARGET, "Skipping invalid entry in {}: {}", path.display(), err);
+                    }
+                }
+            }
+            self.segments.push(path);
+        }
+        if num_blocks > 0 {
+            tracing::log::info!(target: TX_WAL_TARGET, "Recovered {} transactions from {} blocks up to #{}", rows.transactions.len() + rows.failed_transactions.len(), num_blocks, self.last_block_height.unwrap());
+        }
+        Ok(rows)
+    }
+
+    /// Appends the rows of the block and syncs them to disk.
+    pub fn append(&mut self, block_height: BlockHeight, rows: &TxRows) -> anyhow::Result<()> {
+        if self.file.is_none() {
+            let path = self.dir.join(format!("{:020}.jsonl", block_height));
+            self.file = Some(File::options().create(true).append(true).open(&path)?);
+            self.segments.push(path);
+        }
+        let file = self.file.as_mut().unwrap();
+        let mut data = serde_json::to_vec(&TxWalEntry { block_height, rows })?;
+        data.push(b'\n');
+        file.write_all(&data)?;
+        file.sync_data()?;
+        self.last_block_height = Some(block_height);
+        Ok(())
+    }
+
+    /// Seals the segments of the rows being committed. The following blocks go to a new segment.
+    pub fn seal(&mut self) -> Vec<PathBuf> {
+        self.file = None;
+        std::mem::take(&mut self.segments)
+    }
+
+    /// Removes the sealed segments after their rows are committed.
+    pub fn remove_segments(segments: &[PathBuf]) -> anyhow::Result<()> {
+        for path in segments {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
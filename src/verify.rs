@@ -0,0 +1,214 @@
+use crate::click::*;
+use crate::repair::range_conditions;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, OnceLock};
+
+use clickhouse::{Client, Row};
+use fastnear_primitives::near_primitives::types::BlockHeight;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+pub const VERIFY_TARGET: &str = "verify";
+
+/// The number of the differing rows that are included in the report per table.
+const MAX_SAMPLES: usize = 10;
+
+static VERIFIER: OnceLock<Verifier> = OnceLock::new();
+
+/// Compares the rows of a reindexed block range with the rows that are already in the database,
+/// instead of inserting them. The rows are compared by their JSON, so any changed column shows up
+/// as a missing row and an extra row.
+pub struct Verifier {
+    /// The condition of the rows within the range by the table.
+    conditions: HashMap<String, String>,
+    tables: tokio::sync::Mutex<HashMap<String, TableDiff>>,
+    /// Tables that got rows, but have no range condition to compare them with.
+    skipped_tables: Mutex<HashSet<String>>,
+}
+
+/// The rows by their JSON: 1 for the produced rows that are missing from the database, -1 for the
+/// rows of the database that were not produced, and 0 for the matching rows.
+#[derive(Default)]
+struct TableDiff {
+    rows: HashMap<String, i8>,
+    produced_rows: u64,
+    existing_rows: u64,
+}
+
+#[derive(Serialize)]
+pub struct TableReport {
+    pub table: String,
+    pub produced_rows: u64,
+    pub existing_rows: u64,
+    pub missing_rows: u64,
+    pub extra_rows: u64,
+    /// The JSON of some of the produced rows that are not in the database.
+    pub missing_samples: Vec<String>,
+    /// The JSON of some of the rows of the database that were not produced.
+    pub extra_samples: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct VerifyReport {
+    pub tables: Vec<TableReport>,
+    pub skipped_tables: Vec<String>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.tables
+            .iter()
+            .all(|table| table.missing_rows == 0 && table.extra_rows == 0)
+    }
+}
+
+impl Verifier {
+    /// Returns the verifier if the `verify` command is running.
+    pub fn get() -> Option<&'static Self> {
+        VERIFIER.get()
+    }
+
+    /// Starts verifying the rows of the pipeline within the block range. Once started, the
+    /// inserts of the process are compared instead of written.
+    pub async fn start(
+        db: &ClickDB,
+        pipeline: &str,
+        from_block_height: BlockHeight,
+        to_block_height: BlockHeight,
+    ) -> clickhouse::error::Result<&'static Self> {
+        let tables = db.tables().await?;
+        let has_failed_transactions = tables.iter().any(|table| table == "failed_transactions");
+        let conditions = range_conditions(pipeline, has_failed_transactions)
+            .into_iter()
+            .filter(|(table, _)| tables.iter().any(|name| name == table))
+            .map(|(table, condition)| {
+                let condition = format!("{} AND {}", condition, chain_filter())
+                    .replace("{from}", &from_block_height.to_string())
+                    .replace("{to}", &to_block_height.to_string());
+                (table.to_string(), condition)
+            })
+            .collect();
+        let verifier = Self {
+            conditions,
+            tables: tokio::sync::Mutex::new(HashMap::new()),
+            skipped_tables: Mutex::new(HashSet::new()),
+        };
+        if VERIFIER.set(verifier).is_err() {
+            panic!("The verifier is already started");
+        }
+        Ok(VERIFIER.get().unwrap())
+    }
+
+    /// Compares the rows with the rows of the table within the range. The rows of the table are
+    /// loaded on the first batch.
+    pub async fn compare<T>(
+        &self,
+        client: &Client,
+        rows: &[T],
+        table: &str,
+    ) -> clickhouse::error::Result<()>
+    where
+        T: Row + Serialize + DeserializeOwned,
+    {
+        let Some(condition) = self.conditions.get(table) else {
+            self.skipped_tables
+                .lock()
+                .unwrap()
+                .insert(table.to_string());
+            return Ok(());
+        };
+        let mut tables = self.tables.lock().await;
+        if !tables.contains_key(table) {
+            let existing = client
+                .query(&format!(
+                    "SELECT ?fields FROM {} WHERE {} SETTINGS final = 1",
+                    table, condition
+                ))
+                .fetch_all::<T>()
+                .await?;
+            let mut diff = TableDiff::default();
+            for row in &existing {
+                diff.rows.insert(serde_json::to_string(row).unwrap(), -1);
+            }
+            diff.existing_rows = diff.rows.len() as u64;
+            tracing::log::info!(target: VERIFY_TARGET, "Loaded {} rows of {}", diff.existing_rows, table);
+            tables.insert(table.to_string(), diff);
+        }
+        let diff = tables.get_mut(table).unwrap();
+        for row in rows {
+            diff.produced_rows += 1;
+            // Duplicates of the produced rows are left as is.
+            match diff.rows.entry(serde_json::to_string(row).unwrap()) {
+                Entry::Occupied(mut entry) => {
+                    if *entry.get() == -1 {
+                        entry.insert(0);
+                    }
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(1);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The differences of all tables. The tables of the range that got no rows are counted as a
+    /// whole.
+    pub async fn report(&self, db: &ClickDB) -> clickhouse::error::Result<VerifyReport> {
+        let tables = self.tables.lock().await;
+        let mut reports = vec![];
+        for (table, condition) in &self.conditions {
+            let report = match tables.get(table) {
+                Some(diff) => {
+                    let samples = |state: i8| -> Vec<String> {
+                        diff.rows
+                            .iter()
+                            .filter(|(_, s)| **s == state)
+                            .take(MAX_SAMPLES)
+                            .map(|(row, _)| row.clone())
+                            .collect()
+                    };
+                    let count = |state: i8| diff.rows.values().filter(|s| **s == state).count();
+                    TableReport {
+                        table: table.clone(),
+                        produced_rows: diff.produced_rows,
+                        existing_rows: diff.existing_rows,
+                        missing_rows: count(1) as u64,
+                        extra_rows: count(-1) as u64,
+                        missing_samples: samples(1),
+                        extra_samples: samples(-1),
+                    }
+                }
+                None => {
+                    let existing_rows = db
+                        .client
+                        .query(&format!(
+                            "SELECT count() FROM {} WHERE {} SETTINGS final = 1",
+                            table, condition
+                        ))
+                        .fetch_one::<u64>()
+                        .await?;
+                    TableReport {
+                        table: table.clone(),
+                        produced_rows: 0,
+                        existing_rows,
+                        missing_rows: 0,
+                        extra_rows: existing_rows,
+                        missing_samples: vec![],
+                        extra_samples: vec![],
+                    }
+                }
+            };
+            reports.push(report);
+        }
+        reports.sort_by(|a, b| a.table.cmp(&b.table));
+        let mut skipped_tables: Vec<String> =
+            self.skipped_tables.lock().unwrap().iter().cloned().collect();
+        skipped_tables.sort();
+        Ok(VerifyReport {
+            tables: reports,
+            skipped_tables,
+        })
+    }
+}
@@ -0,0 +1,176 @@
+use crate::click::*;
+use crate::implicit::parse_account_or_key;
+use std::collections::HashMap;
+
+use clickhouse::Row;
+use fastnear_primitives::near_primitives::types::AccountId;
+use serde::{Deserialize, Serialize};
+
+#[derive(Row, Serialize, Deserialize)]
+pub struct WatchListRow {
+    pub account_id: String,
+    pub muted_until: Option<u64>,
+    pub tags: Vec<String>,
+}
+
+/// A rule from the `watch_receipt_rules` table that tags the receipts by their predecessor, e.g.
+/// everything that originates from `aurora`, so the flows into the receivers are tracked even if
+/// neither the signer nor the receiver is watched.
+#[derive(Row, Deserialize)]
+pub struct WatchReceiptRuleRow {
+    pub rule_id: String,
+    /// The account pattern of the predecessor.
+    pub predecessor_pattern: String,
+    /// The account pattern of the receiver, `*` for any receiver.
+    pub receiver_pattern: String,
+    pub tags: Vec<String>,
+}
+
+impl WatchReceiptRuleRow {
+    pub fn matches(&self, predecessor_id: &AccountId, receiver_id: &AccountId) -> bool {
+        matches_account_pattern(&self.predecessor_pattern, predecessor_id)
+            && matches_account_pattern(&self.receiver_pattern, receiver_id)
+    }
+}
+
+/// Matches either an exact account ID, `*` for any account, or `*.suffix` for the account and its
+/// sub-accounts.
+pub fn matches_account_pattern(pattern: &str, account_id: &AccountId) -> bool {
+    match pattern.strip_prefix('*') {
+        Some("") => true,
+        Some(suffix) => {
+            account_id.as_str().ends_with(suffix)
+                || Some(account_id.as_str()) == suffix.strip_prefix('.')
+        }
+        None => account_id.as_str() == pattern,
+    }
+}
+
+pub struct WatchListEntry {
+    /// The timestamp in nanoseconds until which the notifications are muted.
+    pub muted_until: Option<u64>,
+    /// Tags or group IDs of the account, e.g. a customer name, `exchange` or `treasury`.
+    pub tags: Vec<String>,
+}
+
+/// Accounts from the `watch_list` table that get extra tracking.
+#[derive(Default)]
+pub struct WatchList {
+    pub accounts: HashMap<AccountId, WatchListEntry>,
+    /// Entries keyed by a public key (e.g. `ed25519:...`) that match the transactions signed with
+    /// the key by any account. An `ed25519` key also watches its implicit account.
+    pub public_keys: HashMap<String, WatchListEntry>,
+    /// Loaded from the optional `watch_receipt_rules` table.
+    pub receipt_rules: Vec<WatchReceiptRuleRow>,
+}
+
+impl WatchList {
+    pub async fn load(db: &ClickDB) -> clickhouse::error::Result<Self> {
+        let rows = db
+            .client
+            .query(&format!(
+                "SELECT ?fields FROM watch_list FINAL WHERE {}",
+                chain_filter()
+            ))
+            .fetch_all::<WatchListRow>()
+            .await?;
+        let mut accounts = HashMap::new();
+        let mut public_keys = HashMap::new();
+        for row in rows {
+            // Account IDs can't have a colon, unlike the `ed25519:` and `secp256k1:` keys.
+            if row.account_id.contains(':') {
+                public_keys.insert(
+                    row.account_id.clone(),
+                    WatchListEntry {
+                        muted_until: row.muted_until,
+                        tags: row.tags.clone(),
+                    },
+                );
+            }
+            match parse_account_or_key(&row.account_id) {
+                Ok(account_id) => {
+                    accounts.insert(
+                        account_id,
+                        WatchListEntry {
+                            muted_until: row.muted_until,
+                            tags: row.tags,
+                        },
+                    );
+                }
+                Err(_) if public_keys.contains_key(&row.account_id) => {}
+                Err(err) => {
+                    tracing::log::warn!(target: CLICKHOUSE_TARGET, "Ignoring invalid watch list account {}: {}", row.account_id, err);
+                }
+            }
+        }
+        let receipt_rules = if db
+            .tables()
+            .await?
+            .iter()
+            .any(|table| table == "watch_receipt_rules")
+        {
+            db.client
+                .query(&format!(
+                    "SELECT ?fields FROM watch_receipt_rules FINAL WHERE {}",
+                    chain_filter()
+                ))
+                .fetch_all::<WatchReceiptRuleRow>()
+                .await?
+        } else {
+            vec![]
+        };
+        Ok(Self {
+            accounts,
+            public_keys,
+            receipt_rules,
+        })
+    }
+
+    pub fn insert(&mut self, account_id: AccountId, tags: Vec<String>) {
+        self.accounts.insert(
+            account_id,
+            WatchListEntry {
+                muted_until: None,
+                tags,
+            },
+        );
+    }
+
+    pub fn contains(&self, account_id: &AccountId) -> bool {
+        self.accounts.contains_key(account_id)
+    }
+
+    /// Muted accounts are still tracked, but don't trigger notifications.
+    pub fn is_muted(&self, account_id: &AccountId, timestamp: u64) -> bool {
+        self.accounts
+            .get(account_id)
+            .and_then(|entry| entry.muted_until)
+            .is_some_and(|muted_until| timestamp < muted_until)
+    }
+
+    pub fn tags(&self, account_id: &AccountId) -> &[String] {
+        self.accounts
+            .get(account_id)
+            .map(|entry| entry.tags.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Returns the tags of the receipt rules that match the receipt.
+    pub fn receipt_tags<'a>(
+        &'a self,
+        predecessor_id: &'a AccountId,
+        receiver_id: &'a AccountId,
+    ) -> impl Iterator<Item = &'a String> {
+        self.receipt_rules
+            .iter()
+            .filter(move |rule| rule.matches(predecessor_id, receiver_id))
+            .flat_map(|rule| rule.tags.iter())
+    }
+
+    /// Returns the tags of the watched public key, or `None` if the key is not watched.
+    pub fn key_tags(&self, public_key: &str) -> Option<&[String]> {
+        self.public_keys
+            .get(public_key)
+            .map(|entry| entry.tags.as_slice())
+    }
+}
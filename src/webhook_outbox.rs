@@ -0,0 +1,157 @@
+use crate::alerts::{Alert, AlertRuleRow, Notifier, ALERTS_TARGET};
+use crate::click::*;
+use std::collections::HashMap;
+use std::env;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use clickhouse::Row;
+use serde::{Deserialize, Serialize};
+use serde_repr::{Deserialize_repr, Serialize_repr};
+
+const DRAIN_INTERVAL: Duration = Duration::from_secs(30);
+const DRAIN_BATCH_SIZE: u64 = 100;
+const DEFAULT_MAX_ATTEMPTS: u64 = 20;
+const MIN_RETRY_DELAY_SECS: u64 = 60;
+const MAX_RETRY_DELAY_SECS: u64 = 3600;
+
+#[derive(Copy, Clone, Debug, Serialize_repr, Deserialize_repr, PartialEq)]
+#[repr(u8)]
+pub enum DeliveryStatus {
+    Pending = 1,
+    Delivered = 2,
+    /// Gave up after `WEBHOOK_OUTBOX_MAX_ATTEMPTS` attempts, or the rule was removed.
+    Failed = 3,
+}
+
+/// An alert that failed to be delivered. Every attempt writes a new version of the row.
+#[derive(Row, Serialize, Deserialize)]
+pub struct WebhookOutboxRow {
+    pub rule_id: String,
+    pub transaction_hash: String,
+    /// The JSON of the alert.
+    pub alert: String,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    pub next_retry_at: u64,
+    pub updated_at: u64,
+}
+
+/// Persists the alerts that failed to be delivered in the `webhook_outbox` table and retries them
+/// in the background with an exponential backoff, so the deliveries survive restarts and longer
+/// outages of the receivers.
+#[derive(Clone)]
+pub struct WebhookOutbox {
+    pub db: ClickDB,
+    pub max_attempts: u64,
+}
+
+impl WebhookOutbox {
+    /// Requires `WEBHOOK_OUTBOX=true`.
+    pub fn from_env(db: &ClickDB) -> Option<Self> {
+        if env::var("WEBHOOK_OUTBOX") != Ok("true".to_string()) {
+            return None;
+        }
+        Some(Self {
+            db: db.clone(),
+            max_attempts: positive_env_var("WEBHOOK_OUTBOX_MAX_ATTEMPTS")
+                .unwrap_or(DEFAULT_MAX_ATTEMPTS),
+        })
+    }
+
+    /// Adds the alert after the in-process retries failed, which count as the first attempt.
+    pub async fn enqueue(&self, alert: &Alert) -> anyhow::Result<()> {
+        let now = now_nanos();
+        let row = WebhookOutboxRow {
+            rule_id: alert.rule_id.clone(),
+            transaction_hash: alert.transaction_hash.clone(),
+            alert: serde_json::to_string(alert)?,
+            status: DeliveryStatus::Pending,
+            attempts: 1,
+            next_retry_at: now + retry_delay(1),
+            updated_at: now,
+        };
+        insert_rows_with_retry(&self.db.client, &vec![row], "webhook_outbox").await?;
+        tracing::log::info!(target: ALERTS_TARGET, "Queued alert {} for {} for a retry", alert.rule_id, alert.transaction_hash);
+        Ok(())
+    }
+
+    /// Starts the worker that retries the due deliveries with the rules loaded on start.
+    pub fn start(self, notifier: Notifier, rules: Vec<AlertRuleRow>) {
+        let rules: HashMap<String, AlertRuleRow> = rules
+            .into_iter()
+            .map(|rule| (rule.rule_id.clone(), rule))
+            .collect();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(DRAIN_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(err) = self.drain(&notifier, &rules).await {
+                    tracing::log::error!(target: ALERTS_TARGET, "Failed to drain the webhook outbox: {}", err);
+                }
+            }
+        });
+    }
+
+    async fn drain(
+        &self,
+        notifier: &Notifier,
+        rules: &HashMap<String, AlertRuleRow>,
+    ) -> anyhow::Result<()> {
+        let rows = self
+            .db
+            .client
+            .query(&format!(
+                "SELECT ?fields FROM webhook_outbox FINAL
+                WHERE status = 'PENDING' AND next_retry_at <= now64(9) AND {}
+                ORDER BY next_retry_at LIMIT ?",
+                chain_filter()
+            ))
+            .bind(DRAIN_BATCH_SIZE)
+            .fetch_all::<WebhookOutboxRow>()
+            .await?;
+        let mut updates = vec![];
+        for mut row in rows {
+            let res = match rules.get(&row.rule_id) {
+                Some(rule) => match serde_json::from_str::<Alert>(&row.alert) {
+                    Ok(alert) => notifier.send(rule, &alert).await,
+                    Err(err) => Err(err.into()),
+                },
+                None => Err(anyhow::anyhow!("The rule was removed")),
+            };
+            row.attempts += 1;
+            row.updated_at = now_nanos();
+            match res {
+                Ok(_) => {
+                    row.status = DeliveryStatus::Delivered;
+                    tracing::log::info!(target: ALERTS_TARGET, "Delivered alert {} for {} on attempt #{}", row.rule_id, row.transaction_hash, row.attempts);
+                }
+                Err(err) if u64::from(row.attempts) >= self.max_attempts => {
+                    row.status = DeliveryStatus::Failed;
+                    tracing::log::error!(target: ALERTS_TARGET, "Giving up on alert {} for {} after {} attempts: {}", row.rule_id, row.transaction_hash, row.attempts, err);
+                }
+                Err(err) => {
+                    row.next_retry_at = row.updated_at + retry_delay(row.attempts);
+                    tracing::log::warn!(target: ALERTS_TARGET, "Attempt #{}: Error sending alert {} for {}: {}", row.attempts, row.rule_id, row.transaction_hash, err);
+                }
+            }
+            updates.push(row);
+        }
+        if !updates.is_empty() {
+            insert_rows_with_retry(&self.db.client, &updates, "webhook_outbox").await?;
+        }
+        Ok(())
+    }
+}
+
+/// The delay in nanoseconds before the next attempt, doubling from a minute up to an hour.
+fn retry_delay(attempts: u32) -> u64 {
+    let delay_secs = MIN_RETRY_DELAY_SECS << attempts.saturating_sub(1).min(6);
+    delay_secs.min(MAX_RETRY_DELAY_SECS) * 1_000_000_000
+}
+
+fn now_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64
+}